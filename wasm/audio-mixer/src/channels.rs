@@ -0,0 +1,308 @@
+//! Channel layouts and the default routing between them
+
+use crate::error::MixerError;
+
+/// Highest channel count supported for tracks and mixer output (5.1)
+pub(crate) const MAX_CHANNELS: u32 = 6;
+
+/// -3 dB, used when folding one speaker into two
+const FOLD_GAIN: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Speaker {
+    Mono,
+    Left,
+    Right,
+    Center,
+    Lfe,
+    SurroundLeft,
+    SurroundRight,
+}
+
+/// Speaker arrangement of interleaved audio, which decides how tracks are
+/// routed to the output and which channel is which for panning, balance
+/// and loudness
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum ChannelLayout {
+    Mono,
+    Stereo,
+    /// Front pair and LFE
+    Surround2_1,
+    /// Front pair and center
+    Surround3_0,
+    /// Front and surround pairs
+    Quad,
+    Surround5_0,
+    Surround5_1,
+}
+
+impl ChannelLayout {
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "mono" | "1.0" => Some(Self::Mono),
+            "stereo" | "2.0" => Some(Self::Stereo),
+            "2.1" => Some(Self::Surround2_1),
+            "3.0" => Some(Self::Surround3_0),
+            "quad" | "4.0" => Some(Self::Quad),
+            "5.0" => Some(Self::Surround5_0),
+            "5.1" => Some(Self::Surround5_1),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Mono => "mono",
+            Self::Stereo => "stereo",
+            Self::Surround2_1 => "2.1",
+            Self::Surround3_0 => "3.0",
+            Self::Quad => "quad",
+            Self::Surround5_0 => "5.0",
+            Self::Surround5_1 => "5.1",
+        }
+    }
+
+    /// Default layout for a channel count from 1 to MAX_CHANNELS. Three
+    /// channels are taken as 3.0; 2.1 must be asked for by name.
+    pub(crate) fn from_channels(channels: u32) -> Result<Self, MixerError> {
+        match channels {
+            1 => Ok(Self::Mono),
+            2 => Ok(Self::Stereo),
+            3 => Ok(Self::Surround3_0),
+            4 => Ok(Self::Quad),
+            5 => Ok(Self::Surround5_0),
+            6 => Ok(Self::Surround5_1),
+            _ => Err(MixerError::InvalidChannelCount(channels)),
+        }
+    }
+
+    pub(crate) fn channels(self) -> usize {
+        self.speakers().len()
+    }
+
+    /// Speaker order of the interleaved channels, as used by WAV and
+    /// WebCodecs
+    fn speakers(self) -> &'static [Speaker] {
+        use Speaker::*;
+        match self {
+            Self::Mono => &[Mono],
+            Self::Stereo => &[Left, Right],
+            Self::Surround2_1 => &[Left, Right, Lfe],
+            Self::Surround3_0 => &[Left, Right, Center],
+            Self::Quad => &[Left, Right, SurroundLeft, SurroundRight],
+            Self::Surround5_0 => &[Left, Right, Center, SurroundLeft, SurroundRight],
+            Self::Surround5_1 => &[Left, Right, Center, Lfe, SurroundLeft, SurroundRight],
+        }
+    }
+}
+
+/// Per-channel weights for BS.1770 loudness: surrounds count +1.5 dB and
+/// the LFE channel is excluded
+pub(crate) fn loudness_weights(layout: ChannelLayout) -> Vec<f64> {
+    layout
+        .speakers()
+        .iter()
+        .map(|speaker| match speaker {
+            Speaker::SurroundLeft | Speaker::SurroundRight => 1.41,
+            Speaker::Lfe => 0.0,
+            _ => 1.0,
+        })
+        .collect()
+}
+
+/// Where a speaker goes when the output layout lacks it
+fn fold(speaker: Speaker) -> &'static [(Speaker, f32)] {
+    use Speaker::*;
+    match speaker {
+        Mono | Center => &[(Left, FOLD_GAIN), (Right, FOLD_GAIN)],
+        Left | Right => &[(Mono, 0.5)],
+        SurroundLeft => &[(Left, FOLD_GAIN)],
+        SurroundRight => &[(Right, FOLD_GAIN)],
+        // Low-frequency effects are dropped when there is no LFE output
+        Lfe => &[],
+    }
+}
+
+/// Add the contribution of `speaker` at `gain` to the `column` of a routing
+/// matrix with `input` columns
+fn route(
+    speaker: Speaker,
+    gain: f32,
+    output: &[Speaker],
+    matrix: &mut [f32],
+    input: usize,
+    column: usize,
+) {
+    match output.iter().position(|&s| s == speaker) {
+        Some(row) => matrix[row * input + column] += gain,
+        None => {
+            for &(target, fold_gain) in fold(speaker) {
+                route(target, gain * fold_gain, output, matrix, input, column);
+            }
+        }
+    }
+}
+
+/// Curve used to pan a mono source between two speakers.
+///
+/// With `x = (1 - pan) / 2` for the left and `x = (1 + pan) / 2` for the
+/// right channel, each law's gain is:
+///
+/// - `EqualPower`: `x^0.5`, -3 dB at center, constant total power
+/// - `Linear`: `x`, -6 dB at center, constant summed amplitude
+/// - `Compromise`: `x^0.75`, -4.5 dB at center, halfway between the two
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum PanLaw {
+    EqualPower,
+    Linear,
+    Compromise,
+}
+
+impl PanLaw {
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "equal_power" | "-3db" => Some(Self::EqualPower),
+            "linear" | "-6db" => Some(Self::Linear),
+            "-4.5db" => Some(Self::Compromise),
+            _ => None,
+        }
+    }
+
+    fn exponent(self) -> f32 {
+        match self {
+            Self::EqualPower => 0.5,
+            Self::Linear => 1.0,
+            Self::Compromise => 0.75,
+        }
+    }
+}
+
+/// Gains for the left and right channel at `pan` (-1.0 left to 1.0 right)
+pub(crate) fn pan_gains(pan: f32, law: PanLaw) -> (f32, f32) {
+    let pan = pan.clamp(-1.0, 1.0);
+    let exponent = law.exponent();
+    (
+        ((1.0 - pan) / 2.0).powf(exponent),
+        ((1.0 + pan) / 2.0).powf(exponent),
+    )
+}
+
+/// Balance gains for the left and right channel of a stereo source; the
+/// centered position leaves both sides untouched
+fn balance_gains(pan: f32) -> (f32, f32) {
+    let pan = pan.clamp(-1.0, 1.0);
+    ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0))
+}
+
+/// Per-channel gains of the master balance on an output with `layout`:
+/// like a stereo track's pan, it attenuates the front speaker on
+/// the opposite side and leaves all other channels alone
+pub(crate) fn balance_channel_gains(layout: ChannelLayout, balance: f32) -> Vec<f32> {
+    let (left, right) = balance_gains(balance);
+    layout
+        .speakers()
+        .iter()
+        .map(|speaker| match speaker {
+            Speaker::Left => left,
+            Speaker::Right => right,
+            _ => 1.0,
+        })
+        .collect()
+}
+
+/// Routing matrix from the `input` to the `output` layout, row-major with one row
+/// of `input` gains per output channel.
+///
+/// Mono sources are panned between the front left and right speakers with
+/// `law`, and for stereo sources pan acts as a balance control that
+/// attenuates the opposite side. Each speaker maps to the same speaker in
+/// the output, and missing ones fold into their neighbours (center and
+/// surrounds at -3 dB into the front pair, the front pair into mono at
+/// -6 dB).
+pub(crate) fn default_matrix(
+    input: ChannelLayout,
+    output: ChannelLayout,
+    pan: f32,
+    law: PanLaw,
+) -> Vec<f32> {
+    let mut matrix = vec![0.0; output.channels() * input.channels()];
+    fill_default_matrix(&mut matrix, input, output, pan, law);
+    matrix
+}
+
+/// Overwrite `matrix` with the routing of [`default_matrix`], so that a
+/// moving pan does not allocate a matrix per frame
+pub(crate) fn fill_default_matrix(
+    matrix: &mut [f32],
+    input: ChannelLayout,
+    output: ChannelLayout,
+    pan: f32,
+    law: PanLaw,
+) {
+    matrix.fill(0.0);
+    if input == ChannelLayout::Mono && output != ChannelLayout::Mono {
+        let (left, right) = pan_gains(pan, law);
+        matrix[0] = left;
+        matrix[1] = right;
+        return;
+    }
+    let (left, right) = if input == ChannelLayout::Stereo {
+        balance_gains(pan)
+    } else {
+        (1.0, 1.0)
+    };
+    let columns = input.channels();
+    for (column, &speaker) in input.speakers().iter().enumerate() {
+        let gain = match speaker {
+            Speaker::Left => left,
+            Speaker::Right => right,
+            _ => 1.0,
+        };
+        route(speaker, gain, output.speakers(), matrix, columns, column);
+    }
+}
+
+/// Fold interleaved stereo into mono, summing each pair at the center gain
+/// of `law` (-3 dB equal power, -6 dB linear, i.e. the average, or -4.5 dB).
+/// A trailing sample without a partner is dropped.
+pub(crate) fn downmix_to_mono(samples: &[f32], law: PanLaw) -> Vec<f32> {
+    let (gain, _) = pan_gains(0.0, law);
+    samples
+        .chunks_exact(2)
+        .map(|frame| (frame[0] + frame[1]) * gain)
+        .collect()
+}
+
+/// Duplicate mono samples into both channels of interleaved stereo at
+/// unity gain
+pub(crate) fn upmix_to_stereo(samples: &[f32]) -> Vec<f32> {
+    samples
+        .iter()
+        .flat_map(|&sample| [sample, sample])
+        .collect()
+}
+
+/// Interleave planar channels into frames. Shorter planes are padded with
+/// silence to the length of the longest.
+pub(crate) fn interleave(planes: &[Vec<f32>]) -> Vec<f32> {
+    let frames = planes.iter().map(Vec::len).max().unwrap_or(0);
+    let mut output = vec![0.0f32; frames * planes.len()];
+    for (channel, plane) in planes.iter().enumerate() {
+        for (frame, &sample) in plane.iter().enumerate() {
+            output[frame * planes.len() + channel] = sample;
+        }
+    }
+    output
+}
+
+/// Split interleaved `samples` into one plane per channel; a trailing
+/// partial frame is dropped
+pub(crate) fn deinterleave(samples: &[f32], channels: usize) -> Vec<Vec<f32>> {
+    let mut planes = vec![Vec::with_capacity(samples.len() / channels); channels];
+    for frame in samples.chunks_exact(channels) {
+        for (plane, &sample) in planes.iter_mut().zip(frame) {
+            plane.push(sample);
+        }
+    }
+    planes
+}
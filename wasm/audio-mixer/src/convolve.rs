@@ -0,0 +1,56 @@
+//! Convolution with an impulse response by FFT overlap-add
+
+use crate::fft::fft;
+
+/// Smallest FFT used; shorter impulse responses still get blocks of a
+/// useful size
+const MIN_FFT_SIZE: usize = 512;
+
+/// Convolve each channel of interleaved `samples` with the mono
+/// `impulse_response`. The output keeps the full tail, i.e. it is
+/// `impulse_response.len() - 1` frames longer than the input; with an
+/// empty impulse response it is empty.
+pub(crate) fn convolve(samples: &[f32], channels: usize, impulse_response: &[f32]) -> Vec<f32> {
+    let frames = samples.len() / channels;
+    if impulse_response.is_empty() || frames == 0 {
+        return Vec::new();
+    }
+    let ir_len = impulse_response.len();
+    let out_frames = frames + ir_len - 1;
+    let fft_size = (2 * ir_len).next_power_of_two().max(MIN_FFT_SIZE);
+    // Each block plus the response must fit one FFT without wrapping
+    let block = fft_size - ir_len + 1;
+
+    let mut ir_re = vec![0.0f64; fft_size];
+    let mut ir_im = vec![0.0f64; fft_size];
+    for (value, &sample) in ir_re.iter_mut().zip(impulse_response) {
+        *value = sample as f64;
+    }
+    fft(&mut ir_re, &mut ir_im, false);
+
+    let mut output = vec![0.0f32; out_frames * channels];
+    let mut re = vec![0.0f64; fft_size];
+    let mut im = vec![0.0f64; fft_size];
+    for channel in 0..channels {
+        for block_start in (0..frames).step_by(block) {
+            re.fill(0.0);
+            im.fill(0.0);
+            let block_frames = block.min(frames - block_start);
+            for (i, value) in re.iter_mut().take(block_frames).enumerate() {
+                *value = samples[(block_start + i) * channels + channel] as f64;
+            }
+            fft(&mut re, &mut im, false);
+            for k in 0..fft_size {
+                let (a, b) = (re[k], im[k]);
+                re[k] = a * ir_re[k] - b * ir_im[k];
+                im[k] = a * ir_im[k] + b * ir_re[k];
+            }
+            fft(&mut re, &mut im, true);
+            let len = (block_frames + ir_len - 1).min(out_frames - block_start);
+            for (i, &value) in re.iter().take(len).enumerate() {
+                output[(block_start + i) * channels + channel] += value as f32;
+            }
+        }
+    }
+    output
+}
@@ -0,0 +1,266 @@
+//! Level-dependent gain: gating, compression and limiting, and silence
+//! detection
+
+use std::collections::VecDeque;
+
+/// Noise gate on a mono buffer: regions whose level stays below
+/// `threshold_db` (dBFS) are silenced.
+///
+/// The level comes from a peak envelope follower that jumps to every peak
+/// and decays with a time constant of `release_samples`, so the gate stays
+/// open through short dips. Once it closes, the gain ramps down over
+/// another `release_samples` samples, leaving the decaying tails of words
+/// intact; on opening it ramps up over `attack_samples`.
+pub(crate) fn gate(
+    samples: &[f32],
+    threshold_db: f32,
+    attack_samples: usize,
+    release_samples: usize,
+) -> Vec<f32> {
+    let threshold = 10f32.powf(threshold_db / 20.0);
+    let decay = if release_samples == 0 {
+        0.0
+    } else {
+        (-1.0 / release_samples as f32).exp()
+    };
+    let attack_step = 1.0 / attack_samples.max(1) as f32;
+    let release_step = 1.0 / release_samples.max(1) as f32;
+
+    let mut envelope = 0.0f32;
+    let mut gain = 0.0f32;
+    samples
+        .iter()
+        .map(|&sample| {
+            envelope = sample.abs().max(envelope * decay);
+            gain = if envelope >= threshold {
+                (gain + attack_step).min(1.0)
+            } else {
+                (gain - release_step).max(0.0)
+            };
+            sample * gain
+        })
+        .collect()
+}
+
+/// Quietest level the compressor detector distinguishes, in dBFS
+const DETECTOR_FLOOR_DB: f32 = -120.0;
+
+/// Settings of [`compress`]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Compressor {
+    pub threshold_db: f32,
+    pub ratio: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    pub makeup_db: f32,
+}
+
+/// One-pole smoothing coefficient for a time constant of `ms`
+fn smoothing(ms: f32, sample_rate: u32) -> f32 {
+    let samples = ms * sample_rate as f32 / 1000.0;
+    if samples <= 0.0 {
+        0.0
+    } else {
+        (-1.0 / samples).exp()
+    }
+}
+
+/// Smoothed amplitude envelope of the linear peak `levels`: a one-pole
+/// follower that rises toward louder levels with the attack time constant
+/// and falls toward quieter ones with the release one
+pub(crate) fn envelope(
+    levels: impl Iterator<Item = f32>,
+    attack_ms: f32,
+    release_ms: f32,
+    sample_rate: u32,
+) -> Vec<f32> {
+    let attack = smoothing(attack_ms, sample_rate);
+    let release = smoothing(release_ms, sample_rate);
+    let mut envelope = 0.0f32;
+    levels
+        .map(|level| {
+            let coefficient = if level > envelope { attack } else { release };
+            envelope = level + coefficient * (envelope - level);
+            envelope
+        })
+        .collect()
+}
+
+/// Gain reduction in dB for each of the linear peak `levels`: levels above
+/// the threshold are reduced by `1 - 1 / ratio` of the excess, and the
+/// reduction moves toward that target with the attack time constant when
+/// it grows and the release one when it shrinks
+fn gain_reduction(
+    levels: impl Iterator<Item = f32>,
+    settings: Compressor,
+    sample_rate: u32,
+) -> impl Iterator<Item = f32> {
+    let slope = 1.0 - 1.0 / settings.ratio.max(1.0);
+    let attack = smoothing(settings.attack_ms, sample_rate);
+    let release = smoothing(settings.release_ms, sample_rate);
+
+    let mut reduction = 0.0f32;
+    levels.map(move |level| {
+        let level_db = (20.0 * level.log10()).max(DETECTOR_FLOOR_DB);
+        let target = (level_db - settings.threshold_db).max(0.0) * slope;
+        let coefficient = if target > reduction { attack } else { release };
+        reduction = target + coefficient * (reduction - target);
+        reduction
+    })
+}
+
+/// Feed-forward compressor with a peak detector on a mono buffer, reducing
+/// gain as [`gain_reduction`] describes and then adding the makeup gain.
+/// Returns the output and the reduction applied to each sample, in dB.
+pub(crate) fn compress(
+    samples: &[f32],
+    settings: Compressor,
+    sample_rate: u32,
+) -> (Vec<f32>, Vec<f32>) {
+    let levels = samples.iter().map(|sample| sample.abs());
+    samples
+        .iter()
+        .zip(gain_reduction(levels, settings, sample_rate))
+        .map(|(&sample, reduction)| {
+            let gain = 10f32.powf((settings.makeup_db - reduction) / 20.0);
+            (sample * gain, reduction)
+        })
+        .unzip()
+}
+
+/// Lookahead peak limiter on interleaved `samples`, keeping every frame at
+/// or below the linear `ceiling`.
+///
+/// The output is the input delayed by `lookahead` frames, so it is that
+/// much longer. Over the delay the gain is already ramping down to what
+/// the coming peak needs: the smallest gain needed within the lookahead
+/// window is averaged over the window, which reaches the needed gain no
+/// later than the peak itself. Afterwards the gain recovers with the
+/// `release_ms` time constant.
+pub(crate) fn limit(
+    samples: &[f32],
+    channels: usize,
+    ceiling: f32,
+    lookahead: usize,
+    release_ms: f32,
+    sample_rate: u32,
+) -> Vec<f32> {
+    let needed: Vec<f32> = frame_peaks(samples, channels)
+        .map(|peak| if peak > ceiling { ceiling / peak } else { 1.0 })
+        .collect();
+    let release = smoothing(release_ms, sample_rate);
+    let window = lookahead + 1;
+    let mut output = vec![0.0f32; (needed.len() + lookahead) * channels];
+
+    // Candidates for the window minimum, increasing from the front
+    let mut minima: VecDeque<(usize, f32)> = VecDeque::with_capacity(window);
+    let mut recent: VecDeque<f32> = vec![1.0; window].into();
+    let mut sum = window as f64;
+    let mut gain = 1.0f32;
+    for (frame, out) in output.chunks_exact_mut(channels).enumerate() {
+        let need = needed.get(frame).copied().unwrap_or(1.0);
+        while minima.back().is_some_and(|&(_, g)| g >= need) {
+            minima.pop_back();
+        }
+        minima.push_back((frame, need));
+        if minima[0].0 + window <= frame {
+            minima.pop_front();
+        }
+        let minimum = minima[0].1;
+        sum += (minimum - recent.pop_front().unwrap_or(1.0)) as f64;
+        recent.push_back(minimum);
+
+        let target = ((sum / window as f64) as f32).min(1.0);
+        gain = if target < gain {
+            target
+        } else {
+            target + release * (gain - target)
+        };
+        if let Some(delayed) = frame.checked_sub(lookahead) {
+            let input = &samples[delayed * channels..(delayed + 1) * channels];
+            for (out, &sample) in out.iter_mut().zip(input) {
+                *out = sample * gain;
+            }
+        }
+    }
+    output
+}
+
+/// A track ducked by the level of another track, the key
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Sidechain {
+    /// Index of the key track in the mixer
+    pub key: usize,
+    pub settings: Compressor,
+}
+
+/// Linear gain for each frame of a track ducked by a key whose frames have
+/// the peak `key_levels`: the key's level above the threshold drives the
+/// reduction as in the compressor, applied to the ducked track instead
+pub(crate) fn duck_gains(key_levels: &[f32], settings: Compressor, sample_rate: u32) -> Vec<f32> {
+    let levels = key_levels.iter().copied();
+    gain_reduction(levels, settings, sample_rate)
+        .map(|reduction| 10f32.powf((settings.makeup_db - reduction) / 20.0))
+        .collect()
+}
+
+/// How far the level must fall below the silence threshold before sound
+/// counts as silent again, so a level hovering at the threshold does not
+/// flip between the two
+const SILENCE_HYSTERESIS_DB: f32 = 3.0;
+
+/// Loudest sample of each frame
+pub(crate) fn frame_peaks(samples: &[f32], channels: usize) -> impl Iterator<Item = f32> + '_ {
+    samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs())))
+}
+
+/// First and one past the last frame whose peak reaches `threshold_db`
+/// (dBFS); `None` when the whole buffer is below it
+pub(crate) fn sound_span(
+    samples: &[f32],
+    channels: usize,
+    threshold_db: f32,
+) -> Option<(usize, usize)> {
+    let threshold = 10f32.powf(threshold_db / 20.0);
+    let mut loud = frame_peaks(samples, channels)
+        .enumerate()
+        .filter(|&(_, peak)| peak >= threshold)
+        .map(|(frame, _)| frame);
+    let first = loud.next()?;
+    Some((first, loud.last().unwrap_or(first) + 1))
+}
+
+/// Silent gaps between sound, as flat `[start, end)` frame pairs; leading
+/// and trailing silence is not included.
+///
+/// Sound starts at a frame peaking at `threshold_db` or above and only
+/// turns silent once peaks fall a further 3 dB below it. Gaps shorter than
+/// `min_frames` are ignored, which also skips the zero crossings of
+/// low-frequency sound.
+pub(crate) fn silence_regions(
+    samples: &[f32],
+    channels: usize,
+    threshold_db: f32,
+    min_frames: usize,
+) -> Vec<u32> {
+    let open = 10f32.powf(threshold_db / 20.0);
+    let close = 10f32.powf((threshold_db - SILENCE_HYSTERESIS_DB) / 20.0);
+    let mut regions = Vec::new();
+    let mut heard = false;
+    let mut silent_from = None;
+    for (frame, peak) in frame_peaks(samples, channels).enumerate() {
+        if peak >= open {
+            if let Some(start) = silent_from.take() {
+                if frame - start >= min_frames.max(1) {
+                    regions.extend([start as u32, frame as u32]);
+                }
+            }
+            heard = true;
+        } else if peak < close && heard && silent_from.is_none() {
+            silent_from = Some(frame);
+        }
+    }
+    regions
+}
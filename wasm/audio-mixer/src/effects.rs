@@ -0,0 +1,102 @@
+//! Offline effects applied to whole buffers
+
+/// Largest feedback magnitude accepted by the delay; anything closer to 1.0
+/// rings for so long that the tail becomes unbounded in practice
+pub(crate) const MAX_DELAY_FEEDBACK: f32 = 0.95;
+/// Level (-60 dB) at which a delay tail is considered to have died out
+const TAIL_FLOOR: f32 = 0.001;
+
+/// Number of echoes until a delay tail decays below -60 dB; the first
+/// echo has full level and each further one is scaled by `feedback`
+fn delay_repeats(feedback: f32) -> usize {
+    let feedback = feedback.abs();
+    if feedback <= TAIL_FLOOR {
+        return 1;
+    }
+    1 + (TAIL_FLOOR.ln() / feedback.ln()).ceil() as usize
+}
+
+/// Output length of [`delay`] for an input of `len` samples: enough echoes
+/// for the tail to fall below -60 dB
+pub(crate) fn delay_output_len(len: usize, delay_samples: usize, feedback: f32) -> usize {
+    let feedback = feedback.clamp(-MAX_DELAY_FEEDBACK, MAX_DELAY_FEEDBACK);
+    if delay_samples == 0 {
+        return len;
+    }
+    len + delay_samples * delay_repeats(feedback)
+}
+
+/// Feedback delay on a mono buffer: every `delay_samples` the signal
+/// repeats, scaled by `feedback` each time, and `mix` blends the echoes
+/// (wet) with the input (dry)
+pub(crate) fn delay(samples: &[f32], delay_samples: usize, feedback: f32, mix: f32) -> Vec<f32> {
+    let feedback = feedback.clamp(-MAX_DELAY_FEEDBACK, MAX_DELAY_FEEDBACK);
+    let len = delay_output_len(samples.len(), delay_samples, feedback);
+    if delay_samples == 0 {
+        return samples.to_vec();
+    }
+
+    let mut wet = vec![0.0f32; len];
+    for n in delay_samples..len {
+        let input = samples.get(n - delay_samples).copied().unwrap_or(0.0);
+        wet[n] = input + feedback * wet[n - delay_samples];
+    }
+    blend(samples, &wet, mix)
+}
+
+/// Dry/wet mix of an effect: `dry * (1 - mix) + wet * mix` with `mix`
+/// clamped to [0.0, 1.0]. The shorter buffer is padded with silence, so a
+/// wet tail longer than the input is kept.
+pub(crate) fn blend(dry: &[f32], wet: &[f32], mix: f32) -> Vec<f32> {
+    let mix = mix.clamp(0.0, 1.0);
+    (0..dry.len().max(wet.len()))
+        .map(|n| {
+            let dry = dry.get(n).copied().unwrap_or(0.0);
+            let wet = wet.get(n).copied().unwrap_or(0.0);
+            dry * (1.0 - mix) + wet * mix
+        })
+        .collect()
+}
+
+/// Scale the side (L - R) component of interleaved stereo `samples` by
+/// `width`, keeping the mid (L + R): 0.0 collapses to mono, 1.0 leaves the
+/// image alone and larger values widen it. Widened samples are clamped to
+/// full scale, and a trailing incomplete frame is left untouched.
+pub(crate) fn stereo_width(samples: &mut [f32], width: f32) {
+    let width = width.max(0.0);
+    if width == 1.0 {
+        return;
+    }
+    for frame in samples.chunks_exact_mut(2) {
+        let mid = (frame[0] + frame[1]) * 0.5;
+        let side = (frame[0] - frame[1]) * 0.5 * width;
+        frame[0] = (mid + side).clamp(-1.0, 1.0);
+        frame[1] = (mid - side).clamp(-1.0, 1.0);
+    }
+}
+
+/// Subtract the mean of each channel of interleaved `samples` from that
+/// channel, so asymmetric offsets on stereo material are each removed. A
+/// trailing incomplete frame is left untouched.
+pub(crate) fn remove_dc_offset(samples: &mut [f32], channels: usize) {
+    let channels = channels.max(1);
+    let frames = samples.len() / channels;
+    if frames == 0 {
+        return;
+    }
+    let mut sums = vec![0.0f64; channels];
+    for frame in samples.chunks_exact(channels) {
+        for (sum, &sample) in sums.iter_mut().zip(frame) {
+            *sum += sample as f64;
+        }
+    }
+    let offsets: Vec<f32> = sums
+        .iter()
+        .map(|sum| (sum / frames as f64) as f32)
+        .collect();
+    for frame in samples.chunks_exact_mut(channels) {
+        for (sample, offset) in frame.iter_mut().zip(&offsets) {
+            *sample -= offset;
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Time-varying gain applied to a track while mixing
+
+use std::f32::consts::{FRAC_PI_2, PI};
+
+/// Shape of a fade ramp
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub(crate) enum FadeCurve {
+    /// Gain rises in a straight line; dips audibly in the middle of a
+    /// crossfade
+    #[default]
+    Linear,
+    /// `sin(t * pi / 2)`, keeping constant power when two fades overlap
+    EqualPower,
+    /// `t^2`: starts slowly and rises steeply at the end
+    Exponential,
+    /// `1 - (1 - t)^2`: rises quickly and settles gently
+    Logarithmic,
+    /// `(1 - cos(t * pi)) / 2`: gentle at both ends
+    SCurve,
+}
+
+impl FadeCurve {
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "linear" => Some(Self::Linear),
+            "equal_power" => Some(Self::EqualPower),
+            "exponential" => Some(Self::Exponential),
+            "logarithmic" => Some(Self::Logarithmic),
+            "s_curve" => Some(Self::SCurve),
+            _ => None,
+        }
+    }
+
+    /// Gain at position `t` of a fade-in, from 0.0 to 1.0
+    fn gain(self, t: f32) -> f32 {
+        match self {
+            Self::Linear => t,
+            Self::EqualPower => (t * FRAC_PI_2).sin(),
+            Self::Exponential => t * t,
+            Self::Logarithmic => 1.0 - (1.0 - t) * (1.0 - t),
+            Self::SCurve => (1.0 - (t * PI).cos()) / 2.0,
+        }
+    }
+}
+
+/// Whether a gain ramp rises or falls
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum FadeDirection {
+    In,
+    Out,
+}
+
+impl FadeDirection {
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "in" => Some(Self::In),
+            "out" => Some(Self::Out),
+            _ => None,
+        }
+    }
+}
+
+/// Gain ramp `len` samples long that runs the whole curve, from exactly
+/// 0.0 to 1.0 for a fade-in and back for a fade-out. A single sample ramp
+/// is the end gain.
+pub(crate) fn fade_ramp(len: usize, curve: FadeCurve, direction: FadeDirection) -> Vec<f32> {
+    let last = len.saturating_sub(1).max(1) as f32;
+    (0..len)
+        .map(|i| {
+            let t = if len == 1 { 1.0 } else { i as f32 / last };
+            match direction {
+                FadeDirection::In => curve.gain(t),
+                FadeDirection::Out => curve.gain(1.0 - t),
+            }
+        })
+        .collect()
+}
+
+/// Fade-in and fade-out ramps at the edges of a track, in frames
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Fades {
+    pub fade_in: usize,
+    pub fade_out: usize,
+    pub curve: FadeCurve,
+}
+
+impl Fades {
+    /// Fit the fades into a track `frames` long: when they would overlap,
+    /// both are shortened proportionally so they meet without overlapping
+    pub(crate) fn clamped(self, frames: usize) -> Self {
+        let total = self.fade_in + self.fade_out;
+        if total <= frames {
+            return self;
+        }
+        let fade_in = (self.fade_in as u64 * frames as u64 / total as u64) as usize;
+        Self {
+            fade_in,
+            fade_out: frames - fade_in,
+            curve: self.curve,
+        }
+    }
+
+    /// Gain of frame `index` in a track `frames` long. The first frame of a
+    /// fade-in and the last frame of a fade-out are silent.
+    pub(crate) fn gain(&self, index: usize, frames: usize) -> f32 {
+        let from_end = frames.saturating_sub(index + 1);
+        if index < self.fade_in {
+            self.curve.gain(index as f32 / self.fade_in as f32)
+        } else if from_end < self.fade_out {
+            self.curve.gain(from_end as f32 / self.fade_out as f32)
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Breakpoint automation of a track parameter, linearly interpolated
+/// between points and held before the first and after the last
+pub(crate) struct Automation {
+    /// (frame offset from the track start, value), sorted by offset
+    points: Vec<(usize, f32)>,
+}
+
+impl Automation {
+    /// Pair up offsets and values; `None` when they differ in length or
+    /// are empty
+    pub(crate) fn new(offsets: &[u32], values: &[f32]) -> Option<Self> {
+        if offsets.is_empty() || offsets.len() != values.len() {
+            return None;
+        }
+        let mut points: Vec<(usize, f32)> = offsets
+            .iter()
+            .map(|&offset| offset as usize)
+            .zip(values.iter().copied())
+            .collect();
+        points.sort_by_key(|&(offset, _)| offset);
+        Some(Self { points })
+    }
+
+    /// Value at frame `index` of the track
+    pub(crate) fn value_at(&self, index: usize) -> f32 {
+        let next = self.points.partition_point(|&(offset, _)| offset <= index);
+        if next == 0 {
+            return self.points[0].1;
+        }
+        let (start, from) = self.points[next - 1];
+        match self.points.get(next) {
+            Some(&(end, to)) => from + (to - from) * (index - start) as f32 / (end - start) as f32,
+            None => from,
+        }
+    }
+}
+
+/// Gain at frame `index` of a track whose mute state toggles at each of
+/// the sorted frames in `changes`, starting unmuted. Each toggle ramps
+/// linearly to the new state over `ramp` frames from the change; a toggle
+/// during a ramp turns around from the level reached so far.
+pub(crate) fn mute_gain(changes: &[usize], ramp: usize, index: usize) -> f32 {
+    let approach = |level: f32, target: f32, elapsed: usize| {
+        if elapsed >= ramp {
+            return target;
+        }
+        let step = elapsed as f32 / ramp as f32;
+        if target > level {
+            (level + step).min(target)
+        } else {
+            (level - step).max(target)
+        }
+    };
+    let (mut level, mut target, mut since) = (1.0, 1.0, 0);
+    for &change in changes.iter().take_while(|&&change| change <= index) {
+        level = approach(level, target, change - since);
+        target = 1.0 - target;
+        since = change;
+    }
+    approach(level, target, index - since)
+}
+
+/// Join interleaved buffers end to end. With `declick_frames`, each join
+/// overlaps that many frames (clamped to the shorter side) with a linear
+/// crossfade to hide the splice, shortening the output by the overlap.
+pub(crate) fn concat(buffers: &[Vec<f32>], channels: usize, declick_frames: usize) -> Vec<f32> {
+    let mut output: Vec<f32> = Vec::with_capacity(buffers.iter().map(Vec::len).sum());
+    for buffer in buffers {
+        let fade = declick_frames
+            .min(output.len() / channels)
+            .min(buffer.len() / channels);
+        let overlap_start = output.len() - fade * channels;
+        for (i, (out, &next)) in output[overlap_start..].iter_mut().zip(buffer).enumerate() {
+            let t = (i / channels) as f32 / fade as f32;
+            *out = *out * (1.0 - t) + next * t;
+        }
+        output.extend_from_slice(&buffer[fade * channels..]);
+    }
+    output
+}
+
+/// Repeat interleaved `samples` end to end until they fill `frames`
+/// frames, cutting the last repeat short. With `crossfade_frames`, each
+/// loop point overlaps that many frames (at most one less than the loop)
+/// with a linear crossfade, as in [`concat`].
+pub(crate) fn repeat(
+    samples: &[f32],
+    channels: usize,
+    frames: usize,
+    crossfade_frames: usize,
+) -> Vec<f32> {
+    let len = samples.len() / channels;
+    let target = frames * channels;
+    if len == 0 {
+        return Vec::new();
+    }
+    let samples = &samples[..len * channels];
+    let crossfade = crossfade_frames.min(len - 1);
+    let mut output = Vec::with_capacity(target + samples.len());
+    while output.len() < target {
+        let fade = crossfade.min(output.len() / channels);
+        let overlap_start = output.len() - fade * channels;
+        for (i, (out, &next)) in output[overlap_start..].iter_mut().zip(samples).enumerate() {
+            let t = (i / channels) as f32 / fade as f32;
+            *out = *out * (1.0 - t) + next * t;
+        }
+        output.extend_from_slice(&samples[fade * channels..]);
+    }
+    output.truncate(target);
+    output
+}
+
+/// Join two mono buffers, overlapping the end of `a` with the start of `b`
+/// for `fade_samples` samples while `a` fades out and `b` fades in.
+///
+/// The fade is clamped to the shorter buffer, so the output always holds
+/// `a.len() + b.len() - fade` samples.
+pub(crate) fn crossfade(a: &[f32], b: &[f32], fade_samples: usize, curve: FadeCurve) -> Vec<f32> {
+    let fade = fade_samples.min(a.len()).min(b.len());
+    let overlap_start = a.len() - fade;
+    let mut output = Vec::with_capacity(a.len() + b.len() - fade);
+    output.extend_from_slice(&a[..overlap_start]);
+    for i in 0..fade {
+        let t = i as f32 / fade as f32;
+        output.push(a[overlap_start + i] * curve.gain(1.0 - t) + b[i] * curve.gain(t));
+    }
+    output.extend_from_slice(&b[fade..]);
+    output
+}
@@ -0,0 +1,150 @@
+//! Errors surfaced to JavaScript as thrown exceptions
+
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum MixerError {
+    /// A string option that does not name one of the accepted values
+    UnknownOption {
+        option: &'static str,
+        value: String,
+        expected: &'static str,
+    },
+    /// Pan that is not a number; finite values are clamped instead
+    InvalidPan(f32),
+    InvalidGain(f32),
+    InvalidSampleRate(u32),
+    /// A channel count of 0 or more than MAX_CHANNELS
+    InvalidChannelCount(u32),
+    /// WAV bit depth other than 16, 24 or 32
+    UnsupportedBitDepth(u32),
+    /// Bytes passed to decode_wav that are not a readable WAV file
+    InvalidWav(&'static str),
+    /// A WAV format tag other than integer PCM or IEEE float
+    UnsupportedWavFormat(u16),
+    /// A track was created without any samples
+    EmptyBuffer,
+    /// Breakpoint offsets and values that do not pair up
+    InvalidAutomation,
+    TrackOutOfRange {
+        index: usize,
+        count: usize,
+    },
+    /// A StreamingMixer track id that was never added
+    UnknownTrackId(u32),
+    /// A StreamingMixer track id that is already in use
+    DuplicateTrackId(u32),
+    /// An EQ preset entry that is not a band object
+    InvalidEqBand {
+        index: u32,
+        reason: &'static str,
+    },
+    /// An add_tracks_bulk entry that does not describe a track
+    InvalidTrackDescriptor {
+        index: u32,
+        reason: &'static str,
+    },
+    /// Bytes passed to AudioMixer.deserialize that are not a valid session
+    InvalidProject(&'static str),
+    /// A session saved by a newer version of the format
+    UnsupportedProjectVersion(u16),
+    /// An FFT size that is not a power of two of at least 2
+    InvalidFftSize(usize),
+    /// A sidechain whose key is the track it ducks
+    SelfSidechain(usize),
+    /// A mute ramp that is negative or not a number
+    InvalidRampTime(f32),
+    /// An output ceiling that is not above 0 and at most full scale
+    InvalidCeiling(f32),
+    /// A buffer to concatenate that does not hold whole frames
+    PartialFrame {
+        index: usize,
+        len: usize,
+        channels: usize,
+    },
+}
+
+impl fmt::Display for MixerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownOption {
+                option,
+                value,
+                expected,
+            } => write!(f, "unknown {option} \"{value}\": expected {expected}"),
+            Self::InvalidPan(pan) => {
+                write!(f, "invalid pan {pan}: expected a value from -1.0 to 1.0")
+            }
+            Self::InvalidGain(gain) => write!(f, "invalid gain {gain}: expected a finite number"),
+            Self::InvalidSampleRate(rate) => {
+                write!(f, "invalid sample rate {rate} Hz: expected a positive rate")
+            }
+            Self::InvalidChannelCount(channels) => write!(
+                f,
+                "invalid channel count {channels}: expected 1 to {}",
+                crate::channels::MAX_CHANNELS
+            ),
+            Self::UnsupportedBitDepth(bits) => {
+                write!(f, "unsupported bit depth {bits}: expected 16, 24 or 32")
+            }
+            Self::InvalidWav(reason) => write!(f, "invalid WAV file: {reason}"),
+            Self::UnsupportedWavFormat(tag) => write!(
+                f,
+                "unsupported WAV format tag {tag:#06x}: expected PCM (1) or IEEE float (3)"
+            ),
+            Self::EmptyBuffer => write!(f, "audio buffer is empty"),
+            Self::InvalidAutomation => write!(
+                f,
+                "automation needs one value per offset and at least one breakpoint"
+            ),
+            Self::TrackOutOfRange { index, count } => {
+                write!(f, "track index {index} is out of range for {count} tracks")
+            }
+            Self::UnknownTrackId(id) => write!(f, "no streaming track with id {id}"),
+            Self::DuplicateTrackId(id) => write!(f, "streaming track id {id} is already in use"),
+            Self::InvalidEqBand { index, reason } => {
+                write!(f, "invalid EQ band #{index}: {reason}")
+            }
+            Self::InvalidTrackDescriptor { index, reason } => {
+                write!(f, "invalid track descriptor #{index}: {reason}")
+            }
+            Self::InvalidProject(reason) => write!(f, "invalid mixer session: {reason}"),
+            Self::UnsupportedProjectVersion(version) => write!(
+                f,
+                "unsupported mixer session version {version}: expected 1 to {}",
+                crate::project::VERSION
+            ),
+            Self::SelfSidechain(index) => {
+                write!(f, "track {index} cannot be the key of its own sidechain")
+            }
+            Self::InvalidRampTime(ms) => write!(
+                f,
+                "invalid mute ramp {ms} ms: expected a finite number of at least 0"
+            ),
+            Self::InvalidCeiling(ceiling) => write!(
+                f,
+                "invalid output ceiling {ceiling}: expected a level above 0.0 and at most 1.0"
+            ),
+            Self::InvalidFftSize(size) => {
+                write!(f, "invalid FFT size {size}: expected a power of two, 2 or more")
+            }
+            Self::PartialFrame {
+                index,
+                len,
+                channels,
+            } => write!(
+                f,
+                "buffer #{index} holds {len} samples, not a whole number of {channels}-channel frames"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MixerError {}
+
+impl From<MixerError> for JsValue {
+    fn from(error: MixerError) -> Self {
+        JsError::new(&error.to_string()).into()
+    }
+}
@@ -0,0 +1,110 @@
+//! Fast Fourier transform and spectrum analysis
+
+use std::f64::consts::PI;
+
+/// Level reported for empty bins, in dBFS
+const SPECTRUM_FLOOR_DB: f32 = -120.0;
+
+/// Window applied to a block before its spectrum is taken
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Window {
+    /// Hann: good general-purpose leakage suppression
+    Hann,
+    /// Hamming: narrower main lobe, higher far sidelobes
+    Hamming,
+    /// Blackman: lowest sidelobes, widest main lobe
+    Blackman,
+}
+
+impl Window {
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "hann" | "hanning" => Some(Self::Hann),
+            "hamming" => Some(Self::Hamming),
+            "blackman" => Some(Self::Blackman),
+            _ => None,
+        }
+    }
+
+    /// Weight of sample `i` in a window `len` samples long
+    fn weight(self, i: usize, len: usize) -> f64 {
+        let x = 2.0 * PI * i as f64 / len as f64;
+        match self {
+            Self::Hann => 0.5 - 0.5 * x.cos(),
+            Self::Hamming => 0.54 - 0.46 * x.cos(),
+            Self::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos(),
+        }
+    }
+}
+
+/// In-place iterative radix-2 FFT of `re` + i`im`, whose length must be a
+/// power of two. The inverse transform is scaled by 1/n.
+pub(crate) fn fft(re: &mut [f64], im: &mut [f64], inverse: bool) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = if inverse { 2.0 } else { -2.0 } * PI / len as f64;
+        let (w_im, w_re) = angle.sin_cos();
+        for start in (0..n).step_by(len) {
+            let (mut c_re, mut c_im) = (1.0, 0.0);
+            for a in start..start + len / 2 {
+                let b = a + len / 2;
+                let t_re = re[b] * c_re - im[b] * c_im;
+                let t_im = re[b] * c_im + im[b] * c_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+                (c_re, c_im) = (c_re * w_re - c_im * w_im, c_re * w_im + c_im * w_re);
+            }
+        }
+        len <<= 1;
+    }
+
+    if inverse {
+        let scale = 1.0 / n as f64;
+        re.iter_mut().chain(im.iter_mut()).for_each(|v| *v *= scale);
+    }
+}
+
+/// Magnitude spectrum of the first `fft_size` samples of a mono buffer,
+/// zero-padded when shorter, in dBFS: `fft_size / 2 + 1` bins from 0 Hz to
+/// Nyquist. Magnitudes are scaled by the window's gain so a full-scale sine
+/// centered on a bin reads 0 dB. `fft_size` must be a power of two.
+pub(crate) fn spectrum(samples: &[f32], fft_size: usize, window: Window) -> Vec<f32> {
+    let mut re = vec![0.0f64; fft_size];
+    let mut im = vec![0.0f64; fft_size];
+    let mut window_sum = 0.0;
+    for (i, value) in re.iter_mut().enumerate() {
+        let weight = window.weight(i, fft_size);
+        window_sum += weight;
+        *value = samples.get(i).copied().unwrap_or(0.0) as f64 * weight;
+    }
+    fft(&mut re, &mut im, false);
+
+    let scale = 2.0 / window_sum;
+    (0..=fft_size / 2)
+        .map(|k| {
+            let magnitude = re[k].hypot(im[k]) * scale;
+            if magnitude > 0.0 {
+                (20.0 * magnitude.log10() as f32).max(SPECTRUM_FLOOR_DB)
+            } else {
+                SPECTRUM_FLOOR_DB
+            }
+        })
+        .collect()
+}
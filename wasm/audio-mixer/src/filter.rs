@@ -0,0 +1,172 @@
+//! Biquad filters using the RBJ Audio EQ Cookbook coefficients
+
+use std::f64::consts::PI;
+
+/// Lowest Q accepted; smaller values make the filter numerically unstable
+const MIN_Q: f32 = 0.01;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum FilterKind {
+    Lowpass,
+    Highpass,
+    /// Constant 0 dB peak gain at the center frequency
+    Bandpass,
+    /// Boost or cut of `gain_db` around the center frequency
+    Peaking,
+    /// Boost or cut of `gain_db` below the corner frequency
+    LowShelf,
+    /// Boost or cut of `gain_db` above the corner frequency
+    HighShelf,
+}
+
+impl FilterKind {
+    /// The pass filters, which have no gain setting
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "lowpass" | "low_pass" => Some(Self::Lowpass),
+            "highpass" | "high_pass" => Some(Self::Highpass),
+            "bandpass" | "band_pass" => Some(Self::Bandpass),
+            _ => None,
+        }
+    }
+
+    /// Any kind usable as an EQ band, including the ones with a gain
+    pub(crate) fn parse_band(name: &str) -> Option<Self> {
+        Self::parse(name).or_else(|| match name.to_ascii_lowercase().as_str() {
+            "peaking" | "peak" => Some(Self::Peaking),
+            "lowshelf" | "low_shelf" => Some(Self::LowShelf),
+            "highshelf" | "high_shelf" => Some(Self::HighShelf),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Lowpass => "lowpass",
+            Self::Highpass => "highpass",
+            Self::Bandpass => "bandpass",
+            Self::Peaking => "peaking",
+            Self::LowShelf => "lowshelf",
+            Self::HighShelf => "highshelf",
+        }
+    }
+}
+
+/// Filter settings attached to a track; coefficients are derived at mix
+/// time from the mixer's sample rate
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FilterSpec {
+    pub kind: FilterKind,
+    pub cutoff: f32,
+    pub q: f32,
+    /// Boost or cut of the peaking and shelving kinds; ignored by the pass
+    /// filters
+    pub gain_db: f32,
+}
+
+/// Normalized biquad coefficients (a0 = 1)
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Biquad {
+    /// Coefficients for `spec` at `sample_rate`. The cutoff is kept below
+    /// Nyquist and Q above a small minimum so the filter stays stable.
+    pub(crate) fn new(spec: FilterSpec, sample_rate: u32) -> Self {
+        let sample_rate = sample_rate.max(1) as f64;
+        let cutoff = (spec.cutoff as f64).min(sample_rate * 0.49).max(1e-3);
+        let q = spec.q.max(MIN_Q) as f64;
+
+        let w0 = 2.0 * PI * cutoff / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+        // Amplitude of the peaking and shelving gain
+        let a = 10f64.powf(spec.gain_db as f64 / 40.0);
+        let shelf = 2.0 * a.sqrt() * alpha;
+        let ([b0, b1, b2], [a0, a1, a2]) = match spec.kind {
+            FilterKind::Lowpass => (
+                [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterKind::Highpass => (
+                [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterKind::Bandpass => ([alpha, 0.0, -alpha], [1.0 + alpha, -2.0 * cos, 1.0 - alpha]),
+            FilterKind::Peaking => (
+                [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+                [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+            ),
+            FilterKind::LowShelf => (
+                [
+                    a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                ],
+                [
+                    (a + 1.0) + (a - 1.0) * cos + shelf,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - shelf,
+                ],
+            ),
+            FilterKind::HighShelf => (
+                [
+                    a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                ],
+                [
+                    (a + 1.0) - (a - 1.0) * cos + shelf,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - shelf,
+                ],
+            ),
+        };
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    /// Coefficients already normalized so that a0 = 1
+    pub(crate) fn from_coefficients(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Self {
+        Self { b0, b1, b2, a1, a2 }
+    }
+
+    /// Filter interleaved `samples` in place, keeping separate state for
+    /// each of the `channels` so the filter runs continuously per channel
+    pub(crate) fn process(&self, samples: &mut [f32], channels: usize) {
+        // Transposed direct form II state per channel
+        let mut state = vec![(0.0f64, 0.0f64); channels];
+        for frame in samples.chunks_exact_mut(channels) {
+            for (sample, (s1, s2)) in frame.iter_mut().zip(state.iter_mut()) {
+                let x = *sample as f64;
+                let y = self.b0 * x + *s1;
+                *s1 = self.b1 * x - self.a1 * y + *s2;
+                *s2 = self.b2 * x - self.a2 * y;
+                *sample = y as f32;
+            }
+        }
+    }
+}
+
+/// Run interleaved `samples` through `bands` in order, each band keeping its
+/// own state per channel
+pub(crate) fn process_chain(
+    bands: &[FilterSpec],
+    samples: &mut [f32],
+    channels: usize,
+    sample_rate: u32,
+) {
+    for &band in bands {
+        Biquad::new(band, sample_rate).process(samples, channels);
+    }
+}
@@ -1,152 +1,4135 @@
+use js_sys::{Float32Array, Int16Array, Int32Array, Uint32Array, Uint8Array};
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use wasm_bindgen::prelude::*;
-use js_sys::Float32Array;
+
+mod channels;
+mod convolve;
+mod dynamics;
+mod effects;
+mod envelope;
+mod error;
+mod fft;
+mod filter;
+mod loudness;
+mod output;
+mod pcm;
+mod project;
+mod resample;
+mod simd;
+mod stream;
+mod stretch;
+mod wav;
+
+use channels::{ChannelLayout, PanLaw};
+use dynamics::{Compressor, Sidechain};
+use envelope::{Automation, FadeCurve, FadeDirection, Fades};
+use error::MixerError;
+use fft::Window;
+use filter::{Biquad, FilterKind, FilterSpec};
+use output::OutputMode;
+use project::{MixerState, TrackState};
+use stream::StreamTrack;
+use wav::WavFormat;
 
 /// Audio track for mixing
 #[wasm_bindgen]
 pub struct AudioTrack {
+    /// Interleaved samples with one value per channel of `layout` per frame
     samples: Vec<f32>,
+    layout: ChannelLayout,
     gain: f32,
     pan: f32, // -1.0 (left) to 1.0 (right)
     start_sample: usize,
+    /// Leading frames of `samples` that are skipped when mixing
+    trim_start: usize,
+    /// Nudge in frames on top of `start_sample`, for phase alignment
+    delay_samples: i32,
+    invert_polarity: bool,
+    /// Gains from each track channel to each output channel, row-major by
+    /// output channel; derived from the channel layouts and pan when unset
+    matrix: Option<Vec<f32>>,
+    fades: Fades,
+    /// Breakpoints replacing the scalar gain and pan when set
+    gain_automation: Option<Automation>,
+    pan_automation: Option<Automation>,
+    muted: bool,
+    soloed: bool,
+    filter: Option<FilterSpec>,
+    /// Rate the samples were recorded at, when known
+    sample_rate: Option<u32>,
+    /// Timeline frame up to which the samples repeat, when looping
+    loop_to_sample: Option<usize>,
+    /// Frames each loop point overlaps with a crossfade
+    loop_crossfade: usize,
+    /// Sorted frames where the mute state toggles, starting unmuted
+    mute_changes: Vec<usize>,
+    /// Stem the track is mixed into by mix_stems; the default stem when
+    /// unset
+    group: Option<String>,
+    /// Key track whose level ducks this one
+    sidechain: Option<Sidechain>,
+}
+
+/// A track's samples prepared for mixing up to some timeline frame
+struct Source<'a> {
+    /// Looped and filtered samples
+    samples: Cow<'a, [f32]>,
+    /// Ducking gain for each timeline frame from 0 when the track is keyed
+    /// by another
+    ducking: Option<Vec<f32>>,
 }
 
 #[wasm_bindgen]
 impl AudioTrack {
+    /// Create a mono track; throws on an empty buffer, a non-finite gain
+    /// or a NaN pan. Pan is clamped to [-1.0, 1.0].
     #[wasm_bindgen(constructor)]
-    pub fn new(samples: &Float32Array, gain: f32, pan: f32, start_sample: usize) -> Self {
-        Self {
-            samples: samples.to_vec(),
+    pub fn new(
+        samples: &Float32Array,
+        gain: f32,
+        pan: f32,
+        start_sample: usize,
+    ) -> Result<AudioTrack, JsValue> {
+        Self::with_channels(samples, 1, gain, pan, start_sample)
+    }
+
+    /// Create a track of interleaved samples with 1 to 6 channels, taken
+    /// as the default layout for the count (mono, stereo, 3.0, quad, 5.0,
+    /// 5.1). Pan positions mono tracks and balances stereo ones; it has no
+    /// effect on other layouts.
+    #[wasm_bindgen]
+    pub fn with_channels(
+        samples: &Float32Array,
+        channels: u32,
+        gain: f32,
+        pan: f32,
+        start_sample: usize,
+    ) -> Result<AudioTrack, JsValue> {
+        Ok(Self::from_samples(
+            samples.to_vec(),
+            ChannelLayout::from_channels(channels)?,
+            gain,
+            pan,
+            start_sample,
+        )?)
+    }
+
+    /// Create a track of interleaved samples in a named channel layout:
+    /// "mono", "stereo", "2.1", "3.0", "quad", "5.0" or "5.1". Otherwise
+    /// behaves like with_channels.
+    #[wasm_bindgen]
+    pub fn with_layout(
+        samples: &Float32Array,
+        layout: &str,
+        gain: f32,
+        pan: f32,
+        start_sample: usize,
+    ) -> Result<AudioTrack, JsValue> {
+        Ok(Self::from_samples(
+            samples.to_vec(),
+            channel_layout(layout)?,
+            gain,
+            pan,
+            start_sample,
+        )?)
+    }
+
+    /// Create a track from samples already written into WASM memory
+    /// through a SampleBuffer, taking the buffer over without copying it.
+    /// Otherwise behaves like with_channels.
+    #[wasm_bindgen]
+    pub fn from_wasm_slice(
+        buffer: SampleBuffer,
+        channels: u32,
+        gain: f32,
+        pan: f32,
+        start_sample: usize,
+    ) -> Result<AudioTrack, JsValue> {
+        Ok(Self::from_samples(
+            buffer.samples,
+            ChannelLayout::from_channels(channels)?,
             gain,
             pan,
             start_sample,
+        )?)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn channels(&self) -> u32 {
+        self.layout.channels() as u32
+    }
+
+    /// Name of the track's channel layout, as accepted by with_layout
+    #[wasm_bindgen(getter)]
+    pub fn channel_layout(&self) -> String {
+        self.layout.name().to_string()
+    }
+
+    /// Route the track with explicit gains instead of its pan: one row per
+    /// output channel, each holding a gain for every track channel. Output
+    /// channels without a row are left silent.
+    #[wasm_bindgen]
+    pub fn set_channel_matrix(&mut self, matrix: &Float32Array) {
+        self.matrix = Some(matrix.to_vec());
+    }
+
+    /// Go back to the default routing derived from the channel layouts
+    #[wasm_bindgen]
+    pub fn clear_channel_matrix(&mut self) {
+        self.matrix = None;
+    }
+
+    /// Ramp the track in over its first `fade_in_samples` frames and out
+    /// over its last `fade_out_samples`. Fades that together exceed the
+    /// track length are shortened proportionally.
+    #[wasm_bindgen]
+    pub fn set_fades(&mut self, fade_in_samples: usize, fade_out_samples: usize) {
+        self.fades.fade_in = fade_in_samples;
+        self.fades.fade_out = fade_out_samples;
+    }
+
+    /// Automate the gain with breakpoints: `offsets` are frames from the
+    /// track start and `values` the gain at each. Gain is interpolated
+    /// linearly between breakpoints and replaces the track's scalar gain.
+    #[wasm_bindgen]
+    pub fn set_gain_automation(
+        &mut self,
+        offsets: &Uint32Array,
+        values: &Float32Array,
+    ) -> Result<(), JsValue> {
+        self.gain_automation = Some(automation(offsets, values)?);
+        Ok(())
+    }
+
+    /// Automate the pan like set_gain_automation; replaces the scalar pan
+    #[wasm_bindgen]
+    pub fn set_pan_automation(
+        &mut self,
+        offsets: &Uint32Array,
+        values: &Float32Array,
+    ) -> Result<(), JsValue> {
+        self.pan_automation = Some(automation(offsets, values)?);
+        Ok(())
+    }
+
+    /// Remove gain and pan automation, going back to the scalar values
+    #[wasm_bindgen]
+    pub fn clear_automation(&mut self) {
+        self.gain_automation = None;
+        self.pan_automation = None;
+    }
+
+    #[wasm_bindgen]
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// While any track is soloed, only soloed tracks are heard
+    #[wasm_bindgen]
+    pub fn set_soloed(&mut self, soloed: bool) {
+        self.soloed = soloed;
+    }
+
+    /// Run the track through a biquad filter before mixing: `kind` is
+    /// "lowpass", "highpass" or "bandpass", `cutoff` the corner (or center)
+    /// frequency in Hz and `q` the resonance (0.707 for a flat Butterworth
+    /// response)
+    #[wasm_bindgen]
+    pub fn set_filter(&mut self, kind: &str, cutoff: f32, q: f32) -> Result<(), JsValue> {
+        self.filter = Some(FilterSpec {
+            kind: filter_kind(kind)?,
+            cutoff,
+            q,
+            gain_db: 0.0,
+        });
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn clear_filter(&mut self) {
+        self.filter = None;
+    }
+
+    /// Skip the first `frames` frames of the samples, e.g. to align takes;
+    /// the rest starts at the track's start_sample. Fades and automation
+    /// offsets count from the first frame that is kept.
+    #[wasm_bindgen]
+    pub fn set_trim_start(&mut self, frames: usize) {
+        self.trim_start = frames;
+    }
+
+    /// Flip the polarity of the track, e.g. for a microphone facing the
+    /// other way
+    #[wasm_bindgen]
+    pub fn set_invert_polarity(&mut self, inverted: bool) {
+        self.invert_polarity = inverted;
+    }
+
+    /// Nudge the track by `delay_samples` frames (negative is earlier) on
+    /// top of its start, for phase alignment between microphones. Frames
+    /// moved before the start of the timeline are not mixed.
+    #[wasm_bindgen]
+    pub fn set_delay_samples(&mut self, delay_samples: i32) {
+        self.delay_samples = delay_samples;
+    }
+
+    /// Declare the sample rate of the track's samples so the mixer can
+    /// convert them to its own rate when the track is added; without it the
+    /// samples are assumed to be at the mixer's rate
+    #[wasm_bindgen]
+    pub fn set_sample_rate(&mut self, sample_rate: u32) -> Result<(), JsValue> {
+        self.sample_rate = Some(checked_sample_rate(sample_rate)?);
+        Ok(())
+    }
+
+    /// Repeat the samples back to back until timeline frame
+    /// `loop_to_sample` (exclusive), e.g. to fill a duration with a music
+    /// bed, cutting the last repeat short; a loop point before the natural
+    /// end shortens the track. `crossfade_samples` frames at each loop
+    /// point overlap with a linear crossfade to hide the seam, shortening
+    /// every repeat by that much. Fades, automation and the filter run over
+    /// the looped track as a whole.
+    #[wasm_bindgen]
+    pub fn set_loop(&mut self, loop_to_sample: usize, crossfade_samples: usize) {
+        self.loop_to_sample = Some(loop_to_sample);
+        self.loop_crossfade = crossfade_samples;
+    }
+
+    /// Play the samples once again
+    #[wasm_bindgen]
+    pub fn clear_loop(&mut self) {
+        self.loop_to_sample = None;
+        self.loop_crossfade = 0;
+    }
+
+    /// Mute and unmute the track while it plays: the mute state toggles at
+    /// each of `offsets`, frames from the track start counted like
+    /// automation offsets, starting unmuted. Each toggle ramps over the
+    /// mixer's mute ramp instead of jumping, so it does not click.
+    #[wasm_bindgen]
+    pub fn set_mute_changes(&mut self, offsets: &Uint32Array) {
+        let mut changes: Vec<usize> = offsets.to_vec().into_iter().map(|o| o as usize).collect();
+        changes.sort_unstable();
+        self.mute_changes = changes;
+    }
+
+    #[wasm_bindgen]
+    pub fn clear_mute_changes(&mut self) {
+        self.mute_changes.clear();
+    }
+
+    /// Assign the track to the named stem of AudioMixer.mix_stems, e.g.
+    /// "dialogue"; an empty name returns it to the default stem
+    #[wasm_bindgen]
+    pub fn set_group(&mut self, group: &str) {
+        self.group = Some(group.to_string()).filter(|g| !g.is_empty());
+    }
+
+    /// Select the fade shape: "linear" (the default), "equal_power",
+    /// "exponential", "logarithmic" or "s_curve"
+    #[wasm_bindgen]
+    pub fn set_fade_curve(&mut self, curve: &str) -> Result<(), JsValue> {
+        self.fades.curve = fade_curve(curve)?;
+        Ok(())
+    }
+}
+
+impl AudioTrack {
+    /// Timeline frame of the first trimmed sample, before 0 when a
+    /// negative delay moves it there
+    fn position(&self) -> i64 {
+        self.start_sample as i64 + self.delay_samples as i64
+    }
+
+    /// The samples left after trimming
+    fn trimmed(&self) -> &[f32] {
+        let skip = self.trim_start.saturating_mul(self.layout.channels());
+        &self.samples[skip.min(self.samples.len())..]
+    }
+
+    /// Frames the track plays for: the trimmed samples, or up to the loop
+    /// point when looping
+    fn frame_count(&self) -> usize {
+        match self.loop_to_sample {
+            Some(end) => (end as i64 - self.position()).max(0) as usize,
+            None => self.trimmed().len() / self.layout.channels(),
+        }
+    }
+
+    /// The trimmed samples, repeated up to the loop point but no further
+    /// than timeline frame `end` when looping
+    fn played(&self, end: usize) -> Cow<'_, [f32]> {
+        let channels = self.layout.channels();
+        match self.loop_to_sample {
+            Some(_) => {
+                let end = (end as i64 - self.position()).max(0) as usize;
+                let frames = self.frame_count().min(end);
+                Cow::Owned(envelope::repeat(
+                    self.trimmed(),
+                    channels,
+                    frames,
+                    self.loop_crossfade,
+                ))
+            }
+            None => Cow::Borrowed(self.trimmed()),
+        }
+    }
+
+    fn from_samples(
+        samples: Vec<f32>,
+        layout: ChannelLayout,
+        gain: f32,
+        pan: f32,
+        start_sample: usize,
+    ) -> Result<Self, MixerError> {
+        if samples.is_empty() {
+            return Err(MixerError::EmptyBuffer);
         }
+        Ok(Self {
+            samples,
+            layout,
+            gain: checked_gain(gain)?,
+            pan: checked_pan(pan)?,
+            start_sample,
+            trim_start: 0,
+            delay_samples: 0,
+            invert_polarity: false,
+            matrix: None,
+            fades: Fades::default(),
+            gain_automation: None,
+            pan_automation: None,
+            muted: false,
+            soloed: false,
+            filter: None,
+            sample_rate: None,
+            loop_to_sample: None,
+            loop_crossfade: 0,
+            mute_changes: Vec::new(),
+            group: None,
+            sidechain: None,
+        })
     }
 }
 
-/// Audio Mixer for combining multiple audio tracks
+/// Samples allocated in WASM memory for JS to fill in place, so large
+/// tracks cross the boundary once instead of being copied from a
+/// Float32Array. Fill it through
+/// `new Float32Array(memory.buffer, buffer.ptr, buffer.length)`; the view
+/// must be recreated after anything that may grow the WASM memory.
 #[wasm_bindgen]
-pub struct AudioMixer {
-    tracks: Vec<AudioTrack>,
-    sample_rate: u32,
-    channels: u32,
+pub struct SampleBuffer {
+    samples: Vec<f32>,
 }
 
 #[wasm_bindgen]
-impl AudioMixer {
+impl SampleBuffer {
+    /// Allocate `length` samples, initialized to silence
     #[wasm_bindgen(constructor)]
-    pub fn new(sample_rate: u32, channels: u32) -> Self {
+    pub fn new(length: usize) -> Self {
         Self {
-            tracks: Vec::new(),
-            sample_rate,
-            channels,
+            samples: vec![0.0; length],
         }
     }
 
-    /// Add a track to the mixer
+    /// Address of the first sample in WASM memory
+    #[wasm_bindgen(getter)]
+    pub fn ptr(&mut self) -> *mut f32 {
+        self.samples.as_mut_ptr()
+    }
+
+    /// Number of samples
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.samples.len()
+    }
+}
+
+/// Multi-band parametric EQ, for tracks or the mix bus
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct Equalizer {
+    /// Applied in order
+    bands: Vec<FilterSpec>,
+}
+
+#[wasm_bindgen]
+impl Equalizer {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a band: "peaking", "lowshelf" or "highshelf" with a boost or
+    /// cut of `gain_db`, or one of the pass filters accepted by
+    /// AudioTrack.set_filter, which ignore the gain
     #[wasm_bindgen]
-    pub fn add_track(&mut self, track: AudioTrack) {
-        self.tracks.push(track);
+    pub fn add_band(
+        &mut self,
+        kind: &str,
+        frequency: f32,
+        gain_db: f32,
+        q: f32,
+    ) -> Result<(), JsValue> {
+        self.bands.push(FilterSpec {
+            kind: band_kind(kind)?,
+            cutoff: frequency,
+            q,
+            gain_db,
+        });
+        Ok(())
+    }
+
+    /// Remove the band at `index`; false when there is no such band
+    #[wasm_bindgen]
+    pub fn remove_band(&mut self, index: usize) -> bool {
+        if index >= self.bands.len() {
+            return false;
+        }
+        self.bands.remove(index);
+        true
     }
 
-    /// Clear all tracks
     #[wasm_bindgen]
     pub fn clear(&mut self) {
-        self.tracks.clear();
+        self.bands.clear();
     }
 
-    /// Mix all tracks and return interleaved stereo output
+    #[wasm_bindgen(getter)]
+    pub fn band_count(&self) -> usize {
+        self.bands.len()
+    }
+
+    /// Filter an interleaved buffer through every band in order; throws
+    /// on a zero sample rate or a channel count outside 1 to MAX_CHANNELS
     #[wasm_bindgen]
-    pub fn mix(&self, duration_samples: usize) -> Float32Array {
-        let output_len = duration_samples * self.channels as usize;
-        let mut output = vec![0.0f32; output_len];
+    pub fn process(
+        &self,
+        samples: &Float32Array,
+        sample_rate: u32,
+        channels: u32,
+    ) -> Result<Float32Array, JsValue> {
+        let channels = checked_channels(channels)?;
+        let mut output = samples.to_vec();
+        filter::process_chain(
+            &self.bands,
+            &mut output,
+            channels,
+            checked_sample_rate(sample_rate)?,
+        );
+        Ok(Float32Array::from(&output[..]))
+    }
 
-        for track in &self.tracks {
-            let track_start = track.start_sample * self.channels as usize;
-            
-            for (i, &sample) in track.samples.iter().enumerate() {
-                let output_idx = track_start + i;
-                if output_idx >= output_len {
-                    break;
-                }
+    /// The bands as an array of `{ type, frequency, gainDb, q }` objects,
+    /// for saving presets
+    #[wasm_bindgen]
+    pub fn to_bands(&self) -> js_sys::Array {
+        self.bands
+            .iter()
+            .map(|band| {
+                let object = js_sys::Object::new();
+                let set = |key: &str, value: JsValue| {
+                    js_sys::Reflect::set(&object, &key.into(), &value).ok();
+                };
+                set("type", band.kind.name().into());
+                set("frequency", band.cutoff.into());
+                set("gainDb", band.gain_db.into());
+                set("q", band.q.into());
+                JsValue::from(object)
+            })
+            .collect()
+    }
 
-                // Apply gain
-                let gained_sample = sample * track.gain;
-
-                if self.channels == 2 {
-                    // Stereo panning
-                    let left_gain = ((1.0 - track.pan) / 2.0).sqrt();
-                    let right_gain = ((1.0 + track.pan) / 2.0).sqrt();
-                    
-                    let stereo_idx = (track.start_sample + i / 2) * 2;
-                    if stereo_idx + 1 < output_len {
-                        if i % 2 == 0 {
-                            output[stereo_idx] += gained_sample * left_gain;
-                        } else {
-                            output[stereo_idx + 1] += gained_sample * right_gain;
-                        }
-                    }
-                } else {
-                    output[output_idx] += gained_sample;
+    /// Rebuild an EQ from the output of to_bands
+    #[wasm_bindgen]
+    pub fn from_bands(bands: &js_sys::Array) -> Result<Equalizer, JsValue> {
+        let mut equalizer = Self::new();
+        for (index, band) in bands.iter().enumerate() {
+            let index = index as u32;
+            let get = |key: &str| js_sys::Reflect::get(&band, &key.into()).ok();
+            let number = |key: &str| get(key).and_then(|value| value.as_f64());
+            let kind = get("type").and_then(|value| value.as_string()).ok_or(
+                MixerError::InvalidEqBand {
+                    index,
+                    reason: "missing type",
+                },
+            )?;
+            let (Some(frequency), Some(gain_db), Some(q)) =
+                (number("frequency"), number("gainDb"), number("q"))
+            else {
+                return Err(MixerError::InvalidEqBand {
+                    index,
+                    reason: "frequency, gainDb and q must be numbers",
                 }
-            }
+                .into());
+            };
+            equalizer.add_band(&kind, frequency as f32, gain_db as f32, q as f32)?;
         }
+        Ok(equalizer)
+    }
+}
 
-        // Normalize to prevent clipping
-        let max_sample = output.iter().map(|s| s.abs()).fold(0.0f32, f32::max);
-        if max_sample > 1.0 {
-            for sample in &mut output {
-                *sample /= max_sample;
-            }
-        }
+/// Frames mixed per block by AudioMixer.render
+const RENDER_BLOCK_FRAMES: usize = 65536;
 
-        Float32Array::from(&output[..])
+/// Stem of mix_stems that holds the tracks without a group
+const DEFAULT_STEM: &str = "default";
+
+/// Length of the ramps added at track edges when declicking is enabled
+const DECLICK_SECONDS: f32 = 0.003;
+/// Default length of the ramp applied when a track's mute state changes
+const DEFAULT_MUTE_RAMP_SECONDS: f32 = 0.005;
+
+/// Result of AudioMixer.normalize_to_lufs
+#[wasm_bindgen]
+pub struct LoudnessNormalization {
+    samples: Vec<f32>,
+    input_lufs: f32,
+    gain_db: f32,
+    peak_limited: bool,
+}
+
+#[wasm_bindgen]
+impl LoudnessNormalization {
+    /// The normalized samples
+    #[wasm_bindgen(getter)]
+    pub fn samples(&self) -> Float32Array {
+        Float32Array::from(&self.samples[..])
     }
 
-    /// Apply gain to a single buffer (utility function)
+    /// Integrated loudness of the input in LUFS
+    #[wasm_bindgen(getter)]
+    pub fn input_lufs(&self) -> f32 {
+        self.input_lufs
+    }
+
+    /// Gain that was applied, in dB
+    #[wasm_bindgen(getter)]
+    pub fn gain_db(&self) -> f32 {
+        self.gain_db
+    }
+
+    /// Whether the gain was reduced to keep the true peak under the
+    /// ceiling, leaving the output quieter than the target
+    #[wasm_bindgen(getter)]
+    pub fn peak_limited(&self) -> bool {
+        self.peak_limited
+    }
+}
+
+/// What the output stage did to the last mix, from AudioMixer.last_mix_report
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MixReport {
+    peak: f32,
+    scale: f32,
+    clipped: bool,
+}
+
+#[wasm_bindgen]
+impl MixReport {
+    /// Peak absolute sample of the summed tracks before the output stage
+    #[wasm_bindgen(getter)]
+    pub fn peak(&self) -> f32 {
+        self.peak
+    }
+
+    /// Factor the whole mix was scaled by; below 1.0 only when the
+    /// "normalize" output mode brought a peak above full scale down
+    #[wasm_bindgen(getter)]
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Whether the output stage changed the mix, by normalizing or clipping
+    #[wasm_bindgen(getter)]
+    pub fn clipped(&self) -> bool {
+        self.clipped
+    }
+}
+
+/// Result of AudioMixer.mix_detailed: the mix with its output stage report
+#[wasm_bindgen]
+pub struct MixResult {
+    samples: Vec<f32>,
+    frames: usize,
+    report: MixReport,
+}
+
+#[wasm_bindgen]
+impl MixResult {
+    /// The interleaved mix, as returned by mix
+    #[wasm_bindgen(getter)]
+    pub fn samples(&self) -> Float32Array {
+        Float32Array::from(&self.samples[..])
+    }
+
+    /// Length of the mix in frames
+    #[wasm_bindgen(getter)]
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Peak absolute sample of the summed tracks before the output stage
+    #[wasm_bindgen(getter)]
+    pub fn peak(&self) -> f32 {
+        self.report.peak
+    }
+
+    /// Whether the output stage changed the mix, by normalizing or clipping
+    #[wasm_bindgen(getter)]
+    pub fn clipped(&self) -> bool {
+        self.report.clipped
+    }
+
+    /// Gain the "normalize" output mode scaled the mix by; 1.0 when the
+    /// peak was within full scale or another mode is selected
+    #[wasm_bindgen(getter)]
+    pub fn normalization_gain(&self) -> f32 {
+        self.report.scale
+    }
+}
+
+/// Result of AudioMixer.decode_wav
+#[wasm_bindgen]
+pub struct DecodedAudio {
+    samples: Vec<f32>,
+    sample_rate: u32,
+    channels: u32,
+}
+
+#[wasm_bindgen]
+impl DecodedAudio {
+    /// Interleaved samples, ready for AudioTrack.with_channels
+    #[wasm_bindgen(getter)]
+    pub fn samples(&self) -> Float32Array {
+        Float32Array::from(&self.samples[..])
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+}
+
+/// Result of AudioMixer.apply_compressor
+#[wasm_bindgen]
+pub struct Compression {
+    samples: Vec<f32>,
+    gain_reduction_db: Vec<f32>,
+}
+
+#[wasm_bindgen]
+impl Compression {
+    /// The compressed samples, makeup gain included
+    #[wasm_bindgen(getter)]
+    pub fn samples(&self) -> Float32Array {
+        Float32Array::from(&self.samples[..])
+    }
+
+    /// Gain reduction applied to each sample in dB (0 or positive), before
+    /// makeup gain, for a gain reduction meter. With a `mix` below 1.0 it
+    /// is the reduction of the compressed signal before blending.
+    #[wasm_bindgen(getter)]
+    pub fn gain_reduction_db(&self) -> Float32Array {
+        Float32Array::from(&self.gain_reduction_db[..])
+    }
+
+    /// Largest gain reduction in dB, for a meter
+    #[wasm_bindgen(getter)]
+    pub fn max_gain_reduction_db(&self) -> f32 {
+        self.gain_reduction_db.iter().copied().fold(0.0, f32::max)
+    }
+}
+
+/// Audio Mixer for combining multiple audio tracks
+#[wasm_bindgen]
+pub struct AudioMixer {
+    tracks: Vec<AudioTrack>,
+    sample_rate: u32,
+    layout: ChannelLayout,
+    pan_law: PanLaw,
+    output_mode: OutputMode,
+    auto_resample: bool,
+    /// Ramp every track in and out over DECLICK_SECONDS
+    declick: bool,
+    /// Length of the ramp at each of a track's mute changes
+    mute_ramp_seconds: f32,
+    /// Master fader applied to the summed tracks before the output stage
+    master_gain: f32,
+    /// Master balance, -1.0 (left) to 1.0 (right)
+    master_balance: f32,
+    /// Linear level normalization scales to and hard clipping clamps to
+    output_ceiling: f32,
+    /// Collect the clipping frames of each mix into last_clip_regions
+    detect_clips: bool,
+    last_mix: Option<MixReport>,
+    /// Flat `[start, end)` frame pairs where the last mix exceeded full scale
+    last_clip_regions: Vec<u32>,
+}
+
+#[wasm_bindgen]
+impl AudioMixer {
+    /// Create a mixer producing `channels` interleaved output channels
+    /// (1 to 6) in the default layout for the count; throws when
+    /// `sample_rate` is 0
+    #[wasm_bindgen(constructor)]
+    pub fn new(sample_rate: u32, channels: u32) -> Result<AudioMixer, JsValue> {
+        Ok(Self::with_output_layout(
+            sample_rate,
+            ChannelLayout::from_channels(channels)?,
+        )?)
+    }
+
+    /// Create a mixer whose output is in a named channel layout, as
+    /// accepted by AudioTrack.with_layout. Tracks are routed speaker by
+    /// speaker into it, folding the ones it lacks into their neighbours.
     #[wasm_bindgen]
-    pub fn apply_gain(samples: &Float32Array, gain: f32) -> Float32Array {
-        let input = samples.to_vec();
-        let output: Vec<f32> = input.iter().map(|s| s * gain).collect();
-        Float32Array::from(&output[..])
+    pub fn with_layout(sample_rate: u32, layout: &str) -> Result<AudioMixer, JsValue> {
+        Ok(Self::with_output_layout(
+            sample_rate,
+            channel_layout(layout)?,
+        )?)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn channels(&self) -> u32 {
+        self.layout.channels() as u32
+    }
+
+    /// Name of the output channel layout
+    #[wasm_bindgen(getter)]
+    pub fn channel_layout(&self) -> String {
+        self.layout.name().to_string()
     }
 
-    /// Crossfade between two buffers
+    /// Select how mono tracks are panned: "equal_power" (the default, -3 dB
+    /// at center, alias "-3db"), "linear" (-6 dB at center, alias "-6db")
+    /// or "-4.5db". With `x = (1 - pan) / 2` for the left and
+    /// `x = (1 + pan) / 2` for the right channel the gains are `sqrt(x)`,
+    /// `x` and `x^0.75` respectively.
     #[wasm_bindgen]
-    pub fn crossfade(
-        buffer_a: &Float32Array,
-        buffer_b: &Float32Array,
-        fade_samples: usize,
-    ) -> Float32Array {
-        let a = buffer_a.to_vec();
-        let b = buffer_b.to_vec();
-        let total_len = a.len() + b.len() - fade_samples;
-        let mut output = vec![0.0f32; total_len];
-
-        // Copy first buffer
-        for (i, &sample) in a.iter().enumerate() {
-            if i < a.len() - fade_samples {
-                output[i] = sample;
-            } else {
-                // Fade out region
-                let fade_pos = i - (a.len() - fade_samples);
-                let fade_factor = 1.0 - (fade_pos as f32 / fade_samples as f32);
-                output[i] = sample * fade_factor;
-            }
-        }
+    pub fn set_pan_law(&mut self, law: &str) -> Result<(), JsValue> {
+        self.pan_law = pan_law(law)?;
+        Ok(())
+    }
 
-        // Mix in second buffer with fade in
-        for (i, &sample) in b.iter().enumerate() {
-            let output_idx = a.len() - fade_samples + i;
-            if i < fade_samples {
-                // Fade in region
-                let fade_factor = i as f32 / fade_samples as f32;
-                output[output_idx] += sample * fade_factor;
-            } else if output_idx < output.len() {
-                output[output_idx] = sample;
-            }
-        }
+    /// Select how a mix louder than full scale is brought back into range:
+    /// "normalize" (the default) scales the whole mix down by its peak,
+    /// "soft_clip" gently saturates only the samples above -2.5 dBFS,
+    /// "hard_clip" clamps to [-1, 1] and "none" leaves the sum untouched
+    #[wasm_bindgen]
+    pub fn set_output_mode(&mut self, mode: &str) -> Result<(), JsValue> {
+        self.output_mode = OutputMode::parse(mode).ok_or_else(|| MixerError::UnknownOption {
+            option: "output mode",
+            value: mode.to_string(),
+            expected: "\"normalize\", \"soft_clip\", \"hard_clip\" or \"none\"",
+        })?;
+        Ok(())
+    }
 
-        Float32Array::from(&output[..])
+    /// Turn peak normalization on (the default) or off. Off returns the
+    /// raw sum from mix, which may exceed [-1, 1], for limiting
+    /// downstream; the same as set_output_mode("none"), and turning it
+    /// back on is set_output_mode("normalize").
+    #[wasm_bindgen]
+    pub fn set_normalize(&mut self, enabled: bool) {
+        self.output_mode = if enabled {
+            OutputMode::Normalize
+        } else {
+            OutputMode::None
+        };
     }
-}
 
+    /// When enabled (the default), tracks whose declared sample rate
+    /// differs from the mixer's are converted to it when added, and
+    /// enabling it converts such tracks already added, so they play at the
+    /// right pitch. Fades and automation offsets of such tracks count
+    /// frames at the mixer's rate. Disabled, samples play at the mixer's
+    /// rate whatever their declared rate.
+    #[wasm_bindgen]
+    pub fn set_auto_resample(&mut self, enabled: bool) {
+        self.auto_resample = enabled;
+        let tracks = std::mem::take(&mut self.tracks);
+        self.tracks = tracks
+            .into_iter()
+            .map(|track| self.prepare_track(track))
+            .collect();
+    }
+
+    /// When enabled, every track fades in and out over at least 3 ms so
+    /// tracks that start or end on a non-zero sample do not click. Longer
+    /// fades set on a track are kept.
+    #[wasm_bindgen]
+    pub fn set_declick(&mut self, enabled: bool) {
+        self.declick = enabled;
+    }
+
+    /// Length in milliseconds of the ramp at each mute change set with
+    /// AudioTrack.set_mute_changes; 5 ms by default, 0 switches instantly.
+    /// The ramp spans `round(sample_rate * ms / 1000)` frames, so it keeps
+    /// its duration at any sample rate. Throws on a negative or NaN length.
+    #[wasm_bindgen]
+    pub fn set_mute_ramp_ms(&mut self, ms: f32) -> Result<(), JsValue> {
+        if !(ms.is_finite() && ms >= 0.0) {
+            return Err(MixerError::InvalidRampTime(ms).into());
+        }
+        self.mute_ramp_seconds = ms / 1000.0;
+        Ok(())
+    }
+
+    /// Level the output stage keeps the mix under, linear (e.g. 0.891 for
+    /// -1 dBFS): "normalize" scales a louder mix down to it and
+    /// "hard_clip" clamps to it, leaving headroom against inter-sample
+    /// peaks. Defaults to 1.0 (full scale); throws unless above 0.0 and at
+    /// most 1.0.
+    #[wasm_bindgen]
+    pub fn set_output_ceiling(&mut self, ceiling: f32) -> Result<(), JsValue> {
+        self.output_ceiling = checked_ceiling(ceiling)?;
+        Ok(())
+    }
+
+    /// Gain of the master bus, applied to the sum of all tracks before
+    /// normalization or clipping, so the mix report and clip regions
+    /// reflect it; throws on a non-finite gain
+    #[wasm_bindgen]
+    pub fn set_master_gain(&mut self, gain: f32) -> Result<(), JsValue> {
+        self.master_gain = checked_gain(gain)?;
+        Ok(())
+    }
+
+    /// Balance of the master bus, clamped to [-1.0, 1.0]; attenuates the
+    /// front left or right output channel, applied with the master gain.
+    /// Throws on NaN.
+    #[wasm_bindgen]
+    pub fn set_master_balance(&mut self, balance: f32) -> Result<(), JsValue> {
+        self.master_balance = checked_pan(balance)?;
+        Ok(())
+    }
+
+    /// When enabled, mix and render record where the summed tracks exceed
+    /// full scale before the output stage, read with last_clip_regions
+    #[wasm_bindgen]
+    pub fn set_clip_detection(&mut self, enabled: bool) {
+        self.detect_clips = enabled;
+    }
+
+    /// Add a track to the mixer
+    #[wasm_bindgen]
+    pub fn add_track(&mut self, track: AudioTrack) {
+        let track = self.prepare_track(track);
+        self.tracks.push(track);
+    }
+
+    /// Add many tracks in one call, e.g. when loading a project, instead
+    /// of constructing and adding each AudioTrack from JS. Each descriptor
+    /// is `{ samples, channels, gain, pan, start }` with `samples` an
+    /// interleaved Float32Array and the rest numbers as in
+    /// AudioTrack.with_channels; `channels` defaults to 1, `gain` to 1.0,
+    /// `pan` and `start` to 0. An optional `layout` names the channel
+    /// layout as AudioTrack.with_layout and takes precedence over
+    /// `channels`. An optional `sampleRate` declares the rate of the
+    /// samples as AudioTrack.set_sample_rate. Throws on the first
+    /// invalid descriptor, e.g. one with a negative `start`, before any
+    /// track is added.
+    #[wasm_bindgen]
+    pub fn add_tracks_bulk(&mut self, descriptors: &js_sys::Array) -> Result<(), JsValue> {
+        let mut tracks = Vec::with_capacity(descriptors.length() as usize);
+        for (index, descriptor) in descriptors.iter().enumerate() {
+            let index = index as u32;
+            let get = |key: &str| {
+                js_sys::Reflect::get(&descriptor, &key.into())
+                    .ok()
+                    .filter(|value| !value.is_undefined())
+            };
+            let samples = get("samples")
+                .and_then(|value| value.dyn_into::<Float32Array>().ok())
+                .ok_or(MixerError::InvalidTrackDescriptor {
+                    index,
+                    reason: "samples must be a Float32Array",
+                })?;
+            let number = |key: &str, default: f64| match get(key) {
+                Some(value) => value.as_f64(),
+                None => Some(default),
+            };
+            let (Some(channels), Some(gain), Some(pan), Some(start)) = (
+                number("channels", 1.0),
+                number("gain", 1.0),
+                number("pan", 0.0),
+                number("start", 0.0),
+            ) else {
+                return Err(MixerError::InvalidTrackDescriptor {
+                    index,
+                    reason: "channels, gain, pan and start must be numbers",
+                }
+                .into());
+            };
+            let layout = match get("layout") {
+                Some(name) => {
+                    let name = name.as_string().ok_or(MixerError::InvalidTrackDescriptor {
+                        index,
+                        reason: "layout must be a string",
+                    })?;
+                    channel_layout(&name)?
+                }
+                None => ChannelLayout::from_channels(channels as u32)?,
+            };
+            let mut track = descriptor_track(index, samples.to_vec(), layout, gain, pan, start)?;
+            if let Some(rate) = get("sampleRate") {
+                let rate = rate.as_f64().ok_or(MixerError::InvalidTrackDescriptor {
+                    index,
+                    reason: "sampleRate must be a number",
+                })?;
+                track.sample_rate = Some(checked_sample_rate(rate as u32)?);
+            }
+            tracks.push(track);
+        }
+        for track in tracks {
+            self.add_track(track);
+        }
+        Ok(())
+    }
+
+    /// Save the session to a versioned binary blob: the sample rate,
+    /// channels, pan law, output mode, declicking and master bus, and for
+    /// each track its samples, channels, gain, pan, position, trim, delay,
+    /// polarity, mute, solo, fade lengths, loop and declared sample rate.
+    /// Automation, mute changes, stem groups, sidechains, channel matrices,
+    /// filters, fade curves and the mute ramp are not saved.
+    /// Samples are stored uncompressed as 32-bit floats.
+    #[wasm_bindgen]
+    pub fn serialize(&self) -> Uint8Array {
+        Uint8Array::from(&project::encode(&self.state())[..])
+    }
+
+    /// Restore a mixer saved with serialize. Throws on data that is not a
+    /// saved session, is truncated, or comes from a newer format version.
+    #[wasm_bindgen]
+    pub fn deserialize(data: &Uint8Array) -> Result<AudioMixer, JsValue> {
+        Ok(Self::from_state(project::decode(&data.to_vec())?)?)
+    }
+
+    /// Remove the track at `index`; later tracks move down one index.
+    /// Returns false when there is no such track.
+    #[wasm_bindgen]
+    pub fn remove_track(&mut self, index: usize) -> bool {
+        if index >= self.tracks.len() {
+            return false;
+        }
+        self.tracks.remove(index);
+        // Sidechains keyed by the removed track go with it
+        for track in &mut self.tracks {
+            track.sidechain = track.sidechain.and_then(|mut sidechain| {
+                match sidechain.key.cmp(&index) {
+                    Ordering::Equal => return None,
+                    Ordering::Greater => sidechain.key -= 1,
+                    Ordering::Less => {}
+                }
+                Some(sidechain)
+            });
+        }
+        true
+    }
+
+    /// Put `track` in place of the one at `index`, converted like in
+    /// add_track. Returns false and drops `track` when there is no such
+    /// track.
+    #[wasm_bindgen]
+    pub fn replace_track(&mut self, index: usize, track: AudioTrack) -> bool {
+        if index >= self.tracks.len() {
+            return false;
+        }
+        self.tracks[index] = self.prepare_track(track);
+        true
+    }
+
+    /// Extend the track at `index` with interleaved `samples`, e.g. each
+    /// block captured while recording into it, without copying the samples
+    /// already there. The samples must be whole frames at the rate the
+    /// track plays at in the mixer; throws on an unknown index or a
+    /// partial frame.
+    #[wasm_bindgen]
+    pub fn append_to_track(&mut self, index: usize, samples: &Float32Array) -> Result<(), JsValue> {
+        self.append_samples(index, &samples.to_vec())?;
+        Ok(())
+    }
+
+    /// Clear all tracks
+    #[wasm_bindgen]
+    pub fn clear(&mut self) {
+        self.tracks.clear();
+    }
+
+    /// Number of tracks added, including muted ones
+    #[wasm_bindgen]
+    pub fn track_count(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// Shortest duration, in frames, that mix needs to include the end of
+    /// every track; 0 without tracks
+    #[wasm_bindgen]
+    pub fn required_duration_samples(&self) -> usize {
+        self.tracks
+            .iter()
+            .map(|track| (track.position() + track.frame_count() as i64).max(0) as usize)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Change the gain of the track at `index`; throws on an unknown index
+    /// or a non-finite gain
+    #[wasm_bindgen]
+    pub fn set_track_gain(&mut self, index: usize, gain: f32) -> Result<(), JsValue> {
+        let gain = checked_gain(gain)?;
+        self.track_mut(index)?.gain = gain;
+        Ok(())
+    }
+
+    /// Change the pan of the track at `index`, clamped to [-1.0, 1.0];
+    /// throws on an unknown index or a NaN pan
+    #[wasm_bindgen]
+    pub fn set_track_pan(&mut self, index: usize, pan: f32) -> Result<(), JsValue> {
+        let pan = checked_pan(pan)?;
+        self.track_mut(index)?.pan = pan;
+        Ok(())
+    }
+
+    /// Move the track at `index` to start at frame `start_sample`
+    #[wasm_bindgen]
+    pub fn set_track_start(&mut self, index: usize, start_sample: usize) -> Result<(), JsValue> {
+        self.track_mut(index)?.start_sample = start_sample;
+        Ok(())
+    }
+
+    /// Trim the first `frames` frames of the track at `index`; see
+    /// AudioTrack.set_trim_start
+    #[wasm_bindgen]
+    pub fn set_track_trim_start(&mut self, index: usize, frames: usize) -> Result<(), JsValue> {
+        self.track_mut(index)?.trim_start = frames;
+        Ok(())
+    }
+
+    /// Mute or unmute the track at `index` (in the order tracks were added)
+    #[wasm_bindgen]
+    pub fn set_track_muted(&mut self, index: usize, muted: bool) -> Result<(), JsValue> {
+        self.track_mut(index)?.muted = muted;
+        Ok(())
+    }
+
+    /// Solo or unsolo the track at `index`; soloing works in place, so the
+    /// soloed tracks keep their gain, pan and routing
+    #[wasm_bindgen]
+    pub fn set_track_soloed(&mut self, index: usize, soloed: bool) -> Result<(), JsValue> {
+        self.track_mut(index)?.soloed = soloed;
+        Ok(())
+    }
+
+    /// Duck the track at `target` by the level of the track at `key`, e.g.
+    /// music under a voiceover: whenever the key's peak level rises above
+    /// `threshold_db` (dBFS) the target's gain is reduced by `1 - 1 /
+    /// ratio` of the excess, following the attack and release times as in
+    /// apply_compressor. The key is measured as it leaves its fader, even
+    /// when muted. Replaces the target's previous sidechain; throws on an
+    /// unknown index or a track keyed by itself.
+    #[wasm_bindgen]
+    pub fn set_sidechain(
+        &mut self,
+        target: usize,
+        key: usize,
+        threshold_db: f32,
+        ratio: f32,
+        attack_ms: f32,
+        release_ms: f32,
+    ) -> Result<(), JsValue> {
+        let count = self.tracks.len();
+        if key >= count {
+            return Err(MixerError::TrackOutOfRange { index: key, count }.into());
+        }
+        if key == target {
+            return Err(MixerError::SelfSidechain(key).into());
+        }
+        self.track_mut(target)?.sidechain = Some(Sidechain {
+            key,
+            settings: Compressor {
+                threshold_db,
+                ratio,
+                attack_ms,
+                release_ms,
+                makeup_db: 0.0,
+            },
+        });
+        Ok(())
+    }
+
+    /// Stop ducking the track at `target`
+    #[wasm_bindgen]
+    pub fn clear_sidechain(&mut self, target: usize) -> Result<(), JsValue> {
+        self.track_mut(target)?.sidechain = None;
+        Ok(())
+    }
+
+    /// Assign the track at `index` to a stem as AudioTrack.set_group
+    #[wasm_bindgen]
+    pub fn set_track_group(&mut self, index: usize, group: &str) -> Result<(), JsValue> {
+        self.track_mut(index)?.set_group(group);
+        Ok(())
+    }
+
+    /// Mix all tracks and return interleaved output with the mixer's
+    /// channel count
+    #[wasm_bindgen]
+    pub fn mix(&mut self, duration_samples: usize) -> Float32Array {
+        Float32Array::from(&self.mix_samples(duration_samples)[..])
+    }
+
+    /// Mix like mix, returning the samples together with the peak before
+    /// the output stage, whether it clipped or normalized, and the
+    /// normalization gain; also updates last_mix_report
+    #[wasm_bindgen]
+    pub fn mix_detailed(&mut self, duration_samples: usize) -> MixResult {
+        self.mix_result(duration_samples)
+    }
+
+    /// Mix only the `length_samples` frames of the timeline starting at
+    /// frame `start_sample`, for streaming a long mix block by block.
+    /// Consecutive windows join seamlessly. The output stage runs per
+    /// window, so "normalize", which needs the peak of the whole mix,
+    /// hard-clips instead; the clipping modes behave as in mix. Filtered
+    /// tracks are processed from their start on every call. Does not
+    /// update last_mix_report.
+    #[wasm_bindgen]
+    pub fn mix_range(&self, start_sample: usize, length_samples: usize) -> Float32Array {
+        Float32Array::from(&self.mix_range_samples(start_sample, length_samples)[..])
+    }
+
+    /// Sum an already rendered `overlay` into a rendered `base` mix, both
+    /// interleaved with the mixer's channel count, starting `offset_samples`
+    /// frames into the base and scaled by `gain`. The base is extended with
+    /// silence when the overlay runs past its end, and the result goes
+    /// through the mixer's output stage like mix. Throws on a non-finite
+    /// gain.
+    #[wasm_bindgen]
+    pub fn overlay(
+        &self,
+        base: &Float32Array,
+        overlay: &Float32Array,
+        offset_samples: usize,
+        gain: f32,
+    ) -> Result<Float32Array, JsValue> {
+        let gain = checked_gain(gain)?;
+        let output = self.overlay_samples(base.to_vec(), &overlay.to_vec(), offset_samples, gain);
+        Ok(Float32Array::from(&output[..]))
+    }
+
+    /// Mix like mix, calling `progress_cb` with the finished fraction (0.0
+    /// to 1.0) after each block of about a second of audio so a progress bar
+    /// can be shown. The result is identical to mix.
+    #[wasm_bindgen]
+    pub fn render(
+        &mut self,
+        duration_samples: usize,
+        progress_cb: &js_sys::Function,
+    ) -> Float32Array {
+        let output = self.render_samples(duration_samples, |fraction| {
+            // Errors thrown by the callback do not stop the render
+            let _ = progress_cb.call1(&JsValue::NULL, &JsValue::from(fraction));
+        });
+        Float32Array::from(&output[..])
+    }
+
+    /// The track at `index` alone over the first `duration_samples` frames
+    /// of the timeline, interleaved with the mixer's channel count, e.g. to
+    /// export stems: placed at its start and processed as in mix (trim,
+    /// loop, filter, fades, automation, gain, pan and polarity), but without
+    /// the master bus or output stage. Mute and solo are ignored. Throws on
+    /// an unknown index.
+    #[wasm_bindgen]
+    pub fn render_track(
+        &self,
+        index: usize,
+        duration_samples: usize,
+    ) -> Result<Float32Array, JsValue> {
+        Ok(Float32Array::from(&self.stem(index, duration_samples)?[..]))
+    }
+
+    /// Mix each group of tracks into its own stem, e.g. dialogue, music and
+    /// effects deliverables: a Map from group name to interleaved samples
+    /// with the mixer's channel count, in the order the groups first appear
+    /// among the tracks. Tracks without a group go to the "default" stem.
+    /// Mute, solo and the master bus apply as in mix, so the stems add up
+    /// to the mix before its output stage, which is not applied: stems may
+    /// exceed full scale.
+    #[wasm_bindgen]
+    pub fn mix_stems(&self, duration_samples: usize) -> js_sys::Map {
+        let stems = js_sys::Map::new();
+        for (group, samples) in self.stem_groups(duration_samples) {
+            stems.set(&group.into(), &Float32Array::from(&samples[..]));
+        }
+        stems
+    }
+
+    /// Levels of each track over the first `duration_samples` frames of the
+    /// timeline, as it reaches the master bus: after trim, filter, fades,
+    /// automation, gain and pan, before the master fader and output stage.
+    /// Returns linear `peak, rms` pairs, one pair per track in add order;
+    /// muted tracks, and unsoloed ones while any track is soloed, read 0.
+    #[wasm_bindgen]
+    pub fn track_levels(&self, duration_samples: usize) -> Float32Array {
+        Float32Array::from(&self.track_level_pairs(duration_samples)[..])
+    }
+
+    /// Peak and normalization/clipping details of the last call to mix,
+    /// or undefined before the first mix
+    #[wasm_bindgen]
+    pub fn last_mix_report(&self) -> Option<MixReport> {
+        self.last_mix
+    }
+
+    /// Frames where the last mix exceeded full scale on any channel, as
+    /// flat `[start, end)` pairs in timeline order, end exclusive. Empty
+    /// when clip detection is off or nothing clipped.
+    #[wasm_bindgen]
+    pub fn last_clip_regions(&self) -> Uint32Array {
+        Uint32Array::from(&self.last_clip_regions[..])
+    }
+
+    /// Apply gain to a single buffer (utility function)
+    #[wasm_bindgen]
+    pub fn apply_gain(samples: &Float32Array, gain: f32) -> Float32Array {
+        let mut output = samples.to_vec();
+        simd::scale(&mut output, gain);
+        Float32Array::from(&output[..])
+    }
+
+    /// Low-pass filter a mono buffer (utility function)
+    #[wasm_bindgen]
+    pub fn apply_lowpass(
+        samples: &Float32Array,
+        cutoff: f32,
+        q: f32,
+        sample_rate: u32,
+    ) -> Result<Float32Array, JsValue> {
+        let spec = FilterSpec {
+            kind: FilterKind::Lowpass,
+            cutoff,
+            q,
+            gain_db: 0.0,
+        };
+        let mut output = samples.to_vec();
+        Biquad::new(spec, checked_sample_rate(sample_rate)?).process(&mut output, 1);
+        Ok(Float32Array::from(&output[..]))
+    }
+
+    /// Filter a mono buffer with any filter kind accepted by
+    /// AudioTrack.set_filter (utility function)
+    #[wasm_bindgen]
+    pub fn apply_filter(
+        samples: &Float32Array,
+        kind: &str,
+        cutoff: f32,
+        q: f32,
+        sample_rate: u32,
+    ) -> Result<Float32Array, JsValue> {
+        let spec = FilterSpec {
+            kind: filter_kind(kind)?,
+            cutoff,
+            q,
+            gain_db: 0.0,
+        };
+        let mut output = samples.to_vec();
+        Biquad::new(spec, checked_sample_rate(sample_rate)?).process(&mut output, 1);
+        Ok(Float32Array::from(&output[..]))
+    }
+
+    /// Integrated loudness of an interleaved buffer in LUFS (ITU-R BS.1770
+    /// K-weighting with absolute and relative gating). Returns -Infinity for
+    /// silence or buffers shorter than 400 ms. Throws on a zero sample rate
+    /// or a channel count outside 1 to MAX_CHANNELS.
+    #[wasm_bindgen]
+    pub fn measure_loudness(
+        samples: &Float32Array,
+        channels: u32,
+        sample_rate: u32,
+    ) -> Result<f32, JsValue> {
+        let channels = checked_channels(channels)?;
+        let sample_rate = checked_sample_rate(sample_rate)?;
+        Ok(loudness::integrated_loudness(&samples.to_vec(), channels, sample_rate) as f32)
+    }
+
+    /// Apply a single gain to an interleaved buffer so its integrated
+    /// loudness hits `target_lufs` (e.g. -16 for podcasts, -23 for
+    /// broadcast). The gain is reduced if the true peak would exceed
+    /// `ceiling_dbfs` (0 dBFS when omitted). Silent input is returned
+    /// unchanged; throws as measure_loudness does.
+    #[wasm_bindgen]
+    pub fn normalize_to_lufs(
+        samples: &Float32Array,
+        channels: u32,
+        sample_rate: u32,
+        target_lufs: f32,
+        ceiling_dbfs: Option<f32>,
+    ) -> Result<LoudnessNormalization, JsValue> {
+        let channels = checked_channels(channels)?;
+        Ok(normalize_to_lufs(
+            samples.to_vec(),
+            channels,
+            checked_sample_rate(sample_rate)?,
+            target_lufs,
+            ceiling_dbfs.unwrap_or(0.0),
+        ))
+    }
+
+    /// Peak amplitude of each channel of an interleaved buffer, one value
+    /// per channel; throws on a channel count outside 1 to MAX_CHANNELS
+    #[wasm_bindgen]
+    pub fn peak_levels(samples: &Float32Array, channels: u32) -> Result<Float32Array, JsValue> {
+        let channels = checked_channels(channels)?;
+        let peaks = loudness::peak_levels(&samples.to_vec(), channels);
+        Ok(Float32Array::from(&peaks[..]))
+    }
+
+    /// Min/max peaks for drawing a waveform: for every `samples_per_pixel`
+    /// frames, one column of `min, max` pairs, one pair per channel in
+    /// channel order. The last column covers the remaining frames, so
+    /// there are `ceil(frames / samples_per_pixel)` columns. Throws on a
+    /// channel count outside 1 to MAX_CHANNELS.
+    #[wasm_bindgen]
+    pub fn compute_waveform_peaks(
+        samples: &Float32Array,
+        channels: u32,
+        samples_per_pixel: usize,
+    ) -> Result<Float32Array, JsValue> {
+        let channels = checked_channels(channels)?;
+        let peaks = loudness::waveform_peaks(&samples.to_vec(), channels, samples_per_pixel);
+        Ok(Float32Array::from(&peaks[..]))
+    }
+
+    /// Like peak_levels, but oversampled 4x so peaks between samples are
+    /// caught; values above 1.0 mean the signal clips once reconstructed
+    #[wasm_bindgen]
+    pub fn true_peak_levels(
+        samples: &Float32Array,
+        channels: u32,
+    ) -> Result<Float32Array, JsValue> {
+        let channels = checked_channels(channels)?;
+        let peaks = loudness::true_peak_levels(&samples.to_vec(), channels);
+        Ok(Float32Array::from(&peaks[..]))
+    }
+
+    /// Unweighted RMS level of a buffer in dBFS, -Infinity for silence
+    #[wasm_bindgen]
+    pub fn measure_rms(samples: &Float32Array) -> f32 {
+        loudness::rms_dbfs(&samples.to_vec()) as f32
+    }
+
+    /// Convert an interleaved buffer between sample rates with a Lanczos
+    /// (windowed-sinc) resampler, keeping the channel count. The output has
+    /// `round(frames * to_rate / from_rate)` frames. Throws on a rate of 0
+    /// or a channel count outside 1 to MAX_CHANNELS.
+    #[wasm_bindgen]
+    pub fn resample(
+        samples: &Float32Array,
+        from_rate: u32,
+        to_rate: u32,
+        channels: u32,
+    ) -> Result<Float32Array, JsValue> {
+        let channels = checked_channels(channels)?;
+        let from_rate = checked_sample_rate(from_rate)?;
+        let to_rate = checked_sample_rate(to_rate)?;
+        let output = resample::resample(&samples.to_vec(), channels, from_rate, to_rate);
+        Ok(Float32Array::from(&output[..]))
+    }
+
+    /// Change the duration of an interleaved buffer by `factor` (2.0 is
+    /// twice as long, clamped to 0.25 to 4) keeping the pitch, with
+    /// waveform-similarity overlap-add (WSOLA). Throws on a zero sample
+    /// rate or a channel count outside 1 to MAX_CHANNELS.
+    #[wasm_bindgen]
+    pub fn time_stretch(
+        samples: &Float32Array,
+        factor: f32,
+        sample_rate: u32,
+        channels: u32,
+    ) -> Result<Float32Array, JsValue> {
+        let channels = checked_channels(channels)?;
+        let output = stretch::time_stretch(
+            &samples.to_vec(),
+            channels,
+            checked_sample_rate(sample_rate)?,
+            factor,
+        );
+        Ok(Float32Array::from(&output[..]))
+    }
+
+    /// Shift the pitch of an interleaved buffer by `semitones` (clamped to
+    /// +-24) keeping its duration; throws as time_stretch does
+    #[wasm_bindgen]
+    pub fn pitch_shift(
+        samples: &Float32Array,
+        semitones: f32,
+        sample_rate: u32,
+        channels: u32,
+    ) -> Result<Float32Array, JsValue> {
+        let channels = checked_channels(channels)?;
+        let output = stretch::pitch_shift(
+            &samples.to_vec(),
+            channels,
+            checked_sample_rate(sample_rate)?,
+            semitones,
+        );
+        Ok(Float32Array::from(&output[..]))
+    }
+
+    /// Magnitude spectrum of the first `fft_size` samples of a mono buffer
+    /// (zero-padded when shorter) after a "hann", "hamming" or "blackman"
+    /// window: `fft_size / 2 + 1` bins in dBFS from 0 Hz to Nyquist, bin `k`
+    /// at `k * sampleRate / fft_size` Hz, floored at -120 dB. A full-scale
+    /// sine centered on a bin reads 0 dB. Throws when `fft_size` is not a
+    /// power of two or the window is unknown.
+    #[wasm_bindgen]
+    pub fn spectrum(
+        samples: &Float32Array,
+        fft_size: usize,
+        window: &str,
+    ) -> Result<Float32Array, JsValue> {
+        if fft_size < 2 || !fft_size.is_power_of_two() {
+            return Err(MixerError::InvalidFftSize(fft_size).into());
+        }
+        let window = Window::parse(window).ok_or_else(|| MixerError::UnknownOption {
+            option: "window",
+            value: window.to_string(),
+            expected: "\"hann\", \"hamming\" or \"blackman\"",
+        })?;
+        let bins = fft::spectrum(&samples.to_vec(), fft_size, window);
+        Ok(Float32Array::from(&bins[..]))
+    }
+
+    /// Convolve every channel of interleaved `samples` with the mono
+    /// `impulse_response`, e.g. a recorded room for convolution reverb,
+    /// using FFT overlap-add. The result includes the tail:
+    /// `impulse_response.length - 1` frames longer than the input. `mix`
+    /// (0.0 dry to 1.0 wet) blends it with the input as blend does, fully
+    /// wet when omitted. Throws on an empty impulse response or a channel
+    /// count outside 1 to MAX_CHANNELS.
+    #[wasm_bindgen]
+    pub fn apply_convolution(
+        samples: &Float32Array,
+        impulse_response: &Float32Array,
+        channels: u32,
+        mix: Option<f32>,
+    ) -> Result<Float32Array, JsValue> {
+        let impulse_response = impulse_response.to_vec();
+        if impulse_response.is_empty() {
+            return Err(MixerError::EmptyBuffer.into());
+        }
+        let channels = checked_channels(channels)?;
+        let samples = samples.to_vec();
+        let wet = convolve::convolve(&samples, channels, &impulse_response);
+        let output = match mix {
+            Some(mix) => effects::blend(&samples, &wet, mix),
+            None => wet,
+        };
+        Ok(Float32Array::from(&output[..]))
+    }
+
+    /// Feedback delay (echo) on a mono buffer. `feedback` is clamped to
+    /// +-0.95 and `mix` (0.0 dry to 1.0 wet) blends the echoes with the
+    /// input. The output is extended so the tail decays below -60 dB:
+    /// `length + delay_samples * (1 + ceil(ln(0.001) / ln(|feedback|)))`
+    /// samples, or a single echo when feedback is 0; see
+    /// delay_output_length.
+    #[wasm_bindgen]
+    pub fn apply_delay(
+        samples: &Float32Array,
+        delay_samples: usize,
+        feedback: f32,
+        mix: f32,
+    ) -> Float32Array {
+        let output = effects::delay(&samples.to_vec(), delay_samples, feedback, mix);
+        Float32Array::from(&output[..])
+    }
+
+    /// Noise gate on a mono buffer: audio whose envelope stays below
+    /// `threshold_db` (dBFS) is faded out over `release_samples` and faded
+    /// back in over `attack_samples` once it rises above. The envelope
+    /// follower also decays over `release_samples`, so word endings are
+    /// not cut off.
+    #[wasm_bindgen]
+    pub fn apply_gate(
+        samples: &Float32Array,
+        threshold_db: f32,
+        attack_samples: usize,
+        release_samples: usize,
+    ) -> Float32Array {
+        let output = dynamics::gate(
+            &samples.to_vec(),
+            threshold_db,
+            attack_samples,
+            release_samples,
+        );
+        Float32Array::from(&output[..])
+    }
+
+    /// Cut the leading and trailing silence from interleaved `samples`:
+    /// everything before the first and after the last frame with a sample
+    /// at or above `threshold_db` (dBFS). Returns an empty buffer when all
+    /// of it is below the threshold; throws on a channel count outside 1
+    /// to MAX_CHANNELS.
+    #[wasm_bindgen]
+    pub fn trim_silence(
+        samples: &Float32Array,
+        threshold_db: f32,
+        channels: u32,
+    ) -> Result<Float32Array, JsValue> {
+        let channels = checked_channels(channels)?;
+        let samples = samples.to_vec();
+        Ok(
+            match dynamics::sound_span(&samples, channels, threshold_db) {
+                Some((start, end)) => {
+                    Float32Array::from(&samples[start * channels..end * channels])
+                }
+                None => Float32Array::new_with_length(0),
+            },
+        )
+    }
+
+    /// Silent gaps inside interleaved `samples` as flat `[start, end)`
+    /// frame pairs, leaving out leading and trailing silence. A gap starts
+    /// once peaks fall 3 dB below `threshold_db` (dBFS) and ends at the
+    /// next frame reaching it; gaps shorter than `min_frames` are skipped.
+    /// Throws on a channel count outside 1 to MAX_CHANNELS, as
+    /// trim_silence does.
+    #[wasm_bindgen]
+    pub fn detect_silence_regions(
+        samples: &Float32Array,
+        threshold_db: f32,
+        channels: u32,
+        min_frames: u32,
+    ) -> Result<Uint32Array, JsValue> {
+        let channels = checked_channels(channels)?;
+        let regions = dynamics::silence_regions(
+            &samples.to_vec(),
+            channels,
+            threshold_db,
+            min_frames as usize,
+        );
+        Ok(Uint32Array::from(&regions[..]))
+    }
+
+    /// Feed-forward compressor on a mono buffer. Peaks above
+    /// `threshold_db` (dBFS) are reduced by the `ratio` (e.g. 4 for 4:1),
+    /// with the gain reduction following the attack and release times,
+    /// then `makeup_db` is added. `mix` (0.0 dry to 1.0 wet) blends the
+    /// compressed signal with the input for parallel compression; fully
+    /// wet when omitted.
+    #[wasm_bindgen]
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply_compressor(
+        samples: &Float32Array,
+        threshold_db: f32,
+        ratio: f32,
+        attack_ms: f32,
+        release_ms: f32,
+        makeup_db: f32,
+        sample_rate: u32,
+        mix: Option<f32>,
+    ) -> Result<Compression, JsValue> {
+        let settings = Compressor {
+            threshold_db,
+            ratio,
+            attack_ms,
+            release_ms,
+            makeup_db,
+        };
+        let dry = samples.to_vec();
+        let (mut samples, gain_reduction_db) =
+            dynamics::compress(&dry, settings, checked_sample_rate(sample_rate)?);
+        if let Some(mix) = mix {
+            samples = effects::blend(&dry, &samples, mix);
+        }
+        Ok(Compression {
+            samples,
+            gain_reduction_db,
+        })
+    }
+
+    /// Lookahead peak limiter, the last stage of a mastering chain: keeps
+    /// interleaved `samples` at or below `ceiling_db` (dBFS, at most 0) by
+    /// turning the gain down over the `lookahead_ms` before each peak
+    /// arrives, then recovering with the `release_ms` time constant.
+    ///
+    /// The output is delayed by the lookahead, rounded to
+    /// `round(sampleRate * lookahead_ms / 1000)` frames, and is that many
+    /// frames longer than the input; drop that many leading frames to
+    /// line it up again. Throws on a zero sample rate, a ceiling above
+    /// 0 dBFS or a channel count outside 1 to MAX_CHANNELS.
+    #[wasm_bindgen]
+    pub fn apply_limiter(
+        samples: &Float32Array,
+        ceiling_db: f32,
+        lookahead_ms: f32,
+        release_ms: f32,
+        sample_rate: u32,
+        channels: u32,
+    ) -> Result<Float32Array, JsValue> {
+        let sample_rate = checked_sample_rate(sample_rate)?;
+        let ceiling = checked_ceiling(db_to_gain(ceiling_db))?;
+        let channels = checked_channels(channels)?;
+        let lookahead = (lookahead_ms * sample_rate as f32 / 1000.0).round() as usize;
+        let output = dynamics::limit(
+            &samples.to_vec(),
+            channels,
+            ceiling,
+            lookahead,
+            release_ms,
+            sample_rate,
+        );
+        Ok(Float32Array::from(&output[..]))
+    }
+
+    /// Amplitude envelope of interleaved `samples`, one value per frame: the
+    /// frame's peak across channels, smoothed so it rises with `attack_ms`
+    /// and falls with `release_ms` time constants. The follower behind
+    /// apply_compressor and sidechain ducking, for custom dynamics or
+    /// meters in JS; throws on a zero sample rate or a channel count
+    /// outside 1 to MAX_CHANNELS.
+    #[wasm_bindgen]
+    pub fn envelope_follower(
+        samples: &Float32Array,
+        attack_ms: f32,
+        release_ms: f32,
+        sample_rate: u32,
+        channels: u32,
+    ) -> Result<Float32Array, JsValue> {
+        let channels = checked_channels(channels)?;
+        let samples = samples.to_vec();
+        let envelope = dynamics::envelope(
+            dynamics::frame_peaks(&samples, channels),
+            attack_ms,
+            release_ms,
+            checked_sample_rate(sample_rate)?,
+        );
+        Ok(Float32Array::from(&envelope[..]))
+    }
+
+    /// Adjust the stereo width of an interleaved stereo buffer with
+    /// mid/side processing: 0.0 sums to mono, 1.0 is unchanged and values
+    /// above 1.0 widen. The result is clamped to [-1.0, 1.0]; a trailing
+    /// sample without a partner is passed through.
+    #[wasm_bindgen]
+    pub fn adjust_stereo_width(samples: &Float32Array, width: f32) -> Float32Array {
+        let mut output = samples.to_vec();
+        effects::stereo_width(&mut output, width);
+        Float32Array::from(&output[..])
+    }
+
+    /// Remove DC offset from an interleaved buffer by subtracting each
+    /// channel's mean from that channel, recovering the headroom an offset
+    /// wastes and the clicks it causes at edits. Throws on a channel count
+    /// outside 1 to MAX_CHANNELS.
+    #[wasm_bindgen]
+    pub fn remove_dc_offset(
+        samples: &Float32Array,
+        channels: u32,
+    ) -> Result<Float32Array, JsValue> {
+        let channels = checked_channels(channels)?;
+        let mut output = samples.to_vec();
+        effects::remove_dc_offset(&mut output, channels);
+        Ok(Float32Array::from(&output[..]))
+    }
+
+    /// Interleave planar channels, as Web Audio's getChannelData gives
+    /// them, into one buffer of frames. Shorter planes are padded with
+    /// silence to the length of the longest.
+    #[wasm_bindgen]
+    pub fn interleave(channels: Vec<Float32Array>) -> Float32Array {
+        let planes: Vec<Vec<f32>> = channels.iter().map(Float32Array::to_vec).collect();
+        Float32Array::from(&channels::interleave(&planes)[..])
+    }
+
+    /// Split an interleaved buffer into an array of one Float32Array per
+    /// channel; a trailing partial frame is dropped. Throws on a channel
+    /// count outside 1 to MAX_CHANNELS.
+    #[wasm_bindgen]
+    pub fn deinterleave(samples: &Float32Array, channels: u32) -> Result<js_sys::Array, JsValue> {
+        let channels = checked_channels(channels)?;
+        Ok(channels::deinterleave(&samples.to_vec(), channels)
+            .iter()
+            .map(|plane| Float32Array::from(&plane[..]))
+            .collect())
+    }
+
+    /// Fold interleaved stereo into mono. `law` sets the gain of the summed
+    /// pair like the pan law at center: "equal_power"/"-3db" keeps the
+    /// power of uncorrelated channels, "linear"/"-6db" averages them and
+    /// keeps the level of identical ones, "-4.5db" sits between. Throws on
+    /// an unknown law.
+    #[wasm_bindgen]
+    pub fn downmix_to_mono(samples: &Float32Array, law: &str) -> Result<Float32Array, JsValue> {
+        let output = channels::downmix_to_mono(&samples.to_vec(), pan_law(law)?);
+        Ok(Float32Array::from(&output[..]))
+    }
+
+    /// Duplicate a mono buffer into both channels of interleaved stereo
+    #[wasm_bindgen]
+    pub fn upmix_to_stereo(samples: &Float32Array) -> Float32Array {
+        Float32Array::from(&channels::upmix_to_stereo(&samples.to_vec())[..])
+    }
+
+    /// Length of the buffer apply_delay returns for an input of `length`
+    /// samples
+    #[wasm_bindgen]
+    pub fn delay_output_length(length: usize, delay_samples: usize, feedback: f32) -> usize {
+        effects::delay_output_len(length, delay_samples, feedback)
+    }
+
+    /// Convert float samples to 16-bit PCM, clamping to [-1.0, 1.0]. With
+    /// `dither`, TPDF dither is added before rounding to avoid distortion
+    /// on quiet passages.
+    #[wasm_bindgen]
+    pub fn to_pcm16(samples: &Float32Array, dither: bool) -> Int16Array {
+        let output: Vec<i16> = pcm::quantize(&samples.to_vec(), 16, dither)
+            .into_iter()
+            .map(|value| value as i16)
+            .collect();
+        Int16Array::from(&output[..])
+    }
+
+    /// Convert float samples to 24-bit PCM like to_pcm16, one value from
+    /// -8388608 to 8388607 per element
+    #[wasm_bindgen]
+    pub fn to_pcm24(samples: &Float32Array, dither: bool) -> Int32Array {
+        let output = pcm::quantize(&samples.to_vec(), 24, dither);
+        Int32Array::from(&output[..])
+    }
+
+    /// Encode an interleaved buffer as a complete WAV file with 16-bit
+    /// PCM, 24-bit PCM or 32-bit float samples (`bit_depth` 16, 24 or 32).
+    /// PCM samples are clamped to [-1.0, 1.0] without dither; use to_pcm16
+    /// for dithered conversion. Throws on a zero sample rate or a channel
+    /// count outside 1 to MAX_CHANNELS.
+    #[wasm_bindgen]
+    pub fn encode_wav(
+        samples: &Float32Array,
+        sample_rate: u32,
+        channels: u32,
+        bit_depth: u32,
+    ) -> Result<Uint8Array, JsValue> {
+        let format = WavFormat::from_bit_depth(bit_depth)
+            .ok_or(MixerError::UnsupportedBitDepth(bit_depth))?;
+        let channels = checked_channels(channels)? as u16;
+        let bytes = wav::encode(
+            &samples.to_vec(),
+            checked_sample_rate(sample_rate)?,
+            channels,
+            format,
+        );
+        Ok(Uint8Array::from(&bytes[..]))
+    }
+
+    /// Decode a WAV file, e.g. one dropped by the user, into interleaved
+    /// float samples with its sample rate and channel count. Reads 16, 24
+    /// and 32-bit integer PCM and 32-bit float, including
+    /// WAVE_FORMAT_EXTENSIBLE files; throws a descriptive error on anything
+    /// else or a malformed file.
+    #[wasm_bindgen]
+    pub fn decode_wav(data: &Uint8Array) -> Result<DecodedAudio, JsValue> {
+        let decoded = wav::decode(&data.to_vec())?;
+        Ok(DecodedAudio {
+            samples: decoded.samples,
+            sample_rate: decoded.sample_rate,
+            channels: decoded.channels as u32,
+        })
+    }
+
+    /// Dry/wet mix of an effect's input (`dry`) and output (`wet`):
+    /// `dry * (1 - mix) + wet * mix`, with `mix` clamped to [0.0, 1.0]. The
+    /// shorter buffer is padded with silence, so an effect's tail is kept;
+    /// use it to compose effects that have no mix of their own.
+    #[wasm_bindgen]
+    pub fn blend(dry: &Float32Array, wet: &Float32Array, mix: f32) -> Float32Array {
+        let output = effects::blend(&dry.to_vec(), &wet.to_vec(), mix);
+        Float32Array::from(&output[..])
+    }
+
+    /// Crossfade between two buffers with linear fades. `fade_samples` is
+    /// clamped to the shorter buffer, so the result is never shorter than
+    /// either input.
+    #[wasm_bindgen]
+    pub fn crossfade(
+        buffer_a: &Float32Array,
+        buffer_b: &Float32Array,
+        fade_samples: usize,
+    ) -> Float32Array {
+        let output = envelope::crossfade(
+            &buffer_a.to_vec(),
+            &buffer_b.to_vec(),
+            fade_samples,
+            FadeCurve::Linear,
+        );
+        Float32Array::from(&output[..])
+    }
+
+    /// Crossfade between two buffers with any curve set_fade_curve accepts.
+    /// Equal-power fades follow cos/sin so the combined level does not dip
+    /// halfway through; use it for uncorrelated material.
+    #[wasm_bindgen]
+    pub fn crossfade_with(
+        buffer_a: &Float32Array,
+        buffer_b: &Float32Array,
+        fade_samples: usize,
+        curve: &str,
+    ) -> Result<Float32Array, JsValue> {
+        let output = envelope::crossfade(
+            &buffer_a.to_vec(),
+            &buffer_b.to_vec(),
+            fade_samples,
+            fade_curve(curve)?,
+        );
+        Ok(Float32Array::from(&output[..]))
+    }
+
+    /// Join interleaved buffers with `channels` channels end to end into
+    /// one. `declick_frames` (0 for a plain join) overlaps each join by a
+    /// few frames with a linear crossfade to avoid clicks at the splice;
+    /// the output is shorter by the overlap. Throws when a buffer does not
+    /// hold whole frames or on a channel count outside 1 to MAX_CHANNELS.
+    #[wasm_bindgen]
+    pub fn concat(
+        buffers: Vec<Float32Array>,
+        channels: u32,
+        declick_frames: usize,
+    ) -> Result<Float32Array, JsValue> {
+        let channels = checked_channels(channels)?;
+        let buffers: Vec<Vec<f32>> = buffers.iter().map(Float32Array::to_vec).collect();
+        if let Some((index, buffer)) = buffers
+            .iter()
+            .enumerate()
+            .find(|(_, buffer)| buffer.len() % channels != 0)
+        {
+            return Err(MixerError::PartialFrame {
+                index,
+                len: buffer.len(),
+                channels,
+            }
+            .into());
+        }
+        let output = envelope::concat(&buffers, channels, declick_frames);
+        Ok(Float32Array::from(&output[..]))
+    }
+
+    /// Gain ramp of `length_samples` to multiply into a buffer: the fade
+    /// curve (as in set_fade_curve) from 0.0 to 1.0 for direction "in",
+    /// or from 1.0 to 0.0 for "out". Throws on an unknown curve or
+    /// direction.
+    #[wasm_bindgen]
+    pub fn generate_fade(
+        length_samples: usize,
+        curve: &str,
+        direction: &str,
+    ) -> Result<Float32Array, JsValue> {
+        let direction =
+            FadeDirection::parse(direction).ok_or_else(|| MixerError::UnknownOption {
+                option: "fade direction",
+                value: direction.to_string(),
+                expected: "\"in\" or \"out\"",
+            })?;
+        let ramp = envelope::fade_ramp(length_samples, fade_curve(curve)?, direction);
+        Ok(Float32Array::from(&ramp[..]))
+    }
+}
+
+impl AudioMixer {
+    fn with_output_layout(sample_rate: u32, layout: ChannelLayout) -> Result<Self, MixerError> {
+        Ok(Self {
+            tracks: Vec::new(),
+            sample_rate: checked_sample_rate(sample_rate)?,
+            layout,
+            pan_law: PanLaw::EqualPower,
+            output_mode: OutputMode::Normalize,
+            auto_resample: true,
+            declick: false,
+            mute_ramp_seconds: DEFAULT_MUTE_RAMP_SECONDS,
+            master_gain: 1.0,
+            master_balance: 0.0,
+            output_ceiling: 1.0,
+            detect_clips: false,
+            last_mix: None,
+            last_clip_regions: Vec::new(),
+        })
+    }
+
+    /// Resample `track` to the mixer's rate when auto-resampling is on and
+    /// the track declares a different rate
+    fn prepare_track(&self, mut track: AudioTrack) -> AudioTrack {
+        match track.sample_rate {
+            Some(rate) if self.auto_resample && rate != self.sample_rate => {
+                track.samples = resample::resample(
+                    &track.samples,
+                    track.layout.channels(),
+                    rate,
+                    self.sample_rate,
+                );
+                track.sample_rate = Some(self.sample_rate);
+            }
+            _ => {}
+        }
+        track
+    }
+
+    /// Snapshot of everything serialize persists
+    fn state(&self) -> MixerState {
+        MixerState {
+            sample_rate: self.sample_rate,
+            layout: self.layout,
+            pan_law: self.pan_law,
+            output_mode: self.output_mode,
+            declick: self.declick,
+            master_gain: self.master_gain,
+            master_balance: self.master_balance,
+            output_ceiling: self.output_ceiling,
+            tracks: self
+                .tracks
+                .iter()
+                .map(|track| TrackState {
+                    layout: track.layout,
+                    gain: track.gain,
+                    pan: track.pan,
+                    start_sample: track.start_sample,
+                    trim_start: track.trim_start,
+                    delay_samples: track.delay_samples,
+                    invert_polarity: track.invert_polarity,
+                    muted: track.muted,
+                    soloed: track.soloed,
+                    fade_in: track.fades.fade_in,
+                    fade_out: track.fades.fade_out,
+                    sample_rate: track.sample_rate,
+                    samples: track.samples.clone(),
+                    loop_to_sample: track.loop_to_sample,
+                    loop_crossfade: track.loop_crossfade,
+                })
+                .collect(),
+        }
+    }
+
+    /// Rebuild a mixer from a decoded session, validating it like the
+    /// setters would
+    fn from_state(state: MixerState) -> Result<Self, MixerError> {
+        let mut tracks = Vec::with_capacity(state.tracks.len());
+        for saved in state.tracks {
+            let mut track = AudioTrack::from_samples(
+                saved.samples,
+                saved.layout,
+                saved.gain,
+                saved.pan,
+                saved.start_sample,
+            )?;
+            track.trim_start = saved.trim_start;
+            track.delay_samples = saved.delay_samples;
+            track.invert_polarity = saved.invert_polarity;
+            track.muted = saved.muted;
+            track.soloed = saved.soloed;
+            track.fades.fade_in = saved.fade_in;
+            track.fades.fade_out = saved.fade_out;
+            track.sample_rate = saved.sample_rate;
+            track.loop_to_sample = saved.loop_to_sample;
+            track.loop_crossfade = saved.loop_crossfade;
+            tracks.push(track);
+        }
+        Ok(Self {
+            tracks,
+            sample_rate: checked_sample_rate(state.sample_rate)?,
+            layout: state.layout,
+            pan_law: state.pan_law,
+            output_mode: state.output_mode,
+            auto_resample: true,
+            declick: state.declick,
+            mute_ramp_seconds: DEFAULT_MUTE_RAMP_SECONDS,
+            master_gain: checked_gain(state.master_gain)?,
+            master_balance: checked_pan(state.master_balance)?,
+            output_ceiling: checked_ceiling(state.output_ceiling)?,
+            detect_clips: false,
+            last_mix: None,
+            last_clip_regions: Vec::new(),
+        })
+    }
+
+    fn overlay_samples(
+        &self,
+        mut base: Vec<f32>,
+        overlay: &[f32],
+        offset: usize,
+        gain: f32,
+    ) -> Vec<f32> {
+        let start = offset.saturating_mul(self.layout.channels());
+        let end = start.saturating_add(overlay.len());
+        if base.len() < end {
+            base.resize(end, 0.0);
+        }
+        simd::mul_add(&mut base[start..], overlay, gain);
+        self.output_mode.apply(&mut base, self.output_ceiling);
+        base
+    }
+
+    fn append_samples(&mut self, index: usize, samples: &[f32]) -> Result<(), MixerError> {
+        let track = self.track_mut(index)?;
+        let channels = track.layout.channels();
+        if !samples.len().is_multiple_of(channels) {
+            return Err(MixerError::PartialFrame {
+                index,
+                len: samples.len(),
+                channels,
+            });
+        }
+        track.samples.extend_from_slice(samples);
+        Ok(())
+    }
+
+    fn track_mut(&mut self, index: usize) -> Result<&mut AudioTrack, MixerError> {
+        let count = self.tracks.len();
+        self.tracks
+            .get_mut(index)
+            .ok_or(MixerError::TrackOutOfRange { index, count })
+    }
+
+    /// Tracks that are heard: with any track soloed, everything else is
+    /// silent (solo in place)
+    fn audible_tracks(&self) -> impl Iterator<Item = &AudioTrack> {
+        let any_soloed = self.tracks.iter().any(|t| t.soloed);
+        self.tracks
+            .iter()
+            .filter(move |t| !t.muted && (t.soloed || !any_soloed))
+    }
+
+    /// The samples the track plays, looped and through its filter, at
+    /// least up to timeline frame `end`. The filter runs from the track
+    /// start so its state carries across every frame, including ones
+    /// before the window being mixed.
+    fn source_samples<'a>(&self, track: &'a AudioTrack, end: usize) -> Cow<'a, [f32]> {
+        let in_channels = track.layout.channels();
+        let played = track.played(end);
+        let Some(spec) = track.filter else {
+            return played;
+        };
+        let frame_count = played.len() / in_channels;
+        let end = (end as i64 - track.position()).clamp(0, frame_count as i64) as usize;
+        let mut samples = played[..end * in_channels].to_vec();
+        Biquad::new(spec, self.sample_rate).process(&mut samples, in_channels);
+        Cow::Owned(samples)
+    }
+
+    /// The track's samples and ducking gains up to timeline frame `end`
+    fn source<'a>(&self, track: &'a AudioTrack, end: usize) -> Source<'a> {
+        Source {
+            samples: self.source_samples(track, end),
+            ducking: self.ducking(track, end),
+        }
+    }
+
+    /// Gain of a keyed track for each timeline frame up to `end`, from the
+    /// peak level of its key as it leaves the key's fader
+    fn ducking(&self, track: &AudioTrack, end: usize) -> Option<Vec<f32>> {
+        let sidechain = track.sidechain?;
+        let key = self.tracks.get(sidechain.key)?;
+        let channels = self.layout.channels();
+        let mut keyed = vec![0.0f32; end * channels];
+        let source = Source {
+            samples: self.source_samples(key, end),
+            ducking: None,
+        };
+        self.mix_track(key, &source, &mut keyed, 0);
+        let levels: Vec<f32> = dynamics::frame_peaks(&keyed, channels).collect();
+        Some(dynamics::duck_gains(
+            &levels,
+            sidechain.settings,
+            self.sample_rate,
+        ))
+    }
+
+    /// Add one track, routed to the output channels, into `output`, which
+    /// holds the timeline from frame `window_start` on. The source covers
+    /// at least up to the window end.
+    fn mix_track(
+        &self,
+        track: &AudioTrack,
+        source: &Source,
+        output: &mut [f32],
+        window_start: usize,
+    ) {
+        let samples = &source.samples;
+        let in_channels = track.layout.channels();
+        let out_channels = self.layout.channels();
+        // Only the default routing follows the pan; an explicit matrix is
+        // used as is
+        let pan_automation = track
+            .pan_automation
+            .as_ref()
+            .filter(|_| track.matrix.is_none());
+        // First track frame inside the window, and where it lands
+        let position = track.position();
+        let first = (window_start as i64 - position).max(0) as usize;
+        let offset = (position - window_start as i64).max(0) as usize;
+        let mut pan = pan_automation.map_or(track.pan, |a| a.value_at(first));
+        let mut matrix = match &track.matrix {
+            Some(matrix) => matrix.clone(),
+            None => channels::default_matrix(track.layout, self.layout, pan, self.pan_law),
+        };
+
+        let frame_count = track.frame_count();
+
+        // Route the track's part of the window into its own buffer with the
+        // gain that changes per frame, then sum it into the output with the
+        // scalar gain in one pass over contiguous samples
+        let skip = (offset * out_channels).min(output.len());
+        let output = &mut output[skip..];
+        let routed_frames = frame_count
+            .saturating_sub(first)
+            .min(output.len() / out_channels);
+        let mut routed = vec![0.0f32; routed_frames * out_channels];
+
+        let mut fades = track.fades;
+        if self.declick {
+            let ramp = (self.sample_rate as f32 * DECLICK_SECONDS).round() as usize;
+            fades.fade_in = fades.fade_in.max(ramp);
+            fades.fade_out = fades.fade_out.max(ramp);
+        }
+        let fades = fades.clamped(frame_count);
+        let mute_ramp = (self.sample_rate as f32 * self.mute_ramp_seconds).round() as usize;
+        let frames = samples.chunks_exact(in_channels).enumerate().skip(first);
+        let out_frames = routed.chunks_exact_mut(out_channels);
+        for ((index, frame), out_frame) in frames.zip(out_frames) {
+            if let Some(automation) = pan_automation {
+                let value = automation.value_at(index);
+                if value != pan {
+                    pan = value;
+                    channels::fill_default_matrix(
+                        &mut matrix,
+                        track.layout,
+                        self.layout,
+                        pan,
+                        self.pan_law,
+                    );
+                }
+            }
+            let gain = track
+                .gain_automation
+                .as_ref()
+                .map_or(1.0, |a| a.value_at(index));
+            let mut gain = gain * fades.gain(index, frame_count);
+            if !track.mute_changes.is_empty() {
+                gain *= envelope::mute_gain(&track.mute_changes, mute_ramp, index);
+            }
+            if let Some(ducking) = &source.ducking {
+                let frame = (position + index as i64) as usize;
+                gain *= ducking.get(frame).copied().unwrap_or(1.0);
+            }
+            for (out, gains) in out_frame.iter_mut().zip(matrix.chunks(in_channels)) {
+                let routed: f32 = frame.iter().zip(gains).map(|(s, g)| s * g).sum();
+                *out = routed * gain;
+            }
+        }
+        let gain = if track.gain_automation.is_some() {
+            1.0
+        } else {
+            track.gain
+        };
+        let gain = if track.invert_polarity { -gain } else { gain };
+        simd::mul_add(output, &routed, gain);
+    }
+
+    /// Sum the audible tracks over `length` frames of the timeline from
+    /// `start`, before the output stage
+    fn sum_tracks(&self, start: usize, length: usize) -> Vec<f32> {
+        let mut output = vec![0.0f32; length * self.layout.channels()];
+        for track in self.audible_tracks() {
+            let samples = self.source(track, start + length);
+            self.mix_track(track, &samples, &mut output, start);
+        }
+        self.apply_master(&mut output);
+        output
+    }
+
+    /// Master gain and balance on summed interleaved output
+    fn apply_master(&self, output: &mut [f32]) {
+        if self.master_gain == 1.0 && self.master_balance == 0.0 {
+            return;
+        }
+        let channels = self.layout.channels();
+        let gains = channels::balance_channel_gains(self.layout, self.master_balance);
+        for frame in output.chunks_exact_mut(channels) {
+            for (sample, gain) in frame.iter_mut().zip(&gains) {
+                *sample *= gain * self.master_gain;
+            }
+        }
+    }
+
+    fn stem(&self, index: usize, duration_samples: usize) -> Result<Vec<f32>, MixerError> {
+        let count = self.tracks.len();
+        let track = self
+            .tracks
+            .get(index)
+            .ok_or(MixerError::TrackOutOfRange { index, count })?;
+        let mut output = vec![0.0f32; duration_samples * self.layout.channels()];
+        let samples = self.source(track, duration_samples);
+        self.mix_track(track, &samples, &mut output, 0);
+        Ok(output)
+    }
+
+    /// Master-bus sums of the audible tracks per group, in order of first
+    /// appearance
+    fn stem_groups(&self, duration_samples: usize) -> Vec<(&str, Vec<f32>)> {
+        let mut stems: Vec<(&str, Vec<f32>)> = Vec::new();
+        for track in self.audible_tracks() {
+            let group = track.group.as_deref().unwrap_or(DEFAULT_STEM);
+            let index = match stems.iter().position(|(name, _)| *name == group) {
+                Some(index) => index,
+                None => {
+                    let silence = vec![0.0f32; duration_samples * self.layout.channels()];
+                    stems.push((group, silence));
+                    stems.len() - 1
+                }
+            };
+            let samples = self.source(track, duration_samples);
+            self.mix_track(track, &samples, &mut stems[index].1, 0);
+        }
+        for (_, output) in &mut stems {
+            self.apply_master(output);
+        }
+        stems
+    }
+
+    fn track_level_pairs(&self, duration_samples: usize) -> Vec<f32> {
+        let audible: Vec<&AudioTrack> = self.audible_tracks().collect();
+        let mut output = vec![0.0f32; duration_samples * self.layout.channels()];
+        let mut levels = Vec::with_capacity(self.tracks.len() * 2);
+        for track in &self.tracks {
+            if !audible.iter().any(|t| std::ptr::eq(*t, track)) {
+                levels.extend([0.0, 0.0]);
+                continue;
+            }
+            output.fill(0.0);
+            let samples = self.source(track, duration_samples);
+            self.mix_track(track, &samples, &mut output, 0);
+            levels.extend([output::peak(&output), loudness::rms(&output)]);
+        }
+        levels
+    }
+
+    fn mix_range_samples(&self, start: usize, length: usize) -> Vec<f32> {
+        let mut output = self.sum_tracks(start, length);
+        let mode = match self.output_mode {
+            OutputMode::Normalize => OutputMode::HardClip,
+            mode => mode,
+        };
+        mode.apply(&mut output, self.output_ceiling);
+        output
+    }
+
+    fn mix_samples(&mut self, duration_samples: usize) -> Vec<f32> {
+        self.render_samples(duration_samples, |_| {})
+    }
+
+    fn mix_result(&mut self, duration_samples: usize) -> MixResult {
+        let samples = self.mix_samples(duration_samples);
+        MixResult {
+            frames: samples.len() / self.layout.channels(),
+            samples,
+            report: self.last_mix.expect("render_samples sets the report"),
+        }
+    }
+
+    /// Mix `duration_samples` frames in blocks of RENDER_BLOCK_FRAMES,
+    /// reporting the finished fraction after each block. Tracks are
+    /// filtered once up front, so the blocks join into exactly the same
+    /// samples as a single pass.
+    fn render_samples(
+        &mut self,
+        duration_samples: usize,
+        mut progress: impl FnMut(f32),
+    ) -> Vec<f32> {
+        let out_channels = self.layout.channels();
+        let mut output = vec![0.0f32; duration_samples * out_channels];
+        let sources: Vec<(&AudioTrack, Source)> = self
+            .audible_tracks()
+            .map(|track| (track, self.source(track, duration_samples)))
+            .collect();
+        let mut clip_regions = Vec::new();
+        let blocks = output.chunks_mut(RENDER_BLOCK_FRAMES * out_channels);
+        for (block_index, block) in blocks.enumerate() {
+            let block_start = block_index * RENDER_BLOCK_FRAMES;
+            for (track, samples) in &sources {
+                self.mix_track(track, samples, block, block_start);
+            }
+            self.apply_master(block);
+            if self.detect_clips {
+                output::clip_regions(block, out_channels, block_start, &mut clip_regions);
+            }
+            let done = block_start + block.len() / out_channels;
+            progress(done as f32 / duration_samples as f32);
+        }
+        drop(sources);
+        if duration_samples == 0 {
+            progress(1.0);
+        }
+
+        self.last_clip_regions = clip_regions;
+        let peak = output::peak(&output);
+        self.last_mix = Some(MixReport {
+            peak,
+            scale: self.output_mode.scale(peak, self.output_ceiling),
+            clipped: self.output_mode.alters(peak, self.output_ceiling),
+        });
+        self.output_mode.apply(&mut output, self.output_ceiling);
+        output
+    }
+}
+
+/// Mixer for live input: each track is fed blocks of samples as they
+/// arrive and buffered in its own ring buffer, and `pull` mixes the next
+/// frames of every track, e.g. one 128-frame render quantum of an
+/// AudioWorklet at a time
+#[wasm_bindgen]
+pub struct StreamingMixer {
+    layout: ChannelLayout,
+    /// Frames each track can buffer before the oldest are dropped
+    capacity_frames: usize,
+    pan_law: PanLaw,
+    tracks: Vec<StreamTrack>,
+}
+
+#[wasm_bindgen]
+impl StreamingMixer {
+    /// Create a mixer producing `channels` interleaved output channels
+    /// (1 to 6, throws otherwise) in the default layout for the count,
+    /// buffering up to `capacity_frames` frames per track
+    #[wasm_bindgen(constructor)]
+    pub fn new(channels: u32, capacity_frames: usize) -> Result<StreamingMixer, JsValue> {
+        Ok(Self {
+            layout: ChannelLayout::from_channels(channels)?,
+            capacity_frames: capacity_frames.max(1),
+            pan_law: PanLaw::EqualPower,
+            tracks: Vec::new(),
+        })
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn channels(&self) -> u32 {
+        self.layout.channels() as u32
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn track_count(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// Select the pan law for mono tracks, as AudioMixer.set_pan_law
+    #[wasm_bindgen]
+    pub fn set_pan_law(&mut self, law: &str) -> Result<(), JsValue> {
+        self.pan_law = pan_law(law)?;
+        Ok(())
+    }
+
+    /// Add an input with `channels` interleaved channels (1 to 6), gain and
+    /// pan as on AudioTrack. Throws when `track_id` is already in use, on
+    /// another channel count, a non-finite gain or a NaN pan.
+    #[wasm_bindgen]
+    pub fn add_track(
+        &mut self,
+        track_id: u32,
+        channels: u32,
+        gain: f32,
+        pan: f32,
+    ) -> Result<(), JsValue> {
+        if self.tracks.iter().any(|t| t.id == track_id) {
+            return Err(MixerError::DuplicateTrackId(track_id).into());
+        }
+        let layout = ChannelLayout::from_channels(channels)?;
+        let (gain, pan) = (checked_gain(gain)?, checked_pan(pan)?);
+        self.tracks.push(StreamTrack::new(
+            track_id,
+            layout,
+            gain,
+            pan,
+            self.capacity_frames,
+        ));
+        Ok(())
+    }
+
+    /// Remove an input and its buffered frames; false when no track has
+    /// the id
+    #[wasm_bindgen]
+    pub fn remove_track(&mut self, track_id: u32) -> bool {
+        let count = self.tracks.len();
+        self.tracks.retain(|t| t.id != track_id);
+        self.tracks.len() != count
+    }
+
+    #[wasm_bindgen]
+    pub fn set_track_gain(&mut self, track_id: u32, gain: f32) -> Result<(), JsValue> {
+        let gain = checked_gain(gain)?;
+        self.track_mut(track_id)?.gain = gain;
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn set_track_pan(&mut self, track_id: u32, pan: f32) -> Result<(), JsValue> {
+        let pan = checked_pan(pan)?;
+        self.track_mut(track_id)?.pan = pan;
+        Ok(())
+    }
+
+    /// Queue interleaved samples for a track. When the buffer is full the
+    /// oldest frames are dropped to keep latency bounded; returns how many
+    /// were dropped. Throws on an unknown id.
+    #[wasm_bindgen]
+    pub fn push_track_block(
+        &mut self,
+        track_id: u32,
+        samples: &Float32Array,
+    ) -> Result<usize, JsValue> {
+        Ok(self.track_mut(track_id)?.push(&samples.to_vec()))
+    }
+
+    /// Frames queued for a track and not yet pulled
+    #[wasm_bindgen]
+    pub fn buffered_frames(&self, track_id: u32) -> Result<usize, JsValue> {
+        let track = self.tracks.iter().find(|t| t.id == track_id);
+        let track = track.ok_or(MixerError::UnknownTrackId(track_id))?;
+        Ok(track.buffer.len() / track.layout.channels())
+    }
+
+    /// Mix the next `frames` frames of every track into interleaved
+    /// output. A track that has not received enough samples contributes
+    /// silence for the rest (an underrun). No output stage is applied.
+    #[wasm_bindgen]
+    pub fn pull(&mut self, frames: usize) -> Float32Array {
+        Float32Array::from(&self.pull_samples(frames)[..])
+    }
+}
+
+impl StreamingMixer {
+    fn track_mut(&mut self, track_id: u32) -> Result<&mut StreamTrack, MixerError> {
+        self.tracks
+            .iter_mut()
+            .find(|t| t.id == track_id)
+            .ok_or(MixerError::UnknownTrackId(track_id))
+    }
+
+    fn pull_samples(&mut self, frames: usize) -> Vec<f32> {
+        let mut output = vec![0.0f32; frames * self.layout.channels()];
+        for track in &mut self.tracks {
+            track.mix_into(&mut output, self.layout, self.pan_law);
+        }
+        output
+    }
+}
+
+/// Build automation from JS arrays, rejecting mismatched lengths
+fn automation(offsets: &Uint32Array, values: &Float32Array) -> Result<Automation, MixerError> {
+    Automation::new(&offsets.to_vec(), &values.to_vec()).ok_or(MixerError::InvalidAutomation)
+}
+
+fn pan_law(law: &str) -> Result<PanLaw, MixerError> {
+    PanLaw::parse(law).ok_or_else(|| MixerError::UnknownOption {
+        option: "pan law",
+        value: law.to_string(),
+        expected: "\"equal_power\", \"linear\" or \"-4.5db\"",
+    })
+}
+
+fn channel_layout(layout: &str) -> Result<ChannelLayout, MixerError> {
+    ChannelLayout::parse(layout).ok_or_else(|| MixerError::UnknownOption {
+        option: "channel layout",
+        value: layout.to_string(),
+        expected: "\"mono\", \"stereo\", \"2.1\", \"3.0\", \"quad\", \"5.0\" or \"5.1\"",
+    })
+}
+
+fn fade_curve(curve: &str) -> Result<FadeCurve, MixerError> {
+    FadeCurve::parse(curve).ok_or_else(|| MixerError::UnknownOption {
+        option: "fade curve",
+        value: curve.to_string(),
+        expected: "\"linear\", \"equal_power\", \"exponential\", \"logarithmic\" or \"s_curve\"",
+    })
+}
+
+fn filter_kind(kind: &str) -> Result<FilterKind, MixerError> {
+    FilterKind::parse(kind).ok_or_else(|| MixerError::UnknownOption {
+        option: "filter",
+        value: kind.to_string(),
+        expected: "\"lowpass\", \"highpass\" or \"bandpass\"",
+    })
+}
+
+fn band_kind(kind: &str) -> Result<FilterKind, MixerError> {
+    FilterKind::parse_band(kind).ok_or_else(|| MixerError::UnknownOption {
+        option: "EQ band",
+        value: kind.to_string(),
+        expected:
+            "\"peaking\", \"lowshelf\", \"highshelf\", \"lowpass\", \"highpass\" or \"bandpass\"",
+    })
+}
+
+fn checked_gain(gain: f32) -> Result<f32, MixerError> {
+    if gain.is_finite() {
+        Ok(gain)
+    } else {
+        Err(MixerError::InvalidGain(gain))
+    }
+}
+
+/// Pan clamped to [-1.0, 1.0]; NaN has no position and is rejected
+fn checked_pan(pan: f32) -> Result<f32, MixerError> {
+    if pan.is_nan() {
+        Err(MixerError::InvalidPan(pan))
+    } else {
+        Ok(pan.clamp(-1.0, 1.0))
+    }
+}
+
+/// Output ceiling above silence and at most full scale
+fn checked_ceiling(ceiling: f32) -> Result<f32, MixerError> {
+    if ceiling > 0.0 && ceiling <= 1.0 {
+        Ok(ceiling)
+    } else {
+        Err(MixerError::InvalidCeiling(ceiling))
+    }
+}
+
+fn checked_sample_rate(sample_rate: u32) -> Result<u32, MixerError> {
+    if sample_rate == 0 {
+        Err(MixerError::InvalidSampleRate(sample_rate))
+    } else {
+        Ok(sample_rate)
+    }
+}
+
+/// Track for an add_tracks_bulk descriptor once its fields are read
+fn descriptor_track(
+    index: u32,
+    samples: Vec<f32>,
+    layout: ChannelLayout,
+    gain: f64,
+    pan: f64,
+    start: f64,
+) -> Result<AudioTrack, MixerError> {
+    if start.is_nan() || start < 0.0 {
+        return Err(MixerError::InvalidTrackDescriptor {
+            index,
+            reason: "start must be 0 or more",
+        });
+    }
+    AudioTrack::from_samples(samples, layout, gain as f32, pan as f32, start as usize)
+}
+
+fn checked_channels(channels: u32) -> Result<usize, MixerError> {
+    ChannelLayout::from_channels(channels).map(ChannelLayout::channels)
+}
+
+fn normalize_to_lufs(
+    mut samples: Vec<f32>,
+    channels: usize,
+    sample_rate: u32,
+    target_lufs: f32,
+    ceiling_dbfs: f32,
+) -> LoudnessNormalization {
+    let input_lufs = loudness::integrated_loudness(&samples, channels, sample_rate) as f32;
+    if !input_lufs.is_finite() {
+        return LoudnessNormalization {
+            samples,
+            input_lufs,
+            gain_db: 0.0,
+            peak_limited: false,
+        };
+    }
+
+    let mut gain = db_to_gain(target_lufs - input_lufs);
+    let ceiling = db_to_gain(ceiling_dbfs);
+    let peak = loudness::true_peak(&samples, channels);
+    let peak_limited = peak * gain > ceiling;
+    if peak_limited {
+        gain = ceiling / peak;
+    }
+    for sample in &mut samples {
+        *sample *= gain;
+    }
+    LoudnessNormalization {
+        samples,
+        input_lufs,
+        gain_db: 20.0 * gain.log10(),
+        peak_limited,
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    fn track(samples: &[f32], channels: u32, pan: f32, start_sample: usize) -> AudioTrack {
+        AudioTrack {
+            samples: samples.to_vec(),
+            layout: ChannelLayout::from_channels(channels).unwrap(),
+            gain: 1.0,
+            pan,
+            start_sample,
+            trim_start: 0,
+            delay_samples: 0,
+            invert_polarity: false,
+            matrix: None,
+            fades: Fades::default(),
+            gain_automation: None,
+            pan_automation: None,
+            muted: false,
+            soloed: false,
+            filter: None,
+            sample_rate: None,
+            loop_to_sample: None,
+            loop_crossfade: 0,
+            mute_changes: Vec::new(),
+            group: None,
+            sidechain: None,
+        }
+    }
+
+    fn mixer(channels: u32, tracks: Vec<AudioTrack>) -> AudioMixer {
+        AudioMixer {
+            tracks,
+            sample_rate: 48000,
+            layout: ChannelLayout::from_channels(channels).unwrap(),
+            pan_law: PanLaw::EqualPower,
+            output_mode: OutputMode::Normalize,
+            auto_resample: false,
+            declick: false,
+            mute_ramp_seconds: DEFAULT_MUTE_RAMP_SECONDS,
+            master_gain: 1.0,
+            master_balance: 0.0,
+            output_ceiling: 1.0,
+            detect_clips: false,
+            last_mix: None,
+            last_clip_regions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn mono_track_is_panned_with_equal_power_gains() {
+        let mut mix = mixer(2, vec![track(&[0.5, -0.25], 1, 0.0, 1)]);
+        let g = FRAC_1_SQRT_2;
+        assert_eq!(
+            mix.mix_samples(4),
+            vec![0.0, 0.0, 0.5 * g, 0.5 * g, -0.25 * g, -0.25 * g, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn mono_track_panned_hard_left_only_reaches_left_channel() {
+        let mut mix = mixer(2, vec![track(&[0.5, 0.25], 1, -1.0, 0)]);
+        assert_eq!(mix.mix_samples(2), vec![0.5, 0.0, 0.25, 0.0]);
+    }
+
+    #[test]
+    fn stereo_track_is_summed_sample_accurately() {
+        let stereo = track(&[0.1, 0.2, 0.3, 0.4], 2, 0.0, 2);
+        let mut mix = mixer(2, vec![stereo]);
+        assert_eq!(
+            mix.mix_samples(5),
+            vec![0.0, 0.0, 0.0, 0.0, 0.1, 0.2, 0.3, 0.4, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn track_is_cut_at_the_end_of_the_mix() {
+        let mut mix = mixer(2, vec![track(&[0.1, 0.2, 0.3, 0.4], 2, 0.0, 2)]);
+        assert_eq!(mix.mix_samples(3), vec![0.0, 0.0, 0.0, 0.0, 0.1, 0.2]);
+    }
+
+    #[test]
+    fn stereo_balance_attenuates_the_opposite_side() {
+        let mut mix = mixer(2, vec![track(&[0.5, 0.5], 2, 0.5, 0)]);
+        assert_eq!(mix.mix_samples(1), vec![0.25, 0.5]);
+    }
+
+    #[test]
+    fn mono_and_stereo_tracks_are_summed() {
+        let mono = track(&[0.5, 0.5], 1, 1.0, 0);
+        let stereo = track(&[0.25, 0.125, 0.25, 0.125], 2, 0.0, 1);
+        let mut mix = mixer(2, vec![mono, stereo]);
+        assert_eq!(mix.mix_samples(3), vec![0.0, 0.5, 0.25, 0.625, 0.25, 0.125]);
+    }
+    #[test]
+    fn linear_pan_law_halves_a_centered_mono_track() {
+        let mut mix = mixer(2, vec![track(&[0.5, 1.0], 1, 0.0, 0)]);
+        mix.pan_law = PanLaw::Linear;
+        assert_eq!(mix.mix_samples(2), vec![0.25, 0.25, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn compromise_pan_law_is_minus_4_5_db_at_center() {
+        let (left, right) = channels::pan_gains(0.0, PanLaw::Compromise);
+        assert_eq!(left, right);
+        assert!((20.0 * left.log10() + 4.515).abs() < 0.01);
+    }
+    #[test]
+    fn normalize_scales_the_whole_mix_by_its_peak() {
+        let mut mix = mixer(1, vec![track(&[2.0, 0.5], 1, 0.0, 0)]);
+        assert_eq!(mix.mix_samples(2), vec![1.0, 0.25]);
+    }
+
+    #[test]
+    fn soft_clip_only_touches_samples_above_the_knee() {
+        let mut mix = mixer(1, vec![track(&[2.0, 0.5, -4.0], 1, 0.0, 0)]);
+        mix.output_mode = OutputMode::SoftClip;
+        let output = mix.mix_samples(3);
+        assert_eq!(output[1], 0.5);
+        assert!(output[0] > 0.95 && output[0] < 1.0);
+        assert!(output[2] < -0.99 && output[2] >= -1.0);
+    }
+
+    #[test]
+    fn hard_clip_clamps_to_full_scale() {
+        let mut mix = mixer(1, vec![track(&[2.0, 0.5, -4.0], 1, 0.0, 0)]);
+        mix.output_mode = OutputMode::HardClip;
+        assert_eq!(mix.mix_samples(3), vec![1.0, 0.5, -1.0]);
+    }
+    #[test]
+    fn linear_fades_ramp_the_track_edges() {
+        let mut faded = track(&[1.0; 6], 1, 0.0, 0);
+        faded.set_fades(2, 2);
+        let mut mix = mixer(1, vec![faded]);
+        assert_eq!(mix.mix_samples(6), vec![0.0, 0.5, 1.0, 1.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn overlapping_fades_are_shortened_proportionally() {
+        let fades = Fades {
+            fade_in: 6,
+            fade_out: 2,
+            curve: FadeCurve::Linear,
+        }
+        .clamped(4);
+        assert_eq!((fades.fade_in, fades.fade_out), (3, 1));
+    }
+    #[test]
+    fn gain_automation_is_interpolated_between_breakpoints() {
+        let mut ramped = track(&[1.0; 6], 1, 0.0, 0);
+        ramped.gain_automation = Automation::new(&[1, 3], &[0.0, 1.0]);
+        let mut mix = mixer(1, vec![ramped]);
+        assert_eq!(mix.mix_samples(6), vec![0.0, 0.0, 0.5, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn pan_automation_moves_a_mono_track_across_the_stereo_field() {
+        let mut moving = track(&[1.0; 3], 1, 0.0, 0);
+        moving.pan_automation = Automation::new(&[0, 2], &[-1.0, 1.0]);
+        let mut mix = mixer(2, vec![moving]);
+        mix.pan_law = PanLaw::Linear;
+        assert_eq!(mix.mix_samples(3), vec![1.0, 0.0, 0.5, 0.5, 0.0, 1.0]);
+    }
+    #[test]
+    fn muted_tracks_are_skipped_and_solo_silences_the_rest() {
+        let tracks = vec![
+            track(&[0.25], 1, 0.0, 0),
+            track(&[0.125], 1, 0.0, 0),
+            track(&[0.5], 1, 0.0, 0),
+        ];
+        let mut mix = mixer(1, tracks);
+        mix.tracks[0].muted = true;
+        assert_eq!(mix.mix_samples(1), vec![0.625]);
+
+        mix.tracks[1].soloed = true;
+        assert_eq!(mix.mix_samples(1), vec![0.125]);
+
+        // A muted track stays silent even when soloed
+        mix.tracks[0].soloed = true;
+        assert_eq!(mix.mix_samples(1), vec![0.125]);
+    }
+    #[test]
+    fn filters_pass_or_block_a_constant_signal() {
+        let spec = |kind| FilterSpec {
+            kind,
+            cutoff: 1000.0,
+            q: 0.707,
+            gain_db: 0.0,
+        };
+        let mut lowpassed = vec![0.5; 4800];
+        Biquad::new(spec(FilterKind::Lowpass), 48000).process(&mut lowpassed, 1);
+        assert!((lowpassed[4799] - 0.5).abs() < 1e-4);
+
+        let mut highpassed = vec![0.5; 4800];
+        Biquad::new(spec(FilterKind::Highpass), 48000).process(&mut highpassed, 1);
+        assert!(highpassed[4799].abs() < 1e-4);
+    }
+
+    #[test]
+    fn track_filter_keeps_separate_state_per_channel() {
+        // Left carries DC, right is silent: a shared state would leak
+        let mut filtered = track(&[1.0, 0.0].repeat(2000), 2, 0.0, 0);
+        filtered.filter = Some(FilterSpec {
+            kind: FilterKind::Lowpass,
+            cutoff: 500.0,
+            q: 0.707,
+            gain_db: 0.0,
+        });
+        let mut mix = mixer(2, vec![filtered]);
+        mix.output_mode = OutputMode::None;
+        let output = mix.mix_samples(2000);
+        assert!(output.iter().skip(1).step_by(2).all(|&s| s == 0.0));
+        assert!((output[3998] - 1.0).abs() < 1e-4);
+    }
+    fn sine(frequency: f32, amplitude: f32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| {
+                amplitude * (2.0 * std::f32::consts::PI * frequency * i as f32 / 48000.0).sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn full_scale_997_hz_sine_measures_minus_3_lufs() {
+        let loudness = loudness::integrated_loudness(&sine(997.0, 1.0, 96000), 1, 48000);
+        assert!((loudness + 3.01).abs() < 0.05, "{loudness}");
+    }
+
+    #[test]
+    fn silent_passages_are_gated_out() {
+        let mut samples = sine(997.0, 0.1, 96000);
+        samples.extend(vec![0.0; 96000]);
+        // Ungated the silence would halve the power (-26 LUFS); only the
+        // blocks straddling the transition still pull the level down
+        let loudness = loudness::integrated_loudness(&samples, 1, 48000);
+        assert!((loudness + 23.01).abs() < 0.5, "{loudness}");
+        assert_eq!(
+            loudness::integrated_loudness(&[0.0; 48000], 1, 48000),
+            f64::NEG_INFINITY
+        );
+    }
+    #[test]
+    fn normalize_to_lufs_hits_the_target_unless_the_peak_limits_it() {
+        let quiet = sine(997.0, 0.1, 96000);
+        let result = normalize_to_lufs(quiet.clone(), 1, 48000, -16.0, 0.0);
+        assert!(!result.peak_limited);
+        assert!((result.gain_db - 7.0).abs() < 0.05, "{}", result.gain_db);
+        let output_lufs = loudness::integrated_loudness(&result.samples, 1, 48000);
+        assert!((output_lufs + 16.0).abs() < 0.05, "{output_lufs}");
+
+        // Reaching -1 LUFS would need a peak of about +2 dBFS
+        let result = normalize_to_lufs(quiet, 1, 48000, -1.0, -1.0);
+        assert!(result.peak_limited);
+        let peak = loudness::true_peak(&result.samples, 1);
+        assert!((20.0 * peak.log10() + 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn true_peak_finds_peaks_between_samples() {
+        // A quarter-rate sine sampled at +-45 degrees peaks between samples
+        let samples: Vec<f32> = (0..400)
+            .map(|i| (std::f32::consts::FRAC_PI_2 * i as f32 + std::f32::consts::FRAC_PI_4).sin())
+            .collect();
+        let sample_peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!((sample_peak - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-4);
+        assert!(loudness::true_peak(&samples, 1) > 0.98);
+    }
+    #[test]
+    fn resampling_keeps_a_tone_and_the_channel_count() {
+        // 441 Hz in stereo, right channel inverted
+        let frames = 4410;
+        let samples: Vec<f32> = (0..frames)
+            .flat_map(|i| {
+                let s = (2.0 * std::f32::consts::PI * 441.0 * i as f32 / 44100.0).sin() * 0.5;
+                [s, -s]
+            })
+            .collect();
+        let output = resample::resample(&samples, 2, 44100, 48000);
+        assert_eq!(output.len(), 4800 * 2);
+        for frame in (100..4700).step_by(97) {
+            let expected =
+                (2.0 * std::f32::consts::PI * 441.0 * frame as f32 / 48000.0).sin() * 0.5;
+            assert!((output[frame * 2] - expected).abs() < 1e-3);
+            assert_eq!(output[frame * 2 + 1], -output[frame * 2]);
+        }
+    }
+
+    #[test]
+    fn auto_resample_converts_tracks_when_added() {
+        let mut mix = mixer(1, Vec::new());
+        mix.auto_resample = true;
+        let mut slow = track(&[0.5; 100], 1, 0.0, 0);
+        slow.set_sample_rate(24000).unwrap();
+        mix.add_track(slow);
+        assert_eq!(mix.tracks[0].samples.len(), 200);
+        assert!(mix.tracks[0].samples.iter().all(|s| (s - 0.5).abs() < 1e-3));
+    }
+    #[test]
+    fn enabling_auto_resample_converts_tracks_already_added() {
+        let mut slow = track(&[0.5; 100], 1, 0.0, 0);
+        slow.sample_rate = Some(24000);
+        let mut mix = mixer(1, vec![slow, track(&[0.5; 100], 1, 0.0, 0)]);
+        mix.set_auto_resample(false);
+        assert_eq!(mix.tracks[0].samples.len(), 100);
+        mix.set_auto_resample(true);
+        assert_eq!(mix.tracks[0].samples.len(), 200);
+        assert_eq!(mix.tracks[0].sample_rate, Some(48000));
+        assert_eq!(mix.tracks[1].samples.len(), 100);
+    }
+
+    #[test]
+    fn delay_repeats_with_feedback_and_rings_out() {
+        let output = effects::delay(&[1.0, 0.0], 2, 0.5, 0.5);
+        // 1 + ceil(ln 0.001 / ln 0.5) = 11 echoes of 2 samples
+        assert_eq!(output.len(), 2 + 2 * 11);
+        assert_eq!(&output[..7], &[0.5, 0.0, 0.5, 0.0, 0.25, 0.0, 0.125]);
+        assert_eq!(output.len(), effects::delay_output_len(2, 2, 0.5));
+    }
+
+    #[test]
+    fn delay_feedback_is_clamped_below_one() {
+        let output = effects::delay(&[1.0], 1, 4.0, 1.0);
+        assert_eq!(output[2], 0.95);
+        assert!(output.last().unwrap().abs() < 0.001);
+    }
+
+    #[test]
+    fn blend_pads_the_shorter_buffer_and_clamps_the_mix() {
+        let dry = [1.0, 1.0];
+        let wet = [0.0, 0.5, 0.25, 0.125];
+        assert_eq!(effects::blend(&dry, &wet, 0.5), [0.5, 0.75, 0.125, 0.0625]);
+        assert_eq!(effects::blend(&dry, &wet, -1.0), [1.0, 1.0, 0.0, 0.0]);
+        assert_eq!(effects::blend(&wet, &dry, 2.0), [1.0, 1.0, 0.0, 0.0]);
+    }
+    #[test]
+    fn equal_power_crossfade_keeps_the_level_halfway() {
+        let a = [1.0; 4];
+        let b = [1.0; 4];
+        let linear = envelope::crossfade(&a, &b, 2, FadeCurve::Linear);
+        assert_eq!(linear, vec![1.0, 1.0, 1.0, 1.0, 1.0, 1.0]);
+
+        let equal_power = envelope::crossfade(&a, &b, 2, FadeCurve::EqualPower);
+        // Halfway both sides are at -3 dB
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((equal_power[3] - 2.0 * half).abs() < 1e-6);
+    }
+    #[test]
+    fn fade_ramps_span_the_whole_curve() {
+        let ramp = envelope::fade_ramp(5, FadeCurve::Linear, FadeDirection::In);
+        assert_eq!(ramp, [0.0, 0.25, 0.5, 0.75, 1.0]);
+        let ramp = envelope::fade_ramp(3, FadeCurve::Exponential, FadeDirection::Out);
+        assert_eq!(ramp, [1.0, 0.25, 0.0]);
+        let ramp = envelope::fade_ramp(3, FadeCurve::Logarithmic, FadeDirection::In);
+        assert_eq!(ramp, [0.0, 0.75, 1.0]);
+        let ramp = envelope::fade_ramp(3, FadeCurve::SCurve, FadeDirection::In);
+        assert!(ramp[0].abs() < 1e-6 && (ramp[1] - 0.5).abs() < 1e-6 && ramp[2] == 1.0);
+        assert_eq!(
+            envelope::fade_ramp(1, FadeCurve::Linear, FadeDirection::Out),
+            [0.0]
+        );
+        assert!(envelope::fade_ramp(0, FadeCurve::Linear, FadeDirection::In).is_empty());
+    }
+    #[test]
+    fn waveform_peaks_keep_min_and_max_per_column() {
+        let stereo = [0.5, -0.1, -0.5, 0.2, 0.25, 0.0, 0.1, 0.3, -1.0, 1.0, 0.0];
+        assert_eq!(
+            loudness::waveform_peaks(&stereo, 2, 2),
+            [-0.5, 0.5, -0.1, 0.2, 0.1, 0.25, 0.0, 0.3, -1.0, -1.0, 1.0, 1.0]
+        );
+        assert!(loudness::waveform_peaks(&[], 2, 4).is_empty());
+    }
+    #[test]
+    fn spectrum_peaks_at_the_sine_frequency() {
+        // Bin 8 of a 64-point FFT
+        let sine: Vec<f32> = (0..64)
+            .map(|i| (2.0 * std::f32::consts::PI * 8.0 * i as f32 / 64.0).sin())
+            .collect();
+        for window in [Window::Hann, Window::Hamming, Window::Blackman] {
+            let bins = fft::spectrum(&sine, 64, window);
+            assert_eq!(bins.len(), 33);
+            assert!(bins[8].abs() < 0.01);
+            assert!(bins[20] < -60.0);
+        }
+        assert!(fft::spectrum(&[], 8, Window::Hann)
+            .iter()
+            .all(|&db| db == -120.0));
+    }
+    #[test]
+    fn fft_convolution_matches_direct_convolution() {
+        let samples: Vec<f32> = (0..1500)
+            .map(|i| ((i * 37 % 101) as f32 - 50.0) / 50.0)
+            .collect();
+        let ir: Vec<f32> = (0..300)
+            .map(|i| (-(i as f32) / 60.0).exp() * if i % 2 == 0 { 1.0 } else { -0.5 })
+            .collect();
+        let output = convolve::convolve(&samples, 2, &ir);
+        assert_eq!(output.len(), samples.len() + 2 * (ir.len() - 1));
+        for channel in 0..2 {
+            for frame in (0..750 + 299).step_by(7) {
+                let expected: f32 = (0..ir.len())
+                    .filter(|&k| k <= frame && frame - k < 750)
+                    .map(|k| ir[k] * samples[(frame - k) * 2 + channel])
+                    .sum();
+                assert!((output[frame * 2 + channel] - expected).abs() < 1e-3);
+            }
+        }
+        assert_eq!(convolve::convolve(&[0.5, -0.25], 1, &[1.0]), [0.5, -0.25]);
+        assert!(convolve::convolve(&[0.5], 1, &[]).is_empty());
+    }
+    #[test]
+    fn concat_joins_buffers_with_an_optional_declick() {
+        let buffers = vec![vec![1.0, -1.0, 1.0, -1.0], vec![], vec![0.0, 0.0, 0.5, 0.5]];
+        assert_eq!(
+            envelope::concat(&buffers, 2, 0),
+            [1.0, -1.0, 1.0, -1.0, 0.0, 0.0, 0.5, 0.5]
+        );
+        // One frame of overlap: the last frame of the first buffer is
+        // still at full gain when the crossfade starts
+        assert_eq!(
+            envelope::concat(&buffers, 2, 1),
+            [1.0, -1.0, 1.0, -1.0, 0.5, 0.5]
+        );
+        let declicked = envelope::concat(&[vec![1.0; 4], vec![0.0; 4]], 1, 2);
+        assert_eq!(declicked, [1.0, 1.0, 1.0, 0.5, 0.0, 0.0]);
+    }
+    #[test]
+    fn crossfade_without_fade_concatenates() {
+        let output = envelope::crossfade(&[1.0, 1.0], &[0.5, 0.5], 0, FadeCurve::Linear);
+        assert_eq!(output, vec![1.0, 1.0, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn crossfade_over_a_whole_buffer() {
+        let a = [1.0; 4];
+        let b = [0.0; 4];
+        let output = envelope::crossfade(&a, &b, 4, FadeCurve::Linear);
+        assert_eq!(output, vec![1.0, 0.75, 0.5, 0.25]);
+    }
+
+    #[test]
+    fn crossfade_clamps_fade_to_the_shorter_buffer() {
+        let a = [1.0; 3];
+        let b = [0.0; 5];
+        let output = envelope::crossfade(&a, &b, 10, FadeCurve::Linear);
+        assert_eq!(output.len(), 5);
+        for (sample, expected) in output.iter().zip([1.0, 2.0 / 3.0, 1.0 / 3.0]) {
+            assert!((sample - expected).abs() < 1e-6);
+        }
+        assert_eq!(&output[3..], &[0.0, 0.0]);
+
+        let empty = envelope::crossfade(&[], &b, 10, FadeCurve::EqualPower);
+        assert_eq!(empty, b.to_vec());
+    }
+    #[test]
+    fn mix_reports_the_peak_before_the_output_stage() {
+        let mut mix = mixer(1, vec![track(&[2.0, 0.5], 1, 0.0, 0)]);
+        assert_eq!(mix.last_mix_report(), None);
+        mix.mix_samples(2);
+        let report = mix.last_mix_report().unwrap();
+        assert_eq!(report.peak(), 2.0);
+        assert_eq!(report.scale(), 0.5);
+        assert!(report.clipped());
+
+        mix.output_mode = OutputMode::HardClip;
+        mix.tracks[0].gain = 0.25;
+        mix.mix_samples(2);
+        let report = mix.last_mix_report().unwrap();
+        assert_eq!(report.peak(), 0.5);
+        assert_eq!(report.scale(), 1.0);
+        assert!(!report.clipped());
+    }
+    #[test]
+    fn channel_counts_outside_one_to_six_are_rejected() {
+        // Every utility taking a raw count goes through checked_channels
+        for channels in 1..=6 {
+            assert_eq!(checked_channels(channels), Ok(channels as usize));
+        }
+        for channels in [0, 7, 8] {
+            assert_eq!(
+                checked_channels(channels),
+                Err(MixerError::InvalidChannelCount(channels))
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_track_parameters_are_rejected_and_pan_is_clamped() {
+        assert_eq!(checked_pan(1.5), Ok(1.0));
+        assert_eq!(checked_pan(-3.0), Ok(-1.0));
+        assert!(matches!(
+            checked_pan(f32::NAN),
+            Err(MixerError::InvalidPan(_))
+        ));
+        assert_eq!(checked_gain(0.5), Ok(0.5));
+        assert!(matches!(
+            checked_gain(f32::INFINITY),
+            Err(MixerError::InvalidGain(_))
+        ));
+        assert_eq!(
+            checked_sample_rate(0),
+            Err(MixerError::InvalidSampleRate(0))
+        );
+        assert_eq!(checked_channels(channels::MAX_CHANNELS), Ok(6));
+        assert_eq!(checked_channels(0), Err(MixerError::InvalidChannelCount(0)));
+        assert_eq!(
+            checked_channels(7).unwrap_err().to_string(),
+            "invalid channel count 7: expected 1 to 6"
+        );
+        let bulk = |start| descriptor_track(3, vec![0.5], ChannelLayout::Mono, 1.0, 0.0, start);
+        assert_eq!(bulk(480.0).map(|t| t.start_sample), Ok(480));
+        for start in [-1.0, f64::NAN] {
+            assert_eq!(
+                bulk(start).err(),
+                Some(MixerError::InvalidTrackDescriptor {
+                    index: 3,
+                    reason: "start must be 0 or more"
+                })
+            );
+        }
+        assert_eq!(
+            fade_curve("cubic").unwrap_err().to_string(),
+            "unknown fade curve \"cubic\": expected \"linear\", \"equal_power\", \"exponential\", \"logarithmic\" or \"s_curve\""
+        );
+    }
+    #[test]
+    fn required_duration_covers_the_last_track_end() {
+        let mut mix = mixer(2, Vec::new());
+        assert_eq!(mix.track_count(), 0);
+        assert_eq!(mix.required_duration_samples(), 0);
+
+        mix.add_track(track(&[0.1; 6], 2, 0.0, 4));
+        mix.add_track(track(&[0.1; 5], 1, 0.0, 1));
+        assert_eq!(mix.track_count(), 2);
+        assert_eq!(mix.required_duration_samples(), 7);
+    }
+    #[test]
+    fn tracks_can_be_removed_and_replaced_by_index() {
+        let mut mix = mixer(1, Vec::new());
+        mix.add_track(track(&[0.1], 1, 0.0, 0));
+        mix.add_track(track(&[0.2], 1, 0.0, 0));
+        mix.add_track(track(&[0.4], 1, 0.0, 0));
+
+        assert!(mix.remove_track(1));
+        assert!(!mix.remove_track(2));
+        assert_eq!(mix.track_count(), 2);
+        assert!((mix.mix_samples(1)[0] - 0.5).abs() < 1e-6);
+
+        assert!(mix.replace_track(0, track(&[0.3], 1, 0.0, 0)));
+        assert!(!mix.replace_track(5, track(&[0.3], 1, 0.0, 0)));
+        assert!((mix.mix_samples(1)[0] - 0.7).abs() < 1e-6);
+    }
+    #[test]
+    fn track_gain_pan_and_start_can_be_changed_in_place() {
+        let mut mix = mixer(2, vec![track(&[0.5], 1, 0.0, 0)]);
+        mix.set_track_gain(0, 2.0).unwrap();
+        mix.set_track_pan(0, 4.0).unwrap();
+        mix.set_track_start(0, 1).unwrap();
+        mix.output_mode = OutputMode::None;
+        assert_eq!(mix.mix_samples(2), vec![0.0, 0.0, 0.0, 1.0]);
+
+        assert!(matches!(
+            mix.track_mut(1),
+            Err(MixerError::TrackOutOfRange { index: 1, count: 1 })
+        ));
+    }
+    #[test]
+    fn tracks_take_over_sample_buffers_without_copying() {
+        let mut buffer = SampleBuffer::new(4);
+        let ptr = buffer.ptr();
+        buffer.samples.copy_from_slice(&[0.1, 0.2, 0.3, 0.4]);
+        let track = AudioTrack::from_wasm_slice(buffer, 2, 1.0, 0.0, 0).unwrap();
+        assert_eq!(track.samples.as_ptr(), ptr as *const f32);
+        assert_eq!(track.samples, vec![0.1, 0.2, 0.3, 0.4]);
+
+        assert!(matches!(
+            AudioTrack::from_samples(Vec::new(), ChannelLayout::Mono, 1.0, 0.0, 0),
+            Err(MixerError::EmptyBuffer)
+        ));
+    }
+    #[test]
+    fn mix_ranges_join_into_the_full_mix() {
+        let mut filtered = track(&sine(440.0, 0.4, 300), 1, 0.3, 20);
+        filtered.filter = Some(FilterSpec {
+            kind: FilterKind::Lowpass,
+            cutoff: 1000.0,
+            q: 0.707,
+            gain_db: 0.0,
+        });
+        filtered.fades.fade_in = 50;
+        let mut panned = track(&sine(220.0, 0.4, 200), 1, 0.0, 150);
+        panned.pan_automation = Automation::new(&[0, 200], &[-1.0, 1.0]);
+        let mut mix = mixer(2, vec![filtered, panned]);
+
+        let full = mix.mix_samples(400);
+        let mut joined = Vec::new();
+        for start in (0..400).step_by(64) {
+            joined.extend(mix.mix_range_samples(start, 64.min(400 - start)));
+        }
+        assert_eq!(joined.len(), full.len());
+        for (a, b) in joined.iter().zip(&full) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn mix_range_hard_clips_instead_of_normalizing() {
+        let mix = mixer(1, vec![track(&[2.0, 0.5], 1, 0.0, 0)]);
+        assert_eq!(mix.mix_range_samples(0, 2), vec![1.0, 0.5]);
+        assert_eq!(mix.mix_range_samples(1, 3), vec![0.5, 0.0, 0.0]);
+    }
+    #[test]
+    fn vector_kernels_match_the_scalar_loops_including_the_tail() {
+        let src: Vec<f32> = (0..11).map(|i| i as f32 * 0.1).collect();
+        let mut expected = vec![0.5f32; 11];
+        simd::scalar::mul_add(&mut expected, &src, 0.3);
+        let mut actual = vec![0.5f32; 11];
+        simd::mul_add(&mut actual, &src, 0.3);
+        assert_eq!(actual, expected);
+
+        simd::scalar::scale(&mut expected, 1.5);
+        simd::scale(&mut actual, 1.5);
+        assert_eq!(actual, expected);
+    }
+
+    /// Ten mono tracks over five minutes of stereo output. Run with
+    /// `cargo test --release -- --ignored --nocapture`, or on wasm32 with
+    /// `--features simd` and `-C target-feature=+simd128` to compare the
+    /// SIMD summation against the scalar loop.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_ten_track_five_minute_mix() {
+        use std::time::Instant;
+
+        let frames = 48000 * 300;
+        let tracks = (0..10)
+            .map(|i| {
+                let mut t = track(&sine(110.0 * (i + 1) as f32, 0.05, frames), 1, 0.0, 0);
+                t.pan = i as f32 / 4.5 - 1.0;
+                t.gain = 0.8;
+                t
+            })
+            .collect();
+        let mut mix = mixer(2, tracks);
+
+        let started = Instant::now();
+        let output = mix.mix_samples(frames);
+        println!("mix: {:?}", started.elapsed());
+
+        let mut sum = vec![0.0f32; output.len()];
+        let started = Instant::now();
+        for _ in 0..10 {
+            simd::scalar::mul_add(&mut sum, &output, 0.8);
+        }
+        println!("scalar summation: {:?}", started.elapsed());
+
+        let started = Instant::now();
+        for _ in 0..10 {
+            simd::mul_add(&mut sum, &output, 0.8);
+        }
+        println!("selected summation: {:?}", started.elapsed());
+    }
+
+    #[test]
+    fn stereo_width_collapses_keeps_or_widens_the_side() {
+        let stereo = [0.6, 0.2, -0.4, 0.4, 0.3];
+        let mut mono = stereo;
+        effects::stereo_width(&mut mono, 0.0);
+        assert_eq!(mono, [0.4, 0.4, 0.0, 0.0, 0.3]);
+
+        let mut same = stereo;
+        effects::stereo_width(&mut same, 1.0);
+        assert_eq!(same, stereo);
+
+        let mut wide = stereo;
+        effects::stereo_width(&mut wide, 2.0);
+        assert!((wide[0] - 0.8).abs() < 1e-6 && wide[1].abs() < 1e-6);
+        assert_eq!(&wide[2..], &[-0.8, 0.8, 0.3]);
+
+        let mut clamped = [1.0, -1.0];
+        effects::stereo_width(&mut clamped, 3.0);
+        assert_eq!(clamped, [1.0, -1.0]);
+    }
+    #[test]
+    fn gate_silences_noise_and_releases_smoothly() {
+        // Quiet noise floor, a loud burst, then the noise floor again
+        let mut input = vec![0.001f32; 100];
+        input.extend(std::iter::repeat_n(0.5, 100));
+        input.extend(std::iter::repeat_n(0.001, 400));
+        let output = dynamics::gate(&input, -40.0, 10, 50);
+
+        assert!(output[..100].iter().all(|&s| s == 0.0));
+        // Attack ramps in over 10 samples
+        assert!((output[100] - 0.05).abs() < 1e-6);
+        assert_eq!(output[150], 0.5);
+        // The tail is held while the envelope decays, then ramped down
+        assert!(output[200] > 0.0);
+        let released = output[200..].iter().position(|&s| s == 0.0).unwrap();
+        // envelope falls below -40 dB after ln(0.5 / 0.01) * 50 = 196 samples
+        assert!((196..=250).contains(&released));
+        assert!(output[200..].windows(2).all(|pair| pair[1] <= pair[0]));
+    }
+    #[test]
+    fn compressor_reduces_the_excess_by_the_ratio() {
+        let settings = Compressor {
+            threshold_db: -20.0,
+            ratio: 4.0,
+            attack_ms: 0.0,
+            release_ms: 0.0,
+            makeup_db: 0.0,
+        };
+        // 0 dBFS is 20 dB over, reduced by 15 dB to -15 dBFS
+        let (output, reduction) = dynamics::compress(&[1.0, 0.05], settings, 48000);
+        assert!((reduction[0] - 15.0).abs() < 1e-4);
+        assert!((output[0] - db_to_gain(-15.0)).abs() < 1e-5);
+        assert_eq!(reduction[1], 0.0);
+        assert_eq!(output[1], 0.05);
+
+        // With attack and release the reduction builds up and decays
+        let settings = Compressor {
+            attack_ms: 1.0,
+            release_ms: 10.0,
+            ..settings
+        };
+        let mut input = vec![1.0f32; 480];
+        input.extend(vec![0.05f32; 480]);
+        let (_, reduction) = dynamics::compress(&input, settings, 48000);
+        assert!(reduction[0] < 1.0);
+        assert!((reduction[479] - 15.0).abs() < 0.01);
+        // One release time constant later, 1/e of the reduction is left
+        assert!(reduction[480] > 14.0);
+        assert!((reduction[959] - 15.0 / std::f32::consts::E).abs() < 0.05);
+    }
+    #[test]
+    fn envelope_follows_the_louder_channel_with_attack_and_release() {
+        // Instant attack and release track the frame peaks exactly
+        let stereo = [0.5, -0.75, 0.0, 0.25];
+        let peaks = dynamics::frame_peaks(&stereo, 2);
+        assert_eq!(dynamics::envelope(peaks, 0.0, 0.0, 48000), [0.75, 0.25]);
+
+        // 1 ms attack and 10 ms release at 48 kHz: 48 and 480 samples
+        let mut input = vec![1.0f32; 48];
+        input.extend(vec![0.0f32; 480]);
+        let envelope = dynamics::envelope(input.into_iter(), 1.0, 10.0, 48000);
+        assert!((envelope[47] - (1.0 - 1.0 / std::f32::consts::E)).abs() < 0.01);
+        assert!((envelope[527] - envelope[47] / std::f32::consts::E).abs() < 0.01);
+        assert!(envelope[48..].windows(2).all(|pair| pair[1] < pair[0]));
+    }
+    #[test]
+    fn peak_levels_are_measured_per_channel() {
+        let stereo = [0.5, -0.1, -0.75, 0.2, 0.25, 0.0];
+        assert_eq!(loudness::peak_levels(&stereo, 2), vec![0.75, 0.2]);
+
+        // A quarter-rate sine sampled at +-45 degrees peaks between samples
+        let s = FRAC_1_SQRT_2;
+        let mut samples = Vec::new();
+        for _ in 0..32 {
+            samples.extend([s, 0.1, s, 0.1, -s, 0.1, -s, 0.1]);
+        }
+        let true_peaks = loudness::true_peak_levels(&samples, 2);
+        assert!((true_peaks[0] - 1.0).abs() < 0.02);
+        assert!((true_peaks[1] - 0.1).abs() < 0.02);
+    }
+    #[test]
+    fn pcm_conversion_clamps_rounds_and_dithers() {
+        let samples = [0.0, 1.0, -1.0, 2.0, 0.5, -0.25];
+        assert_eq!(
+            pcm::quantize(&samples, 16, false),
+            vec![0, 32767, -32767, 32767, 16384, -8192]
+        );
+        assert_eq!(pcm::quantize(&[1.0], 24, false), vec![8388607]);
+
+        // Dither stays within +-1 LSB and averages out
+        let quiet = vec![0.25 / 32767.0; 10000];
+        let dithered = pcm::quantize(&quiet, 16, true);
+        assert!(dithered.iter().all(|v| (-1..=1).contains(v)));
+        let mean = dithered.iter().sum::<i32>() as f32 / dithered.len() as f32;
+        assert!((mean - 0.25).abs() < 0.05);
+    }
+    #[test]
+    fn wav_files_have_consistent_chunk_sizes() {
+        let u32_at =
+            |bytes: &[u8], at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let samples = [0.5, -0.5, 1.0, -1.0, 0.25];
+
+        let wav16 = wav::encode(&samples, 48000, 2, WavFormat::Pcm16);
+        assert_eq!(&wav16[..4], b"RIFF");
+        assert_eq!(u32_at(&wav16, 4) as usize, wav16.len() - 8);
+        assert_eq!(&wav16[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&wav16, 16), 16);
+        assert_eq!(u32_at(&wav16, 28), 48000 * 4);
+        assert_eq!(&wav16[36..40], b"data");
+        // The incomplete last frame is dropped
+        assert_eq!(u32_at(&wav16, 40), 8);
+        assert_eq!(&wav16[44..48], &[0x00, 0x40, 0x00, 0xc0]);
+
+        let wav24 = wav::encode(&samples[..1], 44100, 1, WavFormat::Pcm24);
+        assert_eq!(u32_at(&wav24, 40), 3);
+        assert_eq!(&wav24[44..47], &[0x00, 0x00, 0x40]);
+        // Odd data is padded to an even chunk size
+        assert_eq!(wav24.len(), 48);
+        assert_eq!(u32_at(&wav24, 4) as usize, wav24.len() - 8);
+
+        let wav32 = wav::encode(&samples[..4], 48000, 2, WavFormat::Float32);
+        assert_eq!(u16::from_le_bytes([wav32[20], wav32[21]]), 3);
+        assert_eq!(&wav32[38..42], b"fact");
+        assert_eq!(u32_at(&wav32, 46), 2);
+        assert_eq!(&wav32[50..54], b"data");
+        assert_eq!(u32_at(&wav32, 4) as usize, wav32.len() - 8);
+        assert_eq!(&wav32[58..62], &0.5f32.to_le_bytes());
+    }
+    #[test]
+    fn declick_ramps_track_edges_over_3_ms() {
+        let mut faded = track(&[1.0; 400], 1, 0.0, 10);
+        faded.fades.fade_out = 200;
+        let mut mix = mixer(1, vec![faded]);
+        mix.declick = true;
+        let output = mix.mix_samples(410);
+
+        // 3 ms at 48 kHz is 144 frames
+        assert_eq!(output[10], 0.0);
+        assert!((output[10 + 72] - 0.5).abs() < 1e-6);
+        assert_eq!(output[10 + 144], 1.0);
+        // The longer fade-out set on the track wins
+        assert!((output[409 - 100] - 0.5).abs() < 1e-6);
+    }
+    #[test]
+    fn trimmed_frames_are_skipped_before_placing_the_track() {
+        let mut stereo = track(&[0.1, 0.2, 0.3, 0.4, 0.5, 0.6], 2, 0.0, 1);
+        stereo.trim_start = 2;
+        let mut mix = mixer(2, vec![stereo]);
+        assert_eq!(mix.required_duration_samples(), 2);
+        assert_eq!(mix.mix_samples(3), vec![0.0, 0.0, 0.5, 0.6, 0.0, 0.0]);
+
+        // Trimming past the end leaves nothing to mix
+        mix.set_track_trim_start(0, 10).unwrap();
+        assert_eq!(mix.required_duration_samples(), 1);
+        assert_eq!(mix.mix_samples(2), vec![0.0; 4]);
+    }
+    /// Frequency of a tone from its rising zero crossings
+    fn tone_frequency(samples: &[f32], sample_rate: f32) -> f32 {
+        let crossings = samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count();
+        crossings as f32 * sample_rate / samples.len() as f32
+    }
+
+    #[test]
+    fn time_stretch_changes_duration_but_not_pitch() {
+        let tone = sine(440.0, 0.5, 48000);
+        let stretched = stretch::time_stretch(&tone, 1, 48000, 1.5);
+        assert_eq!(stretched.len(), 72000);
+        let frequency = tone_frequency(&stretched[4800..67200], 48000.0);
+        assert!((frequency - 440.0).abs() < 10.0, "{frequency}");
+
+        let squeezed = stretch::time_stretch(&tone, 1, 48000, 0.5);
+        assert_eq!(squeezed.len(), 24000);
+        let frequency = tone_frequency(&squeezed[2400..21600], 48000.0);
+        assert!((frequency - 440.0).abs() < 10.0, "{frequency}");
+    }
+
+    #[test]
+    fn pitch_shift_changes_pitch_but_not_duration() {
+        let tone: Vec<f32> = sine(440.0, 0.5, 24000)
+            .into_iter()
+            .flat_map(|s| [s, s])
+            .collect();
+        let shifted = stretch::pitch_shift(&tone, 2, 48000, 12.0);
+        assert_eq!(shifted.len(), tone.len());
+        let left: Vec<f32> = shifted.iter().step_by(2).copied().collect();
+        let frequency = tone_frequency(&left[2400..21600], 48000.0);
+        assert!((frequency - 880.0).abs() < 20.0, "{frequency}");
+    }
+    #[test]
+    fn eq_bands_boost_and_cut_in_order() {
+        let band = |kind, cutoff, gain_db| FilterSpec {
+            kind,
+            cutoff,
+            q: 1.0,
+            gain_db,
+        };
+        let peak = |samples: &[f32]| samples[24000..].iter().fold(0.0f32, |p, s| p.max(s.abs()));
+
+        // +6 dB peaking at the tone's frequency doubles it
+        let mut tone = sine(1000.0, 0.25, 48000);
+        filter::process_chain(
+            &[band(FilterKind::Peaking, 1000.0, 6.0)],
+            &mut tone,
+            1,
+            48000,
+        );
+        assert!((peak(&tone) - 0.5).abs() < 0.01);
+
+        // Shelves move the band they cover and leave the other alone
+        let bands = [
+            band(FilterKind::LowShelf, 200.0, -12.0),
+            band(FilterKind::HighShelf, 8000.0, 6.0),
+        ];
+        let mut low = sine(50.0, 0.5, 48000);
+        filter::process_chain(&bands, &mut low, 1, 48000);
+        assert!((peak(&low) - 0.5 * db_to_gain(-12.0)).abs() < 0.01);
+        let mut mid = sine(1500.0, 0.5, 48000);
+        filter::process_chain(&bands, &mut mid, 1, 48000);
+        assert!((peak(&mid) - 0.5).abs() < 0.03);
+
+        assert_eq!(
+            FilterKind::parse_band("low_shelf"),
+            Some(FilterKind::LowShelf)
+        );
+        assert_eq!(FilterKind::parse("peaking"), None);
+    }
+    #[test]
+    fn polarity_and_delay_line_up_a_second_microphone() {
+        let close = track(&[0.0, 0.5, 0.25, 0.0], 1, 0.0, 0);
+        // The far microphone hears the same sound two frames later with
+        // its polarity flipped
+        let mut far = track(&[0.0, 0.0, 0.0, -0.5, -0.25, 0.0], 1, 0.0, 0);
+        far.invert_polarity = true;
+        far.delay_samples = -2;
+        let mut mix = mixer(1, vec![close, far]);
+        assert_eq!(mix.mix_samples(4), vec![0.0, 1.0, 0.5, 0.0]);
+        assert_eq!(mix.required_duration_samples(), 4);
+
+        mix.tracks[1].delay_samples = 3;
+        assert_eq!(mix.required_duration_samples(), 9);
+    }
+    #[test]
+    fn render_reports_progress_and_matches_a_single_pass() {
+        let mut filtered = track(&sine(440.0, 0.6, 150_000), 2, 0.2, 1000);
+        filtered.filter = Some(FilterSpec {
+            kind: FilterKind::Highpass,
+            cutoff: 200.0,
+            q: 0.707,
+            gain_db: 0.0,
+        });
+        let mut moving = track(&sine(330.0, 0.6, 200_000), 1, 0.0, 0);
+        moving.pan_automation = Automation::new(&[0, 200_000], &[-1.0, 1.0]);
+        let mut mix = mixer(2, vec![filtered, moving]);
+
+        let single = {
+            let mut output = vec![0.0f32; 180_000 * 2];
+            for track in &mix.tracks {
+                let samples = mix.source(track, 180_000);
+                mix.mix_track(track, &samples, &mut output, 0);
+            }
+            mix.output_mode.apply(&mut output, mix.output_ceiling);
+            output
+        };
+        let mut reported = Vec::new();
+        let rendered = mix.render_samples(180_000, |fraction| reported.push(fraction));
+        assert_eq!(rendered, single);
+        assert_eq!(reported.len(), 3);
+        assert_eq!(reported.last(), Some(&1.0));
+        assert!(reported.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+    #[test]
+    fn clip_detection_records_regions_across_blocks() {
+        let frames = RENDER_BLOCK_FRAMES + 10;
+        let mut samples = vec![0.5f32; frames * 2];
+        // Right channel only, then a region spanning the block boundary
+        samples[2 * 3 + 1] = -1.5;
+        for frame in RENDER_BLOCK_FRAMES - 2..RENDER_BLOCK_FRAMES + 3 {
+            samples[frame * 2] = 1.2;
+        }
+        let mut mix = mixer(2, vec![track(&samples, 2, 0.0, 0)]);
+        mix.mix_samples(frames);
+        assert!(mix.last_clip_regions.is_empty());
+
+        mix.detect_clips = true;
+        mix.mix_samples(frames);
+        let boundary = RENDER_BLOCK_FRAMES as u32;
+        assert_eq!(mix.last_clip_regions, [3, 4, boundary - 2, boundary + 3]);
+
+        mix.tracks[0].gain = 0.5;
+        mix.mix_samples(frames);
+        assert!(mix.last_clip_regions.is_empty());
+    }
+    #[test]
+    fn master_gain_and_balance_apply_before_the_output_stage() {
+        let mut mix = mixer(2, vec![track(&[0.8, 0.8, 0.4, 0.4], 2, 0.0, 0)]);
+        mix.set_master_gain(2.0).unwrap();
+        mix.set_master_balance(0.5).unwrap();
+        mix.output_mode = OutputMode::None;
+        assert_eq!(mix.mix_samples(2), [0.8, 1.6, 0.4, 0.8]);
+        assert_eq!(mix.mix_range_samples(1, 1), [0.4, 0.8]);
+
+        mix.output_mode = OutputMode::Normalize;
+        mix.mix_samples(2);
+        assert_eq!(mix.last_mix_report().unwrap().peak(), 1.6);
+
+        let mut mono = mixer(1, vec![track(&[0.5], 1, 0.0, 0)]);
+        mono.set_master_balance(-1.0).unwrap();
+        assert_eq!(mono.mix_samples(1), [0.5]);
+    }
+    #[test]
+    fn interleave_and_deinterleave_round_trip() {
+        let planes = vec![vec![1.0, 2.0, 3.0], vec![-1.0, -2.0]];
+        let interleaved = channels::interleave(&planes);
+        assert_eq!(interleaved, [1.0, -1.0, 2.0, -2.0, 3.0, 0.0]);
+        assert_eq!(
+            channels::deinterleave(&interleaved, 2),
+            [vec![1.0, 2.0, 3.0], vec![-1.0, -2.0, 0.0]]
+        );
+        assert_eq!(
+            channels::deinterleave(&interleaved[..5], 3),
+            [vec![1.0], vec![-1.0], vec![2.0]]
+        );
+        assert!(channels::interleave(&[]).is_empty());
+    }
+    #[test]
+    fn silence_is_trimmed_and_gaps_are_found_with_hysteresis() {
+        let frames = [0.0, 0.0, 0.5, 0.0009, 0.0005, 0.0, 0.5, 0.0, 0.5, 0.0, 0.0];
+        let stereo: Vec<f32> = frames.iter().flat_map(|&s| [0.0, s]).collect();
+        assert_eq!(dynamics::sound_span(&stereo, 2, -60.0), Some((2, 9)));
+        assert_eq!(dynamics::sound_span(&[0.0; 8], 2, -60.0), None);
+        // 0.0009 is below -60 dBFS but above -63 dBFS, so the first gap
+        // only starts at 0.0005; the trailing silence is not a gap
+        assert_eq!(
+            dynamics::silence_regions(&stereo, 2, -60.0, 1),
+            [4, 6, 7, 8]
+        );
+        assert_eq!(dynamics::silence_regions(&stereo, 2, -60.0, 2), [4, 6]);
+    }
+    #[test]
+    fn downmix_and_upmix_between_mono_and_stereo() {
+        let stereo = [0.5, 0.5, 1.0, 0.0, 0.25];
+        assert_eq!(
+            channels::downmix_to_mono(&stereo, PanLaw::Linear),
+            [0.5, 0.5]
+        );
+        let mono = channels::downmix_to_mono(&stereo, PanLaw::EqualPower);
+        assert!((mono[0] - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+        assert_eq!(
+            channels::upmix_to_stereo(&[0.5, -0.25]),
+            [0.5, 0.5, -0.25, -0.25]
+        );
+    }
+    #[test]
+    fn ring_buffer_wraps_and_drops_the_oldest_samples() {
+        let mut ring = stream::RingBuffer::new(4);
+        assert_eq!(ring.push(&[1.0, 2.0, 3.0]), 0);
+        let mut out = [0.0; 2];
+        assert_eq!(ring.pop_into(&mut out), 2);
+        assert_eq!(out, [1.0, 2.0]);
+        assert_eq!(ring.push(&[4.0, 5.0, 6.0, 7.0]), 1);
+        let mut out = [0.0; 6];
+        assert_eq!(ring.pop_into(&mut out), 4);
+        assert_eq!(out, [4.0, 5.0, 6.0, 7.0, 0.0, 0.0]);
+        assert_eq!(ring.push(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]), 2);
+        assert_eq!(ring.len(), 4);
+    }
+
+    #[test]
+    fn streaming_mixer_pans_tracks_and_underruns_to_silence() {
+        let mut mix = StreamingMixer::new(2, 4).unwrap();
+        mix.tracks
+            .push(StreamTrack::new(7, ChannelLayout::Mono, 0.5, -1.0, 4));
+        mix.tracks
+            .push(StreamTrack::new(9, ChannelLayout::Stereo, 1.0, 0.0, 4));
+        assert_eq!(mix.track_mut(7).unwrap().push(&[1.0, 1.0, 1.0]), 0);
+        assert_eq!(mix.track_mut(9).unwrap().push(&[0.25, 0.5, 0.25]), 0);
+        assert_eq!(mix.pull_samples(2), [0.75, 0.5, 0.5, 0.0]);
+        assert_eq!(mix.pull_samples(2), [0.5, 0.0, 0.0, 0.0]);
+        assert_eq!(mix.pull_samples(1), [0.0, 0.0]);
+
+        assert_eq!(mix.track_mut(9).unwrap().push(&[0.0; 12]), 2);
+        assert_eq!(mix.track_mut(1).err(), Some(MixerError::UnknownTrackId(1)));
+        assert!(mix.remove_track(7));
+        assert!(!mix.remove_track(7));
+    }
+    #[test]
+    fn mute_changes_ramp_instead_of_jumping() {
+        assert_eq!(envelope::mute_gain(&[2, 6], 4, 0), 1.0);
+        assert_eq!(envelope::mute_gain(&[2, 6], 4, 3), 0.75);
+        assert_eq!(envelope::mute_gain(&[2, 6], 4, 6), 0.0);
+        assert_eq!(envelope::mute_gain(&[2, 6], 4, 8), 0.5);
+        assert_eq!(envelope::mute_gain(&[2, 6], 4, 20), 1.0);
+        // Unmuting halfway through the fade turns around from there
+        assert_eq!(envelope::mute_gain(&[0, 2], 4, 2), 0.5);
+        assert_eq!(envelope::mute_gain(&[0, 2], 4, 3), 0.75);
+        assert_eq!(envelope::mute_gain(&[3], 0, 3), 0.0);
+
+        let mut toggled = track(&[1.0; 8], 1, 0.0, 0);
+        toggled.mute_changes = vec![2];
+        let mut mix = mixer(1, vec![toggled]);
+        // A 2 ms ramp at 2 kHz spans 4 frames
+        mix.sample_rate = 2000;
+        mix.mute_ramp_seconds = 0.002;
+        assert_eq!(
+            mix.mix_samples(8),
+            [1.0, 1.0, 1.0, 0.75, 0.5, 0.25, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn looped_tracks_repeat_up_to_the_loop_point() {
+        assert_eq!(
+            envelope::repeat(&[1.0, 2.0, 3.0, 4.0], 2, 5, 0),
+            [1.0, 2.0, 3.0, 4.0, 1.0, 2.0, 3.0, 4.0, 1.0, 2.0]
+        );
+        // Each loop point blends the tail into the head over two frames
+        assert_eq!(
+            envelope::repeat(&[0.0, 1.0, 2.0, 4.0], 1, 7, 2),
+            [
+                0.0,
+                1.0,
+                2.0,
+                4.0 * 0.5 + 1.0 * 0.5,
+                2.0,
+                4.0 * 0.5 + 1.0 * 0.5,
+                2.0
+            ]
+        );
+
+        let mut bed = track(&[0.1, 0.2, 0.3], 1, 0.0, 2);
+        bed.loop_to_sample = Some(10);
+        let mut mix = mixer(1, vec![bed]);
+        mix.output_mode = OutputMode::None;
+        assert_eq!(mix.required_duration_samples(), 10);
+        assert_eq!(
+            mix.mix_samples(12),
+            [0.0, 0.0, 0.1, 0.2, 0.3, 0.1, 0.2, 0.3, 0.1, 0.2, 0.0, 0.0]
+        );
+        // A mix shorter than the loop stops at its own end
+        assert_eq!(mix.mix_samples(4), [0.0, 0.0, 0.1, 0.2]);
+        // The fade out ends at the loop point, not the end of the samples
+        mix.tracks[0].fades.fade_out = 2;
+        assert_eq!(mix.mix_samples(10)[8..], [0.1 * 0.5, 0.0]);
+    }
+
+    #[test]
+    fn dc_offset_is_removed_per_channel() {
+        let mut stereo = [0.6, -0.1, 0.2, -0.3, 0.4, -0.2, 0.9];
+        effects::remove_dc_offset(&mut stereo, 2);
+        let expected = [0.2, 0.1, -0.2, -0.1, 0.0, 0.0, 0.9];
+        for (actual, expected) in stereo.iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-6, "{stereo:?}");
+        }
+    }
+
+    #[test]
+    fn disabling_normalization_returns_the_raw_sum() {
+        let loud = || track(&[0.75, 0.75], 1, 0.0, 0);
+        let mut mix = mixer(1, vec![loud(), loud()]);
+        assert_eq!(mix.mix_samples(2), [1.0, 1.0]);
+        mix.set_normalize(false);
+        assert_eq!(mix.mix_samples(2), [1.5, 1.5]);
+        assert!(!mix.last_mix.unwrap().clipped);
+        mix.set_normalize(true);
+        assert_eq!(mix.mix_samples(2), [1.0, 1.0]);
+    }
+
+    #[test]
+    fn wav_files_decode_what_encode_wrote() {
+        let samples = [0.5, -0.25, 1.0, -1.0, 0.0, 0.125];
+        for (format, tolerance) in [
+            (WavFormat::Pcm16, 1.0 / 32_768.0),
+            (WavFormat::Pcm24, 1.0 / 8_388_608.0),
+            (WavFormat::Float32, 0.0),
+        ] {
+            let decoded = wav::decode(&wav::encode(&samples, 44100, 2, format)).unwrap();
+            assert_eq!((decoded.sample_rate, decoded.channels), (44100, 2));
+            assert_eq!(decoded.samples.len(), samples.len());
+            for (a, b) in decoded.samples.iter().zip(samples) {
+                assert!((a - b).abs() <= tolerance, "{format:?}: {a} != {b}");
+            }
+        }
+
+        let mut file = wav::encode(&samples, 8000, 1, WavFormat::Pcm16);
+        assert_eq!(
+            wav::decode(&file[..12]),
+            Err(MixerError::InvalidWav("missing fmt chunk"))
+        );
+        // A data size left at its maximum by an unfinished recording
+        file[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(wav::decode(&file).unwrap().samples.len(), 6);
+        file[20..22].copy_from_slice(&2u16.to_le_bytes());
+        assert_eq!(wav::decode(&file), Err(MixerError::UnsupportedWavFormat(2)));
+        assert_eq!(
+            wav::decode(b"RIFX\0\0\0\0WAVE"),
+            Err(MixerError::InvalidWav("not a RIFF/WAVE file"))
+        );
+    }
+
+    #[test]
+    fn detailed_mix_reports_the_normalization() {
+        let loud = || track(&[0.8, -0.2, 0.5], 1, 0.0, 0);
+        let mut mix = mixer(2, vec![loud(), loud()]);
+        let result = mix.mix_result(4);
+        assert_eq!(result.frames, 4);
+        assert_eq!(result.samples.len(), 8);
+        assert_eq!(result.report.peak, 1.6 * FRAC_1_SQRT_2);
+        assert!(result.report.clipped);
+        assert_eq!(result.report.scale, 1.0 / result.report.peak);
+        assert_eq!(Some(result.report), mix.last_mix);
+    }
+
+    #[test]
+    fn sidechain_ducks_the_target_while_the_key_is_loud() {
+        let mut voice = track(&[0.0, 0.0, 1.0, 1.0, 0.0], 1, 0.0, 0);
+        voice.muted = true;
+        let tracks = vec![track(&[0.5; 5], 1, 0.0, 0), voice];
+        let mut mix = mixer(1, tracks);
+        mix.output_mode = OutputMode::None;
+        // 20 dB over the threshold at 2:1 is 10 dB of reduction, instantly
+        mix.set_sidechain(0, 1, -20.0, 2.0, 0.0, 0.0).unwrap();
+        let ducked = 0.5 * 10f32.powf(-10.0 / 20.0);
+        let output = mix.mix_samples(5);
+        assert_eq!(output[..2], [0.5, 0.5]);
+        assert!((output[2] - ducked).abs() < 1e-6 && (output[3] - ducked).abs() < 1e-6);
+        assert_eq!(output[4], 0.5);
+
+        // Removing a track before the key keeps the pair; removing the key
+        // drops the sidechain
+        mix.tracks.insert(0, track(&[0.0], 1, 0.0, 0));
+        mix.tracks[1].sidechain.as_mut().unwrap().key = 2;
+        assert!(mix.remove_track(0));
+        assert_eq!(mix.tracks[0].sidechain.unwrap().key, 1);
+        assert!(mix.remove_track(1));
+        assert!(mix.tracks[0].sidechain.is_none());
+    }
+
+    #[test]
+    fn grouped_stems_add_up_to_the_mix() {
+        let group = |samples: &[f32], name: &str| {
+            let mut grouped = track(samples, 1, 0.0, 0);
+            grouped.set_group(name);
+            grouped
+        };
+        let mut muted = group(&[0.5, 0.5], "sfx");
+        muted.muted = true;
+        let tracks = vec![
+            group(&[0.1, 0.2], "music"),
+            track(&[0.3, 0.3], 1, 0.0, 0),
+            muted,
+            group(&[0.2, 0.1], "music"),
+        ];
+        let mut mix = mixer(1, tracks);
+        mix.master_gain = 2.0;
+        let stems = mix.stem_groups(2);
+        let names: Vec<&str> = stems.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["music", "default"]);
+        assert_eq!(stems[1].1, [0.6, 0.6]);
+        let summed: Vec<f32> = (0..2).map(|i| stems[0].1[i] + stems[1].1[i]).collect();
+        mix.output_mode = OutputMode::None;
+        assert_eq!(mix.mix_samples(2), summed);
+    }
+
+    #[test]
+    fn stems_render_one_track_without_the_master_bus() {
+        let mut left = track(&[0.5, 0.5], 1, -1.0, 1);
+        left.muted = true;
+        let mut mix = mixer(2, vec![left, track(&[0.25], 1, 0.0, 0)]);
+        mix.master_gain = 4.0;
+        assert_eq!(
+            mix.stem(0, 4).unwrap(),
+            [0.0, 0.0, 0.5, 0.0, 0.5, 0.0, 0.0, 0.0]
+        );
+        assert_eq!(
+            mix.stem(2, 4).err(),
+            Some(MixerError::TrackOutOfRange { index: 2, count: 2 })
+        );
+    }
+
+    #[test]
+    fn track_levels_follow_add_order_after_gain_and_pan() {
+        let mut quiet = track(&[0.5, -0.5, 0.5, -0.5], 1, -1.0, 0);
+        quiet.gain = 0.5;
+        let mut muted = track(&[1.0; 4], 1, 0.0, 0);
+        muted.muted = true;
+        let late = track(&[1.0, 1.0], 2, 0.0, 3);
+        let mut mix = mixer(2, vec![quiet, muted, late]);
+        mix.master_gain = 0.25;
+
+        // Hard left at half gain: 0.25 on the left channel, silence on the
+        // right
+        let levels = mix.track_level_pairs(4);
+        assert_eq!(levels.len(), 6);
+        assert_eq!(levels[0], 0.25);
+        assert!((levels[1] - 0.25 * FRAC_1_SQRT_2).abs() < 1e-6);
+        assert_eq!(levels[2..4], [0.0, 0.0]);
+        // Only the last of four frames holds the stereo track
+        assert_eq!(levels[4], 1.0);
+        assert!((levels[5] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn output_ceiling_bounds_normalization_and_hard_clipping() {
+        let mut mix = mixer(1, vec![track(&[2.0, -1.0, 0.5], 1, 0.0, 0)]);
+        mix.output_ceiling = 0.5;
+        let output = mix.mix_samples(3);
+        assert_eq!(output, [0.5, -0.25, 0.125]);
+        let report = mix.last_mix.unwrap();
+        assert_eq!(report.scale, 0.25);
+        assert!(report.clipped);
+
+        mix.output_mode = OutputMode::HardClip;
+        assert_eq!(mix.mix_samples(3), [0.5, -0.5, 0.5]);
+        assert_eq!(checked_ceiling(0.0), Err(MixerError::InvalidCeiling(0.0)));
+        assert!(checked_ceiling(1.5).is_err() && checked_ceiling(f32::NAN).is_err());
+    }
+
+    #[test]
+    fn limiter_catches_peaks_before_they_arrive() {
+        // A burst at 2.0 in a 0.5 stereo signal, with 10 frames of lookahead
+        let mut samples = vec![0.5f32; 200];
+        samples[100..110].fill(2.0);
+        let output = dynamics::limit(&samples, 2, 1.0, 10, 5.0, 1000);
+        assert_eq!(output.len(), 220);
+        assert_eq!(output[..20], [0.0; 20]);
+        assert!(output.iter().all(|s| s.abs() <= 1.0 + 1e-6));
+        // The burst is caught at the ceiling, the ramp starts ahead of it
+        // and the signal is untouched well before and after
+        assert!((output[120] - 1.0).abs() < 1e-5);
+        assert!(output[110] < 0.5 && output[110] > 0.25);
+        assert_eq!(output[20..90], [0.5; 70]);
+        assert!((output[218] - 0.5).abs() < 1e-3);
+
+        let quiet = dynamics::limit(&[0.25, -0.5], 1, 0.9, 0, 50.0, 48000);
+        assert_eq!(quiet, [0.25, -0.5]);
+    }
+
+    #[test]
+    fn overlay_sums_into_and_extends_a_rendered_mix() {
+        let mut mix = mixer(2, Vec::new());
+        mix.output_mode = OutputMode::None;
+        let base = vec![0.25, 0.25, 0.5, 0.5];
+        assert_eq!(
+            mix.overlay_samples(base.clone(), &[0.5, 1.0, 1.0, 1.0], 1, 0.5),
+            [0.25, 0.25, 0.75, 1.0, 0.5, 0.5]
+        );
+        // Past the end the gap is filled with silence
+        assert_eq!(
+            mix.overlay_samples(base.clone(), &[1.0, -1.0], 3, 1.0),
+            [0.25, 0.25, 0.5, 0.5, 0.0, 0.0, 1.0, -1.0]
+        );
+        // Clipping is handled by the output stage, as in mix
+        mix.output_mode = OutputMode::Normalize;
+        assert_eq!(
+            mix.overlay_samples(base, &[1.5, 0.0], 1, 1.0),
+            [0.125, 0.125, 1.0, 0.25]
+        );
+    }
+
+    #[test]
+    fn appending_extends_a_track_and_the_duration() {
+        let mut mix = mixer(2, vec![track(&[0.5, 0.5], 2, 0.0, 10)]);
+        mix.output_mode = OutputMode::None;
+        assert_eq!(mix.required_duration_samples(), 11);
+        mix.append_samples(0, &[0.25, -0.25, 0.1, 0.2]).unwrap();
+        assert_eq!(mix.required_duration_samples(), 13);
+        assert_eq!(mix.mix_samples(13)[20..], [0.5, 0.5, 0.25, -0.25, 0.1, 0.2]);
+        assert_eq!(
+            mix.append_samples(1, &[0.0, 0.0]),
+            Err(MixerError::TrackOutOfRange { index: 1, count: 1 })
+        );
+        assert!(matches!(
+            mix.append_samples(0, &[0.0]),
+            Err(MixerError::PartialFrame { len: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn routing_follows_the_channel_layouts() {
+        // A 2.1 track keeps its LFE in a 2.1 mix and drops it in stereo
+        let mut bass = track(&[0.5, 0.25, 0.75], 3, 0.0, 0);
+        bass.layout = ChannelLayout::Surround2_1;
+        let mut mix = mixer(3, vec![bass]);
+        mix.layout = ChannelLayout::Surround2_1;
+        mix.output_mode = OutputMode::None;
+        assert_eq!(mix.mix_samples(1), [0.5, 0.25, 0.75]);
+        mix.layout = ChannelLayout::Stereo;
+        assert_eq!(mix.mix_samples(1), [0.5, 0.25]);
+        // The default three-channel layout is 3.0, whose center folds
+        // into the front pair instead
+        let mut mix = mixer(2, vec![track(&[0.5, 0.25, 0.5], 3, 0.0, 0)]);
+        mix.output_mode = OutputMode::None;
+        let g = FRAC_1_SQRT_2;
+        assert_eq!(mix.mix_samples(1), [0.5 + 0.5 * g, 0.25 + 0.5 * g]);
+        // A mono track is panned across the front pair of any layout
+        let mut mix = mixer(3, vec![track(&[1.0], 1, -1.0, 0)]);
+        mix.layout = ChannelLayout::Surround2_1;
+        mix.output_mode = OutputMode::None;
+        assert_eq!(mix.mix_samples(1), [1.0, 0.0, 0.0]);
+
+        assert_eq!(channel_layout("5.1").map(ChannelLayout::channels), Ok(6));
+        assert_eq!(
+            ChannelLayout::from_channels(3),
+            Ok(ChannelLayout::Surround3_0)
+        );
+        assert_eq!(
+            ChannelLayout::from_channels(9),
+            Err(MixerError::InvalidChannelCount(9))
+        );
+        assert!(channel_layout("7.1").is_err());
+    }
+
+    #[test]
+    fn project_round_trips_and_rejects_bad_blobs() {
+        let mut track = AudioTrack::from_samples(
+            vec![0.5, -0.25, 1.0, 0.0],
+            ChannelLayout::Stereo,
+            0.8,
+            -0.5,
+            96,
+        )
+        .unwrap();
+        track.delay_samples = -12;
+        track.muted = true;
+        track.fades.fade_in = 2;
+        track.sample_rate = Some(44100);
+        track.loop_to_sample = Some(500);
+        let mut original = mixer(2, vec![track]);
+        original.pan_law = PanLaw::Linear;
+        original.master_gain = 0.5;
+        original.declick = true;
+        original.output_ceiling = 0.891;
+
+        let state = project::decode(&project::encode(&original.state())).unwrap();
+        let restored = AudioMixer::from_state(state).unwrap();
+        assert_eq!(
+            project::encode(&restored.state()),
+            project::encode(&original.state())
+        );
+        assert_eq!(restored.tracks[0].samples, [0.5, -0.25, 1.0, 0.0]);
+        assert_eq!(restored.tracks[0].delay_samples, -12);
+        assert_eq!(restored.tracks[0].sample_rate, Some(44100));
+        assert_eq!(restored.tracks[0].loop_to_sample, Some(500));
+        assert!(restored.declick && restored.tracks[0].muted);
+        assert_eq!(restored.output_ceiling, 0.891);
+
+        let bytes = project::encode(&original.state());
+        assert_eq!(
+            project::decode(b"RIFF\x01\x00").err(),
+            Some(MixerError::InvalidProject("not a saved mixer session"))
+        );
+        assert!(matches!(
+            project::decode(&bytes[..bytes.len() - 3]),
+            Err(MixerError::InvalidProject(_))
+        ));
+        // Version 1 sessions have no ceiling and restore with full scale,
+        // and before version 3 the layout is the count's default
+        let mut old = bytes.clone();
+        old[4..6].copy_from_slice(&1u16.to_le_bytes());
+        old.drain(25..30);
+        let old = project::decode(&old).unwrap();
+        assert_eq!(old.output_ceiling, 1.0);
+        assert_eq!(old.layout, ChannelLayout::Stereo);
+        let mut future = bytes.clone();
+        future[4..6].copy_from_slice(&(project::VERSION + 1).to_le_bytes());
+        assert_eq!(
+            project::decode(&future).err(),
+            Some(MixerError::UnsupportedProjectVersion(project::VERSION + 1))
+        );
+    }
+}
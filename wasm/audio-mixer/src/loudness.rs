@@ -0,0 +1,243 @@
+//! Loudness measurement following ITU-R BS.1770-4
+
+use crate::channels::{self, ChannelLayout};
+use crate::filter::Biquad;
+
+/// Gating block length and hop in seconds (400 ms blocks, 75% overlap)
+const BLOCK_SECONDS: f64 = 0.4;
+const STEPS_PER_BLOCK: usize = 4;
+/// Blocks quieter than this never count towards integrated loudness
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Relative gate below the loudness of the blocks that pass the absolute
+/// gate
+const RELATIVE_GATE_LU: f64 = 10.0;
+
+/// Loudness in LUFS of a weighted mean square
+fn lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// The two K-weighting stages: a high shelf modelling the head and an
+/// RLB high-pass, derived for any sample rate
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate.max(1) as f64;
+
+    // Stage 1: +4 dB high shelf around 1.7 kHz
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::from_coefficients(
+        (vh + vb * k / q + k * k) / a0,
+        2.0 * (k * k - vh) / a0,
+        (vh - vb * k / q + k * k) / a0,
+        2.0 * (k * k - 1.0) / a0,
+        (1.0 - k / q + k * k) / a0,
+    );
+
+    // Stage 2: high-pass at 38 Hz
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let highpass = Biquad::from_coefficients(
+        1.0,
+        -2.0,
+        1.0,
+        2.0 * (k * k - 1.0) / a0,
+        (1.0 - k / q + k * k) / a0,
+    );
+
+    [shelf, highpass]
+}
+
+/// Integrated loudness in LUFS of interleaved `samples`.
+///
+/// The signal is K-weighted and split into 400 ms blocks overlapping by
+/// 75%. Blocks below -70 LUFS are dropped, then blocks more than 10 LU
+/// below the loudness of the remaining ones, and the rest are averaged.
+/// Returns negative infinity for silence or input shorter than one block.
+pub(crate) fn integrated_loudness(samples: &[f32], channels: usize, sample_rate: u32) -> f64 {
+    let channels = channels.max(1);
+    let mut weighted = samples.to_vec();
+    for stage in k_weighting(sample_rate) {
+        stage.process(&mut weighted, channels);
+    }
+
+    // Mean square per channel of each 100 ms step; a block is the mean of
+    // four consecutive steps
+    let step =
+        ((sample_rate as f64 * BLOCK_SECONDS / STEPS_PER_BLOCK as f64).round() as usize).max(1);
+    let steps: Vec<Vec<f64>> = weighted
+        .chunks_exact(step * channels)
+        .map(|chunk| {
+            let mut power = vec![0.0; channels];
+            for frame in chunk.chunks_exact(channels) {
+                for (sum, &sample) in power.iter_mut().zip(frame) {
+                    *sum += sample as f64 * sample as f64;
+                }
+            }
+            power.iter().map(|sum| sum / step as f64).collect()
+        })
+        .collect();
+
+    // Counts without a default layout weigh every channel the same
+    let weights = ChannelLayout::from_channels(channels as u32)
+        .map_or_else(|_| vec![1.0; channels], channels::loudness_weights);
+    let blocks: Vec<Vec<f64>> = steps
+        .windows(STEPS_PER_BLOCK)
+        .map(|window| {
+            (0..channels)
+                .map(|channel| {
+                    window.iter().map(|step| step[channel]).sum::<f64>() / STEPS_PER_BLOCK as f64
+                })
+                .collect()
+        })
+        .collect();
+    let block_power =
+        |block: &[f64]| -> f64 { block.iter().zip(&weights).map(|(z, g)| z * g).sum() };
+
+    let gated_power = |threshold: f64| -> Option<f64> {
+        let passing: Vec<f64> = blocks
+            .iter()
+            .map(|block| block_power(block))
+            .filter(|&power| lufs(power) > threshold)
+            .collect();
+        if passing.is_empty() {
+            None
+        } else {
+            Some(passing.iter().sum::<f64>() / passing.len() as f64)
+        }
+    };
+
+    let Some(absolute) = gated_power(ABSOLUTE_GATE_LUFS) else {
+        return f64::NEG_INFINITY;
+    };
+    let relative_gate = lufs(absolute) - RELATIVE_GATE_LU;
+    gated_power(relative_gate.max(ABSOLUTE_GATE_LUFS)).map_or(f64::NEG_INFINITY, lufs)
+}
+
+/// RMS level of `samples` across all channels in dBFS, negative infinity
+/// for silence
+pub(crate) fn rms_dbfs(samples: &[f32]) -> f64 {
+    if samples.is_empty() {
+        return f64::NEG_INFINITY;
+    }
+    let power = samples.iter().map(|&s| s as f64 * s as f64).sum::<f64>() / samples.len() as f64;
+    10.0 * power.log10()
+}
+
+/// Linear RMS level of `samples` across all channels, 0.0 for silence
+pub(crate) fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let power = samples.iter().map(|&s| s as f64 * s as f64).sum::<f64>() / samples.len() as f64;
+    power.sqrt() as f32
+}
+
+/// Oversampling factor for true-peak estimation
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+/// Half the length of the interpolation kernel, in input samples
+const TRUE_PEAK_HALF_TAPS: isize = 6;
+
+/// Hann-windowed sinc interpolation kernel
+fn interpolation_kernel(x: f64) -> f64 {
+    let half = TRUE_PEAK_HALF_TAPS as f64;
+    if x == 0.0 {
+        return 1.0;
+    }
+    if x.abs() >= half {
+        return 0.0;
+    }
+    let pi_x = std::f64::consts::PI * x;
+    let window = 0.5 * (1.0 + (pi_x / half).cos());
+    pi_x.sin() / pi_x * window
+}
+
+/// Largest absolute sample of each channel of interleaved `samples`
+pub(crate) fn peak_levels(samples: &[f32], channels: usize) -> Vec<f32> {
+    let channels = channels.max(1);
+    let mut peaks = vec![0.0f32; channels];
+    for frame in samples.chunks_exact(channels) {
+        for (peak, sample) in peaks.iter_mut().zip(frame) {
+            *peak = peak.max(sample.abs());
+        }
+    }
+    peaks
+}
+
+/// Minimum and maximum of each channel over every `frames_per_column`
+/// frames of interleaved `samples`, laid out per column as `min, max` for
+/// channel 0, then channel 1 and so on. The last column may cover fewer
+/// frames; a trailing partial frame is ignored.
+pub(crate) fn waveform_peaks(
+    samples: &[f32],
+    channels: usize,
+    frames_per_column: usize,
+) -> Vec<f32> {
+    let channels = channels.max(1);
+    let column_len = frames_per_column.max(1) * channels;
+    let whole = samples.len() / channels * channels;
+    let mut peaks = Vec::with_capacity(whole.div_ceil(column_len) * channels * 2);
+    for column in samples[..whole].chunks(column_len) {
+        for channel in 0..channels {
+            let (min, max) = column
+                .iter()
+                .skip(channel)
+                .step_by(channels)
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &s| {
+                    (min.min(s), max.max(s))
+                });
+            peaks.extend([min, max]);
+        }
+    }
+    peaks
+}
+
+/// Estimated true peak of interleaved `samples` as a linear amplitude.
+///
+/// Each channel is upsampled 4x with a windowed-sinc interpolator so peaks
+/// between samples, which a DAC or lossy encoder would reproduce, are
+/// found as well.
+pub(crate) fn true_peak(samples: &[f32], channels: usize) -> f32 {
+    true_peak_levels(samples, channels)
+        .into_iter()
+        .fold(0.0, f32::max)
+}
+
+/// [`true_peak`] of each channel separately
+pub(crate) fn true_peak_levels(samples: &[f32], channels: usize) -> Vec<f32> {
+    let channels = channels.max(1);
+    let frames = samples.len() / channels;
+    let phases: Vec<Vec<f64>> = (1..TRUE_PEAK_OVERSAMPLING)
+        .map(|phase| {
+            let offset = phase as f64 / TRUE_PEAK_OVERSAMPLING as f64;
+            (1 - TRUE_PEAK_HALF_TAPS..=TRUE_PEAK_HALF_TAPS)
+                .map(|tap| interpolation_kernel(offset - tap as f64))
+                .collect()
+        })
+        .collect();
+
+    let mut peaks = peak_levels(samples, channels);
+    for (channel, peak) in peaks.iter_mut().enumerate() {
+        let sample = |frame: isize| -> f64 {
+            if frame < 0 || frame as usize >= frames {
+                0.0
+            } else {
+                samples[frame as usize * channels + channel] as f64
+            }
+        };
+        for frame in 0..frames as isize {
+            for kernel in &phases {
+                let value: f64 = kernel
+                    .iter()
+                    .zip(1 - TRUE_PEAK_HALF_TAPS..)
+                    .map(|(weight, tap)| weight * sample(frame + tap))
+                    .sum();
+                *peak = peak.max(value.abs() as f32);
+            }
+        }
+    }
+    peaks
+}
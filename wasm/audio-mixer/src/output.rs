@@ -0,0 +1,116 @@
+//! Final stage applied to the summed mix to keep it within [-1.0, 1.0], or
+//! within a lower ceiling
+
+/// Level below which soft clipping leaves samples untouched
+const SOFT_CLIP_KNEE: f32 = 0.75;
+
+/// How the mixer handles a mix that exceeds full scale
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum OutputMode {
+    /// Scale the whole mix down by its peak when it exceeds the ceiling
+    Normalize,
+    /// Saturate samples above the knee with tanh, approaching but never
+    /// reaching 1.0
+    SoftClip,
+    /// Clamp every sample to the ceiling
+    HardClip,
+    /// Leave the summed samples as they are
+    None,
+}
+
+impl OutputMode {
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "normalize" => Some(Self::Normalize),
+            "soft_clip" => Some(Self::SoftClip),
+            "hard_clip" => Some(Self::HardClip),
+            "none" => Some(Self::None),
+            _ => None,
+        }
+    }
+
+    /// Gain the mode applies to a whole mix peaking at `peak`; only
+    /// normalization scales, the clipping modes work per sample
+    pub(crate) fn scale(self, peak: f32, ceiling: f32) -> f32 {
+        match self {
+            Self::Normalize if peak > ceiling => ceiling / peak,
+            _ => 1.0,
+        }
+    }
+
+    /// Whether a mix peaking at `peak` comes out of this stage changed
+    pub(crate) fn alters(self, peak: f32, ceiling: f32) -> bool {
+        match self {
+            Self::Normalize | Self::HardClip => peak > ceiling,
+            Self::SoftClip => peak > SOFT_CLIP_KNEE,
+            Self::None => false,
+        }
+    }
+
+    /// Bring `samples` into range; normalization and hard clipping keep
+    /// them within `ceiling` (linear, at most 1.0), soft clipping within
+    /// full scale
+    pub(crate) fn apply(self, samples: &mut [f32], ceiling: f32) {
+        match self {
+            Self::Normalize => {
+                let gain = self.scale(peak(samples), ceiling);
+                if gain < 1.0 {
+                    for sample in samples {
+                        *sample *= gain;
+                    }
+                }
+            }
+            Self::SoftClip => {
+                for sample in samples {
+                    *sample = soft_clip(*sample);
+                }
+            }
+            Self::HardClip => {
+                for sample in samples {
+                    *sample = sample.clamp(-ceiling, ceiling);
+                }
+            }
+            Self::None => {}
+        }
+    }
+}
+
+/// Largest absolute sample value
+pub(crate) fn peak(samples: &[f32]) -> f32 {
+    samples.iter().map(|s| s.abs()).fold(0.0f32, f32::max)
+}
+
+/// Append the frames of `samples` where any channel exceeds full scale to
+/// `regions`, as flat `[start, end)` frame pairs counted from
+/// `first_frame`. A region touching the end of the previous one is merged
+/// into it, so blocks of a mix can be scanned one after another.
+pub(crate) fn clip_regions(
+    samples: &[f32],
+    channels: usize,
+    first_frame: usize,
+    regions: &mut Vec<u32>,
+) {
+    for (index, frame) in samples.chunks_exact(channels).enumerate() {
+        if frame.iter().all(|sample| sample.abs() <= 1.0) {
+            continue;
+        }
+        let frame = (first_frame + index) as u32;
+        match regions.last_mut() {
+            Some(end) if *end == frame => *end += 1,
+            _ => regions.extend([frame, frame + 1]),
+        }
+    }
+}
+
+/// Linear below the knee; above it the excess is squashed by tanh into the
+/// remaining headroom. The slope is continuous at the knee, so quiet
+/// material is unchanged and only peaks are rounded off.
+pub(crate) fn soft_clip(sample: f32) -> f32 {
+    let level = sample.abs();
+    if level <= SOFT_CLIP_KNEE {
+        return sample;
+    }
+    let headroom = 1.0 - SOFT_CLIP_KNEE;
+    let clipped = SOFT_CLIP_KNEE + headroom * ((level - SOFT_CLIP_KNEE) / headroom).tanh();
+    clipped.copysign(sample)
+}
@@ -0,0 +1,36 @@
+//! Conversion of float samples to integer PCM
+
+/// Small xorshift generator for dither noise; seeded with a constant so
+/// exports are reproducible
+struct Xorshift(u32);
+
+impl Xorshift {
+    /// Uniform value in [0.0, 1.0)
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+/// Quantize `samples` to signed integers of `bits` bits. Samples are
+/// clamped to [-1.0, 1.0] and scaled so full scale maps to the largest
+/// positive value. With `dither`, triangular (TPDF) noise of +-1 LSB is
+/// added before rounding, which turns quantization distortion on quiet
+/// passages into a constant, benign noise floor.
+pub(crate) fn quantize(samples: &[f32], bits: u32, dither: bool) -> Vec<i32> {
+    let max = ((1i64 << (bits - 1)) - 1) as f32;
+    let min = -max - 1.0;
+    let mut noise = Xorshift(0x9e37_79b9);
+    samples
+        .iter()
+        .map(|&sample| {
+            let mut value = sample.clamp(-1.0, 1.0) * max;
+            if dither {
+                value += noise.next() - noise.next();
+            }
+            value.round().clamp(min, max) as i32
+        })
+        .collect()
+}
@@ -0,0 +1,305 @@
+//! Binary save format for a mixing session.
+//!
+//! Layout, all little-endian: the magic `FMIX`, a u16 format version, the
+//! mixer fields, a u32 track count and one record per track. Each track
+//! record starts with its u32 byte length, so a reader can skip fields that
+//! a later version appends to the end of a record.
+
+use crate::channels::{ChannelLayout, PanLaw};
+use crate::error::MixerError;
+use crate::output::OutputMode;
+
+const MAGIC: &[u8; 4] = b"FMIX";
+/// Version written by [`encode`]; [`decode`] reads this and older ones
+pub(crate) const VERSION: u16 = 3;
+
+const FLAG_INVERTED: u8 = 1;
+const FLAG_MUTED: u8 = 1 << 1;
+const FLAG_SOLOED: u8 = 1 << 2;
+const FLAG_LOOPED: u8 = 1 << 3;
+
+/// Mixer settings that are saved with a session
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MixerState {
+    pub sample_rate: u32,
+    /// Output layout; sessions before version 3 store only the channel
+    /// count and get its default layout
+    pub layout: ChannelLayout,
+    pub pan_law: PanLaw,
+    pub output_mode: OutputMode,
+    pub declick: bool,
+    pub master_gain: f32,
+    pub master_balance: f32,
+    /// Output ceiling, added in version 2; 1.0 in older sessions
+    pub output_ceiling: f32,
+    pub tracks: Vec<TrackState>,
+}
+
+/// Track settings and samples that are saved with a session
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TrackState {
+    pub layout: ChannelLayout,
+    pub gain: f32,
+    pub pan: f32,
+    pub start_sample: usize,
+    pub trim_start: usize,
+    pub delay_samples: i32,
+    pub invert_polarity: bool,
+    pub muted: bool,
+    pub soloed: bool,
+    pub fade_in: usize,
+    pub fade_out: usize,
+    pub sample_rate: Option<u32>,
+    pub samples: Vec<f32>,
+    pub loop_to_sample: Option<usize>,
+    pub loop_crossfade: usize,
+}
+
+fn pan_law_id(law: PanLaw) -> u8 {
+    match law {
+        PanLaw::EqualPower => 0,
+        PanLaw::Linear => 1,
+        PanLaw::Compromise => 2,
+    }
+}
+
+fn pan_law_from_id(id: u8) -> Option<PanLaw> {
+    match id {
+        0 => Some(PanLaw::EqualPower),
+        1 => Some(PanLaw::Linear),
+        2 => Some(PanLaw::Compromise),
+        _ => None,
+    }
+}
+
+fn layout_id(layout: ChannelLayout) -> u8 {
+    match layout {
+        ChannelLayout::Mono => 0,
+        ChannelLayout::Stereo => 1,
+        ChannelLayout::Surround2_1 => 2,
+        ChannelLayout::Surround3_0 => 3,
+        ChannelLayout::Quad => 4,
+        ChannelLayout::Surround5_0 => 5,
+        ChannelLayout::Surround5_1 => 6,
+    }
+}
+
+/// The layout stored after a channel count, which it must agree with
+fn layout_from_id(id: u8, channels: u32) -> Result<ChannelLayout, MixerError> {
+    let layout = match id {
+        0 => ChannelLayout::Mono,
+        1 => ChannelLayout::Stereo,
+        2 => ChannelLayout::Surround2_1,
+        3 => ChannelLayout::Surround3_0,
+        4 => ChannelLayout::Quad,
+        5 => ChannelLayout::Surround5_0,
+        6 => ChannelLayout::Surround5_1,
+        _ => return Err(MixerError::InvalidProject("unknown channel layout")),
+    };
+    if layout.channels() != channels as usize {
+        return Err(MixerError::InvalidProject(
+            "channel layout does not match the channel count",
+        ));
+    }
+    Ok(layout)
+}
+
+fn output_mode_id(mode: OutputMode) -> u8 {
+    match mode {
+        OutputMode::Normalize => 0,
+        OutputMode::SoftClip => 1,
+        OutputMode::HardClip => 2,
+        OutputMode::None => 3,
+    }
+}
+
+fn output_mode_from_id(id: u8) -> Option<OutputMode> {
+    match id {
+        0 => Some(OutputMode::Normalize),
+        1 => Some(OutputMode::SoftClip),
+        2 => Some(OutputMode::HardClip),
+        3 => Some(OutputMode::None),
+        _ => None,
+    }
+}
+
+pub(crate) fn encode(state: &MixerState) -> Vec<u8> {
+    let samples: usize = state.tracks.iter().map(|track| track.samples.len()).sum();
+    let mut out = Vec::with_capacity(64 + 64 * state.tracks.len() + 4 * samples);
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&state.sample_rate.to_le_bytes());
+    out.extend_from_slice(&(state.layout.channels() as u32).to_le_bytes());
+    out.push(pan_law_id(state.pan_law));
+    out.push(output_mode_id(state.output_mode));
+    out.push(state.declick as u8);
+    out.extend_from_slice(&state.master_gain.to_le_bytes());
+    out.extend_from_slice(&state.master_balance.to_le_bytes());
+    out.extend_from_slice(&state.output_ceiling.to_le_bytes());
+    out.push(layout_id(state.layout));
+    out.extend_from_slice(&(state.tracks.len() as u32).to_le_bytes());
+    for track in &state.tracks {
+        let record_start = out.len();
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&(track.layout.channels() as u32).to_le_bytes());
+        out.extend_from_slice(&track.gain.to_le_bytes());
+        out.extend_from_slice(&track.pan.to_le_bytes());
+        out.extend_from_slice(&(track.start_sample as u64).to_le_bytes());
+        out.extend_from_slice(&(track.trim_start as u64).to_le_bytes());
+        out.extend_from_slice(&track.delay_samples.to_le_bytes());
+        let flags = (track.invert_polarity as u8 * FLAG_INVERTED)
+            | (track.muted as u8 * FLAG_MUTED)
+            | (track.soloed as u8 * FLAG_SOLOED)
+            | (track.loop_to_sample.is_some() as u8 * FLAG_LOOPED);
+        out.push(flags);
+        out.extend_from_slice(&(track.fade_in as u64).to_le_bytes());
+        out.extend_from_slice(&(track.fade_out as u64).to_le_bytes());
+        out.extend_from_slice(&track.sample_rate.unwrap_or(0).to_le_bytes());
+        out.extend_from_slice(&(track.samples.len() as u64).to_le_bytes());
+        for sample in &track.samples {
+            out.extend_from_slice(&sample.to_le_bytes());
+        }
+        out.extend_from_slice(&(track.loop_to_sample.unwrap_or(0) as u64).to_le_bytes());
+        out.extend_from_slice(&(track.loop_crossfade as u64).to_le_bytes());
+        out.push(layout_id(track.layout));
+        let record_len = (out.len() - record_start - 4) as u32;
+        out[record_start..record_start + 4].copy_from_slice(&record_len.to_le_bytes());
+    }
+    out
+}
+
+/// Cursor over the bytes of a saved session
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], MixerError> {
+        if self.data.len() < len {
+            return Err(MixerError::InvalidProject("data ends early"));
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], MixerError> {
+        Ok(self.take(N)?.try_into().expect("slice has N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, MixerError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, MixerError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, MixerError> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<usize, MixerError> {
+        usize::try_from(u64::from_le_bytes(self.array()?))
+            .map_err(|_| MixerError::InvalidProject("length does not fit in memory"))
+    }
+
+    fn f32(&mut self) -> Result<f32, MixerError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+}
+
+pub(crate) fn decode(data: &[u8]) -> Result<MixerState, MixerError> {
+    let mut reader = Reader { data };
+    if reader.take(4).ok() != Some(&MAGIC[..]) {
+        return Err(MixerError::InvalidProject("not a saved mixer session"));
+    }
+    let version = u16::from_le_bytes(reader.array()?);
+    if version == 0 || version > VERSION {
+        return Err(MixerError::UnsupportedProjectVersion(version));
+    }
+    let sample_rate = reader.u32()?;
+    let channels = reader.u32()?;
+    let pan_law =
+        pan_law_from_id(reader.u8()?).ok_or(MixerError::InvalidProject("unknown pan law"))?;
+    let output_mode = output_mode_from_id(reader.u8()?)
+        .ok_or(MixerError::InvalidProject("unknown output mode"))?;
+    let declick = reader.u8()? != 0;
+    let master_gain = reader.f32()?;
+    let master_balance = reader.f32()?;
+    let output_ceiling = if version >= 2 { reader.f32()? } else { 1.0 };
+    let layout = if version >= 3 {
+        layout_from_id(reader.u8()?, channels)?
+    } else {
+        ChannelLayout::from_channels(channels)?
+    };
+    let count = reader.u32()?;
+
+    let mut tracks = Vec::new();
+    for _ in 0..count {
+        let record_len = reader.u32()? as usize;
+        let mut record = Reader {
+            data: reader.take(record_len)?,
+        };
+        let channels = record.u32()?;
+        let gain = record.f32()?;
+        let pan = record.f32()?;
+        let start_sample = record.u64()?;
+        let trim_start = record.u64()?;
+        let delay_samples = record.i32()?;
+        let flags = record.u8()?;
+        let fade_in = record.u64()?;
+        let fade_out = record.u64()?;
+        let sample_rate = Some(record.u32()?).filter(|&rate| rate > 0);
+        let len = record.u64()?;
+        let bytes = record.take(
+            len.checked_mul(4)
+                .ok_or(MixerError::InvalidProject("length does not fit in memory"))?,
+        )?;
+        let samples = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        // Looping was appended to the record after the first sessions
+        // were saved
+        let (loop_to, loop_crossfade) = if record.data.is_empty() {
+            (0, 0)
+        } else {
+            (record.u64()?, record.u64()?)
+        };
+        // As was the layout, with the default for the channel count before
+        let layout = if record.data.is_empty() {
+            ChannelLayout::from_channels(channels)?
+        } else {
+            layout_from_id(record.u8()?, channels)?
+        };
+        tracks.push(TrackState {
+            layout,
+            gain,
+            pan,
+            start_sample,
+            trim_start,
+            delay_samples,
+            invert_polarity: flags & FLAG_INVERTED != 0,
+            muted: flags & FLAG_MUTED != 0,
+            soloed: flags & FLAG_SOLOED != 0,
+            fade_in,
+            fade_out,
+            sample_rate,
+            samples,
+            loop_to_sample: Some(loop_to).filter(|_| flags & FLAG_LOOPED != 0),
+            loop_crossfade,
+        });
+    }
+    Ok(MixerState {
+        sample_rate,
+        layout,
+        pan_law,
+        output_mode,
+        declick,
+        master_gain,
+        master_balance,
+        output_ceiling,
+        tracks,
+    })
+}
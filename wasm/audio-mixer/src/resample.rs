@@ -0,0 +1,65 @@
+//! Sample-rate conversion with a windowed-sinc (Lanczos) kernel
+
+use std::f64::consts::PI;
+
+/// Lobes on each side of the Lanczos kernel
+const LANCZOS_LOBES: f64 = 4.0;
+
+fn lanczos(x: f64) -> f64 {
+    if x == 0.0 {
+        return 1.0;
+    }
+    if x.abs() >= LANCZOS_LOBES {
+        return 0.0;
+    }
+    let pi_x = PI * x;
+    LANCZOS_LOBES * pi_x.sin() * (pi_x / LANCZOS_LOBES).sin() / (pi_x * pi_x)
+}
+
+/// Number of output frames for `frames` input frames, rounded to nearest
+pub(crate) fn output_frames(frames: usize, from_rate: u32, to_rate: u32) -> usize {
+    ((frames as u64 * to_rate as u64 + from_rate as u64 / 2) / from_rate as u64) as usize
+}
+
+/// Convert interleaved `samples` from `from_rate` to `to_rate`, preserving
+/// the channel count.
+///
+/// When downsampling the kernel is stretched by the rate ratio so it also
+/// acts as the anti-aliasing low-pass. Weights are normalized per output
+/// frame, which keeps the level right at the buffer edges.
+pub(crate) fn resample(samples: &[f32], channels: usize, from_rate: u32, to_rate: u32) -> Vec<f32> {
+    let channels = channels.max(1);
+    if from_rate == to_rate || from_rate == 0 || to_rate == 0 {
+        return samples.to_vec();
+    }
+    let frames = samples.len() / channels;
+    let out_frames = output_frames(frames, from_rate, to_rate);
+    let step = from_rate as f64 / to_rate as f64;
+    let scale = (to_rate as f64 / from_rate as f64).min(1.0);
+    let support = LANCZOS_LOBES / scale;
+
+    let mut output = Vec::with_capacity(out_frames * channels);
+    let mut weights = Vec::new();
+    for frame in 0..out_frames {
+        let position = frame as f64 * step;
+        let first = ((position - support).floor() as isize + 1).max(0) as usize;
+        let last = ((position + support).ceil() as usize).min(frames);
+
+        weights.clear();
+        weights.extend((first..last).map(|i| lanczos((position - i as f64) * scale)));
+        let total: f64 = weights.iter().sum();
+        for channel in 0..channels {
+            let value: f64 = weights
+                .iter()
+                .zip(first..last)
+                .map(|(weight, i)| weight * samples[i * channels + channel] as f64)
+                .sum();
+            output.push(if total.abs() > f64::EPSILON {
+                (value / total) as f32
+            } else {
+                0.0
+            });
+        }
+    }
+    output
+}
@@ -0,0 +1,65 @@
+//! Inner loops of the mixer. With the `simd` feature and a build using
+//! `-C target-feature=+simd128` they process four samples at a time with
+//! wasm SIMD; everywhere else the scalar versions are used.
+
+#[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+pub(crate) use wasm::{mul_add, scale};
+
+#[cfg(not(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128")))]
+pub(crate) use scalar::{mul_add, scale};
+
+pub(crate) mod scalar {
+    /// `dst += src * gain`, over the shorter of the two buffers
+    pub(crate) fn mul_add(dst: &mut [f32], src: &[f32], gain: f32) {
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d += s * gain;
+        }
+    }
+
+    /// `samples *= gain`
+    pub(crate) fn scale(samples: &mut [f32], gain: f32) {
+        for sample in samples {
+            *sample *= gain;
+        }
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+mod wasm {
+    use super::scalar;
+    use core::arch::wasm32::{f32x4_add, f32x4_mul, f32x4_splat, v128, v128_load, v128_store};
+
+    /// `dst += src * gain`, over the shorter of the two buffers. Multiply
+    /// and add stay separate operations, so results match the scalar loop.
+    pub(crate) fn mul_add(dst: &mut [f32], src: &[f32], gain: f32) {
+        let len = dst.len().min(src.len());
+        let gains = f32x4_splat(gain);
+        let mut dst_lanes = dst[..len].chunks_exact_mut(4);
+        let mut src_lanes = src[..len].chunks_exact(4);
+        for (d, s) in (&mut dst_lanes).zip(&mut src_lanes) {
+            // SAFETY: both chunks hold exactly four f32s, and v128_load and
+            // v128_store accept unaligned addresses
+            unsafe {
+                let product = f32x4_mul(v128_load(s.as_ptr() as *const v128), gains);
+                let sum = f32x4_add(v128_load(d.as_ptr() as *const v128), product);
+                v128_store(d.as_mut_ptr() as *mut v128, sum);
+            }
+        }
+        scalar::mul_add(dst_lanes.into_remainder(), src_lanes.remainder(), gain);
+    }
+
+    /// `samples *= gain`
+    pub(crate) fn scale(samples: &mut [f32], gain: f32) {
+        let gains = f32x4_splat(gain);
+        let mut lanes = samples.chunks_exact_mut(4);
+        for lane in &mut lanes {
+            // SAFETY: the chunk holds exactly four f32s, and v128_load and
+            // v128_store accept unaligned addresses
+            unsafe {
+                let scaled = f32x4_mul(v128_load(lane.as_ptr() as *const v128), gains);
+                v128_store(lane.as_mut_ptr() as *mut v128, scaled);
+            }
+        }
+        scalar::scale(lanes.into_remainder(), gain);
+    }
+}
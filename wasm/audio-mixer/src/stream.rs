@@ -0,0 +1,111 @@
+//! Per-track ring buffers for mixing audio that arrives block by block
+
+use crate::channels::{self, ChannelLayout, PanLaw};
+
+/// Fixed-capacity FIFO of samples that overwrites the oldest samples when
+/// full, so a stalled reader never makes the writer fail
+pub(crate) struct RingBuffer {
+    data: Vec<f32>,
+    /// Index of the oldest sample
+    read: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            data: vec![0.0; capacity.max(1)],
+            read: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Append `samples`, dropping the oldest buffered (or pushed) samples
+    /// that no longer fit; returns how many were dropped
+    pub(crate) fn push(&mut self, samples: &[f32]) -> usize {
+        let capacity = self.data.len();
+        let skipped = samples.len().saturating_sub(capacity);
+        let samples = &samples[skipped..];
+        let overflow = (self.len + samples.len()).saturating_sub(capacity);
+        self.read = (self.read + overflow) % capacity;
+        self.len -= overflow;
+
+        let write = (self.read + self.len) % capacity;
+        let first = samples.len().min(capacity - write);
+        self.data[write..write + first].copy_from_slice(&samples[..first]);
+        self.data[..samples.len() - first].copy_from_slice(&samples[first..]);
+        self.len += samples.len();
+        skipped + overflow
+    }
+
+    /// Move up to `output.len()` of the oldest samples into `output`;
+    /// returns how many were available
+    pub(crate) fn pop_into(&mut self, output: &mut [f32]) -> usize {
+        let capacity = self.data.len();
+        let count = output.len().min(self.len);
+        let first = count.min(capacity - self.read);
+        output[..first].copy_from_slice(&self.data[self.read..self.read + first]);
+        output[first..count].copy_from_slice(&self.data[..count - first]);
+        self.read = (self.read + count) % capacity;
+        self.len -= count;
+        count
+    }
+}
+
+/// One live input of a StreamingMixer
+pub(crate) struct StreamTrack {
+    pub id: u32,
+    pub layout: ChannelLayout,
+    pub gain: f32,
+    pub pan: f32,
+    /// Interleaved frames waiting to be mixed
+    pub buffer: RingBuffer,
+}
+
+impl StreamTrack {
+    pub(crate) fn new(
+        id: u32,
+        layout: ChannelLayout,
+        gain: f32,
+        pan: f32,
+        capacity_frames: usize,
+    ) -> Self {
+        Self {
+            id,
+            layout,
+            gain,
+            pan,
+            buffer: RingBuffer::new(capacity_frames.max(1) * layout.channels()),
+        }
+    }
+
+    /// Queue interleaved frames, dropping a trailing partial frame; returns
+    /// the number of frames lost to overflow
+    pub(crate) fn push(&mut self, samples: &[f32]) -> usize {
+        let channels = self.layout.channels();
+        let whole = samples.len() - samples.len() % channels;
+        self.buffer.push(&samples[..whole]) / channels
+    }
+
+    /// Take the next frames and add them to `output` with the track's gain
+    /// and the default routing for its pan. Frames that have not arrived
+    /// yet are silence.
+    pub(crate) fn mix_into(&mut self, output: &mut [f32], out_layout: ChannelLayout, law: PanLaw) {
+        let (channels, out_channels) = (self.layout.channels(), out_layout.channels());
+        let frames = output.len() / out_channels;
+        let mut samples = vec![0.0f32; frames * channels];
+        let available = self.buffer.pop_into(&mut samples);
+        let matrix = channels::default_matrix(self.layout, out_layout, self.pan, law);
+        let frames = samples[..available].chunks_exact(channels);
+        for (frame, out_frame) in frames.zip(output.chunks_exact_mut(out_channels)) {
+            for (out, gains) in out_frame.iter_mut().zip(matrix.chunks(channels)) {
+                let routed: f32 = frame.iter().zip(gains).map(|(s, g)| s * g).sum();
+                *out += routed * self.gain;
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Time stretching (WSOLA) and pitch shifting
+
+use crate::resample;
+use std::f32::consts::PI;
+
+/// Range of accepted stretch factors
+const MIN_STRETCH: f32 = 0.25;
+const MAX_STRETCH: f32 = 4.0;
+/// Largest pitch shift in either direction, in semitones
+pub(crate) const MAX_SEMITONES: f32 = 24.0;
+/// Length of the overlapped segments
+const SEGMENT_SECONDS: f32 = 0.04;
+/// Stride of the similarity search inside a segment; every sample is not
+/// needed to line up the waveform
+const CORRELATION_STRIDE: usize = 4;
+
+/// Change the duration of interleaved `samples` by `factor` (2.0 is twice
+/// as long) without changing the pitch, using waveform-similarity
+/// overlap-add (WSOLA).
+///
+/// The output is built from Hann-windowed segments at 50% overlap. Each
+/// segment is read near its nominal position in the input, shifted by up to
+/// a quarter segment to where it best continues the previous one, which
+/// avoids the phase jumps of plain overlap-add.
+pub(crate) fn time_stretch(
+    samples: &[f32],
+    channels: usize,
+    sample_rate: u32,
+    factor: f32,
+) -> Vec<f32> {
+    let channels = channels.max(1);
+    let frames = samples.len() / channels;
+    let factor = if factor.is_finite() {
+        factor.clamp(MIN_STRETCH, MAX_STRETCH)
+    } else {
+        1.0
+    };
+    if frames == 0 || factor == 1.0 {
+        return samples.to_vec();
+    }
+
+    let segment = ((sample_rate as f32 * SEGMENT_SECONDS) as usize / 2 * 2).max(32);
+    let hop = segment / 2;
+    let tolerance = segment / 4;
+    let window: Vec<f32> = (0..segment)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / segment as f32).cos())
+        .collect();
+    // Similarity is measured on the channel sum
+    let mono: Vec<f32> = samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum())
+        .collect();
+    let mono_at = |frame: usize| mono.get(frame).copied().unwrap_or(0.0);
+
+    let out_frames = (frames as f64 * factor as f64).round() as usize;
+    let mut output = vec![0.0f32; out_frames * channels];
+    let mut weights = vec![0.0f32; out_frames];
+    let mut previous: Option<usize> = None;
+    for out_pos in (0..out_frames).step_by(hop) {
+        let nominal = ((out_pos as f64 / factor as f64) as usize).min(frames - 1);
+        let start = match previous {
+            None => nominal,
+            Some(previous) => {
+                let target = previous + hop;
+                let first = nominal.saturating_sub(tolerance);
+                let last = (nominal + tolerance).min(frames - 1);
+                (first..=last)
+                    .map(|candidate| {
+                        let similarity: f32 = (0..segment)
+                            .step_by(CORRELATION_STRIDE)
+                            .map(|i| mono_at(candidate + i) * mono_at(target + i))
+                            .sum();
+                        (candidate, similarity)
+                    })
+                    .fold((nominal, f32::NEG_INFINITY), |best, candidate| {
+                        if candidate.1 > best.1 {
+                            candidate
+                        } else {
+                            best
+                        }
+                    })
+                    .0
+            }
+        };
+
+        for (i, &w) in window.iter().enumerate() {
+            let (out, source) = (out_pos + i, start + i);
+            if out >= out_frames || source >= frames {
+                break;
+            }
+            for channel in 0..channels {
+                output[out * channels + channel] += w * samples[source * channels + channel];
+            }
+            weights[out] += w;
+        }
+        previous = Some(start);
+    }
+
+    // Dividing by the summed window keeps the level at the edges, where
+    // fewer segments overlap
+    for (frame, &weight) in output.chunks_exact_mut(channels).zip(&weights) {
+        if weight > 1e-3 {
+            for sample in frame {
+                *sample /= weight;
+            }
+        }
+    }
+    output
+}
+
+/// Shift the pitch of interleaved `samples` by `semitones` (clamped to
+/// +-24) keeping the duration: the audio is time stretched by the pitch
+/// ratio, then resampled back to the original length.
+pub(crate) fn pitch_shift(
+    samples: &[f32],
+    channels: usize,
+    sample_rate: u32,
+    semitones: f32,
+) -> Vec<f32> {
+    let channels = channels.max(1);
+    let frames = samples.len() / channels;
+    let semitones = if semitones.is_finite() {
+        semitones.clamp(-MAX_SEMITONES, MAX_SEMITONES)
+    } else {
+        0.0
+    };
+    if semitones == 0.0 || frames == 0 {
+        return samples.to_vec();
+    }
+    let ratio = 2f32.powf(semitones / 12.0);
+    let stretched = time_stretch(samples, channels, sample_rate, ratio);
+    // Playing the stretched audio `ratio` times faster restores the length
+    let from_rate = (sample_rate as f64 * ratio as f64).round() as u32;
+    let mut output = resample::resample(&stretched, channels, from_rate, sample_rate);
+    output.resize(frames * channels, 0.0);
+    output
+}
@@ -0,0 +1,205 @@
+//! RIFF/WAVE encoding and decoding of interleaved float samples
+
+use crate::error::MixerError;
+use crate::pcm;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+/// Format tag whose actual format is the first two bytes of the SubFormat
+/// GUID in the fmt extension
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Sample formats a WAV file can be written with
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum WavFormat {
+    Pcm16,
+    Pcm24,
+    Float32,
+}
+
+impl WavFormat {
+    pub(crate) fn from_bit_depth(bits: u32) -> Option<Self> {
+        match bits {
+            16 => Some(Self::Pcm16),
+            24 => Some(Self::Pcm24),
+            32 => Some(Self::Float32),
+            _ => None,
+        }
+    }
+
+    fn bytes_per_sample(self) -> usize {
+        match self {
+            Self::Pcm16 => 2,
+            Self::Pcm24 => 3,
+            Self::Float32 => 4,
+        }
+    }
+}
+
+/// Complete WAV file of interleaved `samples`. A trailing incomplete frame
+/// is dropped. Float files carry the extended fmt chunk and the fact chunk
+/// that non-PCM WAVE files require.
+pub(crate) fn encode(
+    samples: &[f32],
+    sample_rate: u32,
+    channels: u16,
+    format: WavFormat,
+) -> Vec<u8> {
+    let channels_len = channels.max(1) as usize;
+    let samples = &samples[..samples.len() / channels_len * channels_len];
+    let bytes_per_sample = format.bytes_per_sample();
+    let data_len = samples.len() * bytes_per_sample;
+    let block_align = (channels_len * bytes_per_sample) as u16;
+    let is_float = format == WavFormat::Float32;
+    let fmt_len: u32 = if is_float { 18 } else { 16 };
+    let fact_len = if is_float { 12 } else { 0 };
+    // "WAVE" + fmt chunk + optional fact chunk + data chunk header + data
+    let riff_len = 4 + 8 + fmt_len as usize + fact_len + 8 + data_len + data_len % 2;
+
+    let mut out = Vec::with_capacity(8 + riff_len);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(riff_len as u32).to_le_bytes());
+    out.extend_from_slice(b"WAVE");
+
+    out.extend_from_slice(b"fmt ");
+    out.extend_from_slice(&fmt_len.to_le_bytes());
+    let tag = if is_float {
+        WAVE_FORMAT_IEEE_FLOAT
+    } else {
+        WAVE_FORMAT_PCM
+    };
+    out.extend_from_slice(&tag.to_le_bytes());
+    out.extend_from_slice(&channels.max(1).to_le_bytes());
+    out.extend_from_slice(&sample_rate.to_le_bytes());
+    out.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    out.extend_from_slice(&block_align.to_le_bytes());
+    out.extend_from_slice(&(bytes_per_sample as u16 * 8).to_le_bytes());
+    if is_float {
+        // cbSize: no extension
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(b"fact");
+        out.extend_from_slice(&4u32.to_le_bytes());
+        out.extend_from_slice(&((samples.len() / channels_len) as u32).to_le_bytes());
+    }
+
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&(data_len as u32).to_le_bytes());
+    match format {
+        WavFormat::Pcm16 => {
+            for value in pcm::quantize(samples, 16, false) {
+                out.extend_from_slice(&(value as i16).to_le_bytes());
+            }
+        }
+        WavFormat::Pcm24 => {
+            for value in pcm::quantize(samples, 24, false) {
+                out.extend_from_slice(&value.to_le_bytes()[..3]);
+            }
+        }
+        WavFormat::Float32 => {
+            for sample in samples {
+                out.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+    }
+    // Chunks are padded to an even size
+    if data_len % 2 == 1 {
+        out.push(0);
+    }
+    out
+}
+
+/// Samples of a decoded WAV file
+#[derive(Debug, PartialEq)]
+pub(crate) struct Decoded {
+    /// Interleaved samples in [-1.0, 1.0) for PCM, as stored for float
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+/// Chunks of a RIFF file after the 12-byte header, as `(id, body)`. A
+/// chunk whose size runs past the end of the data is cut short, as in
+/// files from a recorder that never went back to fill in the size.
+fn chunks(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 12;
+    std::iter::from_fn(move || {
+        let header = data.get(pos..pos + 8)?;
+        let id = [header[0], header[1], header[2], header[3]];
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let start = pos + 8;
+        let end = start.saturating_add(len).min(data.len());
+        // Chunks are padded to an even size
+        pos = end + len % 2;
+        Some((id, &data[start..end]))
+    })
+}
+
+/// Decode a WAV file of 16, 24 or 32-bit integer PCM or 32-bit float
+/// samples, including WAVE_FORMAT_EXTENSIBLE files. A trailing incomplete
+/// frame is dropped.
+pub(crate) fn decode(data: &[u8]) -> Result<Decoded, MixerError> {
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(MixerError::InvalidWav("not a RIFF/WAVE file"));
+    }
+    let fmt = chunks(data)
+        .find(|(id, _)| id == b"fmt ")
+        .map(|(_, body)| body)
+        .ok_or(MixerError::InvalidWav("missing fmt chunk"))?;
+    if fmt.len() < 16 {
+        return Err(MixerError::InvalidWav("fmt chunk is too short"));
+    }
+    let u16_at = |pos: usize| u16::from_le_bytes([fmt[pos], fmt[pos + 1]]);
+    let mut tag = u16_at(0);
+    let channels = u16_at(2);
+    let sample_rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+    let bits = u16_at(14);
+    if tag == WAVE_FORMAT_EXTENSIBLE {
+        if fmt.len() < 26 {
+            return Err(MixerError::InvalidWav("fmt extension is too short"));
+        }
+        tag = u16_at(24);
+    }
+    if channels == 0 {
+        return Err(MixerError::InvalidWav("file has no channels"));
+    }
+    if sample_rate == 0 {
+        return Err(MixerError::InvalidSampleRate(sample_rate));
+    }
+    let format = match (tag, bits) {
+        (WAVE_FORMAT_PCM, 16) => WavFormat::Pcm16,
+        (WAVE_FORMAT_PCM, 24) => WavFormat::Pcm24,
+        (WAVE_FORMAT_PCM, 32) | (WAVE_FORMAT_IEEE_FLOAT, 32) => WavFormat::Float32,
+        (WAVE_FORMAT_PCM, bits) => return Err(MixerError::UnsupportedBitDepth(bits as u32)),
+        (tag, _) => return Err(MixerError::UnsupportedWavFormat(tag)),
+    };
+    let body = chunks(data)
+        .find(|(id, _)| id == b"data")
+        .map(|(_, body)| body)
+        .ok_or(MixerError::InvalidWav("missing data chunk"))?;
+
+    let frame_len = format.bytes_per_sample() * channels as usize;
+    let body = &body[..body.len() / frame_len * frame_len];
+    let samples = match (format, tag) {
+        (WavFormat::Pcm16, _) => body
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32_768.0)
+            .collect(),
+        (WavFormat::Pcm24, _) => body
+            .chunks_exact(3)
+            .map(|b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0)
+            .collect(),
+        (WavFormat::Float32, WAVE_FORMAT_PCM) => body
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0)
+            .collect(),
+        (WavFormat::Float32, _) => body
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+    };
+    Ok(Decoded {
+        samples,
+        sample_rate,
+        channels,
+    })
+}
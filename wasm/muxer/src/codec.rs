@@ -0,0 +1,546 @@
+//! Codec string parsing and codec configuration records
+
+/// MP4 sample entry type for a WebCodecs video codec string
+pub(crate) fn video_sample_entry(codec: &str) -> Option<[u8; 4]> {
+    match codec.split('.').next()? {
+        "avc1" | "avc3" => Some(*b"avc1"),
+        "hvc1" | "hev1" => Some(*b"hvc1"),
+        "vp09" => Some(*b"vp09"),
+        "av01" => Some(*b"av01"),
+        _ => None,
+    }
+}
+
+/// MP4 sample entry type for a WebCodecs audio codec string
+pub(crate) fn audio_sample_entry(codec: &str) -> Option<[u8; 4]> {
+    match codec.split('.').next()? {
+        "mp4a" => Some(*b"mp4a"),
+        "opus" => Some(*b"Opus"),
+        _ => None,
+    }
+}
+
+/// Matroska CodecID for a WebCodecs video codec string allowed in WebM
+pub(crate) fn webm_video_codec_id(codec: &str) -> Option<&'static str> {
+    match codec.split('.').next()? {
+        "vp8" => Some("V_VP8"),
+        "vp09" | "vp9" => Some("V_VP9"),
+        "av01" => Some("V_AV1"),
+        _ => None,
+    }
+}
+
+/// Matroska CodecID for a WebCodecs audio codec string allowed in WebM
+pub(crate) fn webm_audio_codec_id(codec: &str) -> Option<&'static str> {
+    match codec.split('.').next()? {
+        "opus" => Some("A_OPUS"),
+        "vorbis" => Some("A_VORBIS"),
+        _ => None,
+    }
+}
+
+/// Build an OpusHead identification header for mono/stereo (mapping family
+/// 0) or multichannel (family 1, Vorbis channel order) streams
+pub(crate) fn opus_head(sample_rate: u32, channels: u32) -> Vec<u8> {
+    let channels = channels.clamp(1, 8) as u8;
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(channels);
+    head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip
+    head.extend_from_slice(&sample_rate.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    if channels <= 2 {
+        head.push(0);
+    } else {
+        // One stream per channel, none coupled
+        head.push(1);
+        head.push(channels); // stream count
+        head.push(0); // coupled count
+        head.extend(0..channels);
+    }
+    head
+}
+
+/// Convert an OpusHead header (little-endian, as found in WebCodecs'
+/// description and WebM CodecPrivate) into a dOps box payload (big-endian)
+pub(crate) fn dops_from_opus_head(head: &[u8]) -> Option<Vec<u8>> {
+    if head.len() < 19 || &head[..8] != b"OpusHead" {
+        return None;
+    }
+    let channels = head[9];
+    let family = head[18];
+    let mut dops = vec![0, channels]; // Version, OutputChannelCount
+    dops.extend_from_slice(&u16::from_le_bytes([head[10], head[11]]).to_be_bytes());
+    dops.extend_from_slice(
+        &u32::from_le_bytes([head[12], head[13], head[14], head[15]]).to_be_bytes(),
+    );
+    dops.extend_from_slice(&i16::from_le_bytes([head[16], head[17]]).to_be_bytes());
+    dops.push(family);
+    if family != 0 {
+        // StreamCount, CoupledCount and the channel mapping table
+        let table = head.get(19..21 + channels as usize)?;
+        dops.extend_from_slice(table);
+    }
+    Some(dops)
+}
+
+/// Iterate over the NAL units of a 4-byte length-prefixed (AVCC) buffer
+fn avcc_nal_units(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let header = data.get(pos..pos + 4)?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let nal = data.get(pos + 4..pos + 4 + len)?;
+        pos += 4 + len;
+        Some(nal)
+    })
+}
+
+/// Whether `data` is start-code delimited (Annex B) rather than a complete
+/// length-prefixed buffer. A 4-byte length of 1 looks like a start code, so
+/// a buffer that parses fully as AVCC counts as AVCC.
+pub(crate) fn is_annex_b(data: &[u8]) -> bool {
+    let start_code = data.starts_with(&[0, 0, 1]) || data.starts_with(&[0, 0, 0, 1]);
+    start_code && avcc_nal_units(data).map(|nal| nal.len() + 4).sum::<usize>() != data.len()
+}
+
+/// Split a start-code delimited (Annex B) buffer into NAL units, without
+/// the zero bytes that precede a start code
+fn annex_b_nal_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i..i + 3] == [0, 0, 1] {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    let ends = starts
+        .iter()
+        .skip(1)
+        .map(|&start| start - 3)
+        .chain([data.len()]);
+    starts
+        .iter()
+        .zip(ends)
+        .filter_map(|(&start, end)| {
+            let nal = &data[start..end];
+            let len = nal.len() - nal.iter().rev().take_while(|&&b| b == 0).count();
+            (len > 0).then(|| &nal[..len])
+        })
+        .collect()
+}
+
+/// Rewrite a start-code delimited (Annex B) H.264/HEVC buffer with 4-byte
+/// big-endian length prefixes (AVCC), the framing MP4 samples require
+pub(crate) fn annex_b_to_avcc(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    for nal in annex_b_nal_units(data) {
+        output.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+        output.extend_from_slice(nal);
+    }
+    output
+}
+
+/// Why a video chunk cannot belong to the given codec, judged from the
+/// framing of its first bytes. Returns `None` when the chunk looks plausible;
+/// this catches wiring mistakes, not corrupt bitstreams.
+pub(crate) fn video_chunk_mismatch(codec: &str, data: &[u8], is_key: bool) -> Option<&'static str> {
+    match codec.split('.').next()? {
+        "avc1" | "avc3" | "hvc1" | "hev1" => {
+            let annex_b = data.starts_with(&[0, 0, 1]) || data.starts_with(&[0, 0, 0, 1]);
+            if annex_b || avcc_nal_units(data).map(|nal| nal.len() + 4).sum::<usize>() == data.len()
+            {
+                None
+            } else {
+                Some("expected length-prefixed or start-code delimited NAL units")
+            }
+        }
+        "vp8" => {
+            // Keyframes carry a start code after the 3-byte frame tag
+            let keyframe = data.first().is_some_and(|b| b & 1 == 0);
+            if keyframe && data.get(3..6) != Some(&[0x9D, 0x01, 0x2A]) {
+                Some("VP8 keyframe start code is missing")
+            } else if is_key && !keyframe {
+                Some("chunk marked as key is not a VP8 keyframe")
+            } else {
+                None
+            }
+        }
+        "vp09" | "vp9" => match data.first() {
+            Some(b) if b >> 6 == 0b10 => None,
+            _ => Some("VP9 frame marker is missing"),
+        },
+        "av01" => {
+            // First OBU header: forbidden bit clear and a defined OBU type
+            let header = *data.first()?;
+            let obu_type = (header >> 3) & 0xF;
+            if header & 0x80 != 0 || !matches!(obu_type, 1..=8 | 15) {
+                Some("chunk does not start with an AV1 OBU header")
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Build an AVCDecoderConfigurationRecord (avcC payload) from the SPS/PPS
+/// carried in-band in a length-prefixed keyframe
+pub(crate) fn avc_config_from_keyframe(data: &[u8]) -> Option<Vec<u8>> {
+    let mut sps = Vec::new();
+    let mut pps = Vec::new();
+    for nal in avcc_nal_units(data) {
+        match nal.first().map(|b| b & 0x1F) {
+            Some(7) => sps.push(nal),
+            Some(8) => pps.push(nal),
+            _ => {}
+        }
+    }
+    if sps.is_empty() || pps.is_empty() || sps[0].len() < 4 {
+        return None;
+    }
+
+    let mut config = vec![
+        1,         // configurationVersion
+        sps[0][1], // AVCProfileIndication
+        sps[0][2], // profile_compatibility
+        sps[0][3], // AVCLevelIndication
+        0xFF,      // reserved + lengthSizeMinusOne = 3
+        0xE0 | sps.len() as u8,
+    ];
+    for nal in &sps {
+        config.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+        config.extend_from_slice(nal);
+    }
+    config.push(pps.len() as u8);
+    for nal in &pps {
+        config.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+        config.extend_from_slice(nal);
+    }
+    Some(config)
+}
+
+/// Numeric fields of a dotted codec string, from the first after the
+/// fourcc; `None` for a missing or non-numeric field
+fn codec_fields(codec: &str) -> Vec<Option<u8>> {
+    codec
+        .split('.')
+        .skip(1)
+        .map(|field| field.parse().ok())
+        .collect()
+}
+
+/// Build a VPCodecConfigurationRecord (vpcC payload after version and
+/// flags) from a codec string such as "vp09.00.10.08". The optional
+/// fields default to 4:2:0 colocated chroma, BT.709 color and limited
+/// range, as in the VP9 codec string definition.
+pub(crate) fn vpcc_from_codec(codec: &str) -> Option<Vec<u8>> {
+    let fields = codec_fields(codec);
+    if fields.len() < 3 {
+        return None;
+    }
+    let field = |index: usize, default: u8| fields.get(index).copied().unwrap_or(Some(default));
+    let bit_depth = field(2, 8)?;
+    let chroma_subsampling = field(3, 1)?;
+    let full_range = field(7, 0)?;
+    Some(vec![
+        field(0, 0)?,  // profile
+        field(1, 10)?, // level
+        (bit_depth << 4) | ((chroma_subsampling & 0x7) << 1) | (full_range & 1),
+        field(4, 1)?, // colourPrimaries
+        field(5, 1)?, // transferCharacteristics
+        field(6, 1)?, // matrixCoefficients
+        0,
+        0, // codecInitializationDataSize
+    ])
+}
+
+/// Read a leb128 value, returning it and the number of bytes it took.
+/// AV1 limits values to 32 bits; larger ones are rejected.
+fn leb128(data: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0u64;
+    for (index, &byte) in data.iter().take(8).enumerate() {
+        value |= ((byte & 0x7F) as u64) << (7 * index);
+        if byte & 0x80 == 0 {
+            let value = u32::try_from(value).ok()?;
+            return Some((value as usize, index + 1));
+        }
+    }
+    None
+}
+
+/// The sequence header OBU of an AV1 temporal unit, including its header
+fn av1_sequence_header(data: &[u8]) -> Option<&[u8]> {
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data[pos];
+        let obu_type = (header >> 3) & 0xF;
+        let header_len = 1 + ((header >> 2) & 1) as usize;
+        // Without obu_has_size_field the OBU runs to the end of the data
+        let rest = data.get(pos + header_len..)?;
+        let (size, size_len) = if header & 0x2 != 0 {
+            leb128(rest)?
+        } else {
+            (rest.len(), 0)
+        };
+        let end = (pos + header_len + size_len).checked_add(size)?;
+        if obu_type == 1 {
+            return data.get(pos..end);
+        }
+        pos = end;
+    }
+    None
+}
+
+/// Build an AV1CodecConfigurationRecord (av1C payload) from a codec string
+/// such as "av01.0.04M.08" and the sequence header OBU carried in the first
+/// keyframe. The optional monochrome and chroma fields default to 4:2:0.
+pub(crate) fn av1c_from_keyframe(codec: &str, data: &[u8]) -> Option<Vec<u8>> {
+    let mut fields = codec.split('.').skip(1);
+    let profile: u8 = fields.next()?.parse().ok()?;
+    let level_tier = fields.next()?;
+    let level: u8 = level_tier.get(..2)?.parse().ok()?;
+    let high_tier = level_tier.ends_with('H');
+    let bit_depth: u8 = fields.next()?.parse().ok()?;
+    let monochrome = fields.next().is_some_and(|field| field == "1");
+    let chroma = fields.next().unwrap_or("110").as_bytes();
+    let chroma_bit = |index: usize, default: u8| {
+        chroma
+            .get(index)
+            .map_or(default, |digit| digit.wrapping_sub(b'0') & 0x3)
+    };
+    let sequence_header = av1_sequence_header(data)?;
+
+    let mut config = vec![
+        0x81, // marker + version 1
+        (profile << 5) | (level & 0x1F),
+        (high_tier as u8) << 7
+            | ((bit_depth > 8) as u8) << 6
+            | ((bit_depth == 12) as u8) << 5
+            | (monochrome as u8) << 4
+            | (chroma_bit(0, 1) & 1) << 3
+            | (chroma_bit(1, 1) & 1) << 2
+            | chroma_bit(2, 0),
+        0, // no initial_presentation_delay
+    ];
+    config.extend_from_slice(sequence_header);
+    Some(config)
+}
+
+/// Length of the ADTS header in front of `data` when it holds exactly one
+/// well-formed ADTS frame: 7 bytes, or 9 with a CRC. Raw AAC has no
+/// framing of its own, so anything else is taken to be raw.
+pub(crate) fn adts_header_len(data: &[u8]) -> Option<usize> {
+    let header = data.get(..7)?;
+    // Syncword 0xFFF and layer 0
+    if header[0] != 0xFF || header[1] & 0xF6 != 0xF0 {
+        return None;
+    }
+    let sample_rate_index = (header[2] >> 2) & 0xF;
+    let frame_len = ((header[3] as usize & 0x3) << 11)
+        | ((header[4] as usize) << 3)
+        | (header[5] as usize >> 5);
+    let header_len = if header[1] & 1 == 1 { 7 } else { 9 };
+    let valid = sample_rate_index < AAC_SAMPLE_RATES.len() as u8
+        && frame_len > header_len
+        && frame_len == data.len();
+    valid.then_some(header_len)
+}
+
+/// The raw AAC frame inside an ADTS frame, as MP4 stores it; other data is
+/// returned unchanged
+pub(crate) fn strip_adts(data: &[u8]) -> &[u8] {
+    match adts_header_len(data) {
+        Some(header_len) => &data[header_len..],
+        None => data,
+    }
+}
+
+/// Build an AudioSpecificConfig from the profile, sampling frequency index
+/// and channel configuration in the header of an ADTS frame
+pub(crate) fn asc_from_adts(data: &[u8]) -> Option<Vec<u8>> {
+    adts_header_len(data)?;
+    let object_type = (data[2] as u16 >> 6) + 1;
+    let sample_rate_index = (data[2] as u16 >> 2) & 0xF;
+    let channels = ((data[2] as u16 & 1) << 2) | (data[3] as u16 >> 6);
+    let bits = (object_type << 11) | (sample_rate_index << 7) | (channels << 3);
+    Some(bits.to_be_bytes().to_vec())
+}
+
+/// Largest Opus frame in bytes
+const OPUS_MAX_FRAME_BYTES: usize = 1275;
+/// Longest Opus packet, in units of 2.5 ms
+const OPUS_MAX_PACKET_DURATION: usize = 48;
+
+/// Duration of each frame of an Opus packet in units of 2.5 ms, from the
+/// configuration number in its TOC byte
+fn opus_frame_duration(toc: u8) -> usize {
+    let config = (toc >> 3) as usize;
+    match config {
+        0..=11 => [4, 8, 16, 24][config % 4],
+        12..=15 => [4, 8][config % 2],
+        _ => [1, 2, 4, 8][config % 4],
+    }
+}
+
+/// A frame length coded in one or two bytes at the start of `data`, with
+/// the number of bytes it took
+fn opus_frame_len(data: &[u8]) -> Option<(usize, usize)> {
+    match *data.first()? {
+        len @ 0..=251 => Some((len as usize, 1)),
+        first => Some((first as usize + 4 * *data.get(1)? as usize, 2)),
+    }
+}
+
+/// Whether `data` is a well-formed Opus packet: a TOC byte followed by
+/// frames whose count, coded lengths and padding add up to the packet size
+/// (RFC 6716 section 3.4)
+pub(crate) fn is_opus_packet(data: &[u8]) -> bool {
+    let Some((&toc, payload)) = data.split_first() else {
+        return false;
+    };
+    match toc & 0x3 {
+        0 => payload.len() <= OPUS_MAX_FRAME_BYTES,
+        1 => payload.len() % 2 == 0 && payload.len() / 2 <= OPUS_MAX_FRAME_BYTES,
+        2 => opus_frame_len(payload).is_some_and(|(first, used)| {
+            let rest = payload.len() - used;
+            first <= rest && first <= OPUS_MAX_FRAME_BYTES && rest - first <= OPUS_MAX_FRAME_BYTES
+        }),
+        _ => is_opus_code3_packet(toc, payload),
+    }
+}
+
+/// Check a packet with an arbitrary number of frames (code 3)
+fn is_opus_code3_packet(toc: u8, payload: &[u8]) -> bool {
+    let Some((&count_byte, mut rest)) = payload.split_first() else {
+        return false;
+    };
+    let (vbr, padded) = (count_byte & 0x80 != 0, count_byte & 0x40 != 0);
+    let count = (count_byte & 0x3F) as usize;
+    if count == 0 || count * opus_frame_duration(toc) > OPUS_MAX_PACKET_DURATION {
+        return false;
+    }
+    let mut padding = 0;
+    // Each length byte of 255 adds 254 bytes and continues in the next one
+    let mut more = padded;
+    while more {
+        let Some((&byte, tail)) = rest.split_first() else {
+            return false;
+        };
+        rest = tail;
+        padding += byte.min(254) as usize;
+        more = byte == 255;
+    }
+    let Some(mut frames_len) = rest.len().checked_sub(padding) else {
+        return false;
+    };
+    if !vbr {
+        return frames_len % count == 0 && frames_len / count <= OPUS_MAX_FRAME_BYTES;
+    }
+    for _ in 1..count {
+        let Some((len, used)) = opus_frame_len(rest) else {
+            return false;
+        };
+        rest = &rest[used..];
+        frames_len = match frames_len.checked_sub(used + len) {
+            Some(left) if len <= OPUS_MAX_FRAME_BYTES => left,
+            _ => return false,
+        };
+    }
+    frames_len <= OPUS_MAX_FRAME_BYTES
+}
+
+/// Why an audio chunk cannot belong to the given codec; `None` when it
+/// looks plausible. Only Opus has framing that can be checked; raw AAC
+/// frames carry none.
+pub(crate) fn audio_chunk_mismatch(codec: &str, data: &[u8]) -> Option<&'static str> {
+    match codec.split('.').next()? {
+        "opus" if !is_opus_packet(data) => Some("not a well-formed Opus packet"),
+        _ => None,
+    }
+}
+
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Build an AudioSpecificConfig for an AAC codec string such as "mp4a.40.2"
+pub(crate) fn aac_audio_specific_config(codec: &str, sample_rate: u32, channels: u32) -> Vec<u8> {
+    let object_type = codec
+        .split('.')
+        .nth(2)
+        .and_then(|s| s.parse::<u32>().ok())
+        .filter(|&aot| aot > 0 && aot < 31)
+        .unwrap_or(2);
+    let channels = channels.min(7);
+
+    match AAC_SAMPLE_RATES
+        .iter()
+        .position(|&rate| rate == sample_rate)
+    {
+        Some(index) => {
+            let bits = (object_type << 11) | ((index as u32) << 7) | (channels << 3);
+            (bits as u16).to_be_bytes().to_vec()
+        }
+        None => {
+            // Explicit frequency: 5 + 4 + 24 + 4 + 3 bits = 40 bits
+            let bits: u64 = ((object_type as u64) << 35)
+                | (0xF << 31)
+                | ((sample_rate as u64 & 0xFF_FFFF) << 7)
+                | ((channels as u64) << 3);
+            bits.to_be_bytes()[3..].to_vec()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vpcc_follows_the_codec_string() {
+        // Profile 0, level 1.0, 8-bit 4:2:0 colocated, BT.709, limited range
+        assert_eq!(
+            vpcc_from_codec("vp09.00.10.08"),
+            Some(vec![0, 10, 0x82, 1, 1, 1, 0, 0])
+        );
+        assert_eq!(
+            vpcc_from_codec("vp09.02.10.10.01.09.16.09.01"),
+            Some(vec![2, 10, 0xA3, 9, 16, 9, 0, 0])
+        );
+        assert_eq!(vpcc_from_codec("vp09.00"), None);
+    }
+
+    #[test]
+    fn av1c_carries_the_sequence_header() {
+        // Temporal delimiter, then a sequence header OBU with a 3-byte payload
+        let keyframe = [0x12, 0x00, 0x0A, 0x03, 0xAA, 0xBB, 0xCC, 0x32, 0x00];
+        assert_eq!(
+            av1c_from_keyframe("av01.0.04M.08", &keyframe),
+            Some(vec![0x81, 0x04, 0x0C, 0, 0x0A, 0x03, 0xAA, 0xBB, 0xCC])
+        );
+        // High tier, 10-bit, level 5.1
+        assert_eq!(
+            av1c_from_keyframe("av01.0.13H.10", &keyframe).map(|c| c[..3].to_vec()),
+            Some(vec![0x81, 13, 0xCC])
+        );
+    }
+
+    #[test]
+    fn malformed_obus_are_rejected() {
+        // Extension flag on the last byte, without room for the extension
+        assert_eq!(av1_sequence_header(&[0x04]), None);
+        // A size beyond 32 bits
+        let oversized = [0x0A, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F];
+        assert_eq!(av1_sequence_header(&oversized), None);
+        // A size running past the end of the data
+        assert_eq!(av1_sequence_header(&[0x0A, 0x05, 0xAA]), None);
+        assert_eq!(
+            leb128(&[0xFF, 0xFF, 0xFF, 0xFF, 0x0F]),
+            Some((u32::MAX as usize, 5))
+        );
+    }
+}
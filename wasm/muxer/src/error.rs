@@ -0,0 +1,203 @@
+//! Errors surfaced to JavaScript as thrown exceptions
+
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum MuxerError {
+    /// Neither a video nor an audio track has been configured
+    NotConfigured,
+    /// Chunks were added for a track that was never configured
+    MissingConfig {
+        track: &'static str,
+    },
+    UnsupportedContainer(String),
+    /// A track id that was not returned by add_video_track
+    UnknownTrack {
+        track_id: u32,
+    },
+    /// A feature was requested that the selected container does not offer
+    UnsupportedOperation {
+        feature: &'static str,
+        container: &'static str,
+    },
+    UnsupportedCodec {
+        track: &'static str,
+        codec: String,
+    },
+    InvalidVideoConfig {
+        width: u32,
+        height: u32,
+    },
+    InvalidAudioConfig {
+        sample_rate: u32,
+        channels: u32,
+    },
+    /// Codec description passed by the caller cannot be used
+    InvalidCodecConfig {
+        track: &'static str,
+        reason: &'static str,
+    },
+    /// Chunk framing does not match the configured codec family
+    CodecMismatch {
+        track: &'static str,
+        index: usize,
+        codec: String,
+        reason: &'static str,
+    },
+    MalformedChunk {
+        track: &'static str,
+        index: usize,
+        reason: String,
+    },
+    /// A chunk without any data
+    EmptyChunk {
+        track: &'static str,
+        index: usize,
+    },
+    /// A chunk larger than the limit from set_max_chunk_bytes
+    ChunkTooLarge {
+        track: &'static str,
+        index: usize,
+        size: usize,
+        max: usize,
+    },
+    /// A subtitle cue with non-finite times or that ends before it starts
+    InvalidCue {
+        start: f64,
+        end: f64,
+    },
+    /// A chapter time that is NaN or infinite
+    InvalidChapterTime(f64),
+    /// A timescale of zero ticks per second
+    InvalidTimescale {
+        track: &'static str,
+    },
+    /// A set_metadata key that is not one of the supported tags
+    UnknownMetadataKey(String),
+    /// A fragment duration target that is negative or not finite
+    InvalidFragmentDuration(f64),
+    /// An A/V sync tolerance that is negative or not finite
+    InvalidSyncTolerance(f64),
+    /// A trim range with non-finite times or that ends before it starts
+    InvalidTrimRange {
+        start: f64,
+        end: f64,
+    },
+    /// Data passed to parse_init_segment that is not a usable ftyp + moov
+    InvalidInitSegment(&'static str),
+    /// A creation time that is negative or not finite
+    InvalidCreationTime(f64),
+}
+
+impl fmt::Display for MuxerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotConfigured => {
+                write!(
+                    f,
+                    "no track configured: call configure_video or configure_audio first"
+                )
+            }
+            Self::MissingConfig { track } => write!(
+                f,
+                "{track} chunks were added but the {track} track is not configured"
+            ),
+            Self::UnsupportedContainer(container) => {
+                write!(
+                    f,
+                    "unsupported container \"{container}\": expected \"mp4\" or \"webm\""
+                )
+            }
+            Self::UnknownTrack { track_id } => write!(f, "no video track with id {track_id}"),
+            Self::UnsupportedOperation { feature, container } => {
+                write!(
+                    f,
+                    "{feature} is not supported for the {container} container"
+                )
+            }
+            Self::UnsupportedCodec { track, codec } => {
+                write!(f, "unsupported {track} codec \"{codec}\"")
+            }
+            Self::InvalidVideoConfig { width, height } => {
+                write!(f, "invalid video dimensions {width}x{height}")
+            }
+            Self::InvalidAudioConfig {
+                sample_rate,
+                channels,
+            } => write!(
+                f,
+                "invalid audio configuration: {sample_rate} Hz, {channels} channels"
+            ),
+            Self::InvalidCodecConfig { track, reason } => {
+                write!(f, "invalid {track} codec configuration: {reason}")
+            }
+            Self::CodecMismatch {
+                track,
+                index,
+                codec,
+                reason,
+            } => write!(
+                f,
+                "{track} chunk #{index} does not match codec \"{codec}\": {reason}"
+            ),
+            Self::MalformedChunk {
+                track,
+                index,
+                reason,
+            } => write!(f, "malformed {track} chunk #{index}: {reason}"),
+            Self::EmptyChunk { track, index } => write!(f, "{track} chunk #{index} is empty"),
+            Self::ChunkTooLarge {
+                track,
+                index,
+                size,
+                max,
+            } => write!(
+                f,
+                "{track} chunk #{index} is {size} bytes, over the limit of {max} bytes"
+            ),
+            Self::InvalidCue { start, end } => write!(
+                f,
+                "invalid subtitle cue from {start} to {end} us: expected finite times with the end after the start"
+            ),
+            Self::InvalidChapterTime(time) => {
+                write!(f, "invalid chapter time {time}: expected a finite number")
+            }
+            Self::InvalidTimescale { track } => {
+                write!(
+                    f,
+                    "invalid {track} timescale 0: expected ticks per second above 0"
+                )
+            }
+            Self::UnknownMetadataKey(key) => write!(
+                f,
+                "unknown metadata key \"{key}\": expected title, artist, comment or encoder"
+            ),
+            Self::InvalidFragmentDuration(seconds) => write!(
+                f,
+                "invalid fragment duration {seconds} s: expected a finite number of seconds, 0 or more"
+            ),
+            Self::InvalidSyncTolerance(seconds) => write!(
+                f,
+                "invalid A/V sync tolerance {seconds} s: expected a finite number of seconds, 0 or more"
+            ),
+            Self::InvalidTrimRange { start, end } => write!(
+                f,
+                "invalid trim range from {start} to {end} us: expected finite times with the end after the start"
+            ),
+            Self::InvalidInitSegment(reason) => write!(f, "invalid init segment: {reason}"),
+            Self::InvalidCreationTime(seconds) => write!(
+                f,
+                "invalid creation time {seconds}: expected seconds since 1970, 0 or more"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MuxerError {}
+
+impl From<MuxerError> for JsValue {
+    fn from(error: MuxerError) -> Self {
+        JsError::new(&error.to_string()).into()
+    }
+}
@@ -1,13 +1,153 @@
+use js_sys::{Function, Uint8Array};
+use std::borrow::Cow;
 use wasm_bindgen::prelude::*;
-use js_sys::Uint8Array;
 
-/// MP4 Muxer for combining encoded video and audio chunks into MP4 container
+mod codec;
+mod error;
+mod metadata;
+mod mp4;
+mod probe;
+mod subtitle;
+mod timing;
+mod webm;
+
+use error::MuxerError;
+use metadata::{Metadata, Tag};
+use mp4::{BoxWriter, TrackFragment, TrackInfo};
+use subtitle::{Chapter, Cue};
+use webm::{Block, WebmChapter, WebmTrack};
+
+/// Default video track timescale (ticks per second)
+const DEFAULT_VIDEO_TIMESCALE: u32 = 90_000;
+/// Subtitle track timescale (milliseconds)
+const SUBTITLE_TIMESCALE: u32 = 1000;
+/// Default movie timescale used for mvhd/tkhd durations (milliseconds)
+const DEFAULT_MOVIE_TIMESCALE: u32 = 1000;
+/// Frame rate assumed for the duration of a lone video sample
+const DEFAULT_FRAME_RATE: u32 = 30;
+/// PCM frames carried by one AAC access unit
+const AAC_FRAME_SAMPLES: u32 = 1024;
+/// Typical Opus packet duration in milliseconds
+const OPUS_FRAME_MS: u32 = 20;
+/// Buffered audio (microseconds) that triggers a streamed fragment when
+/// there is no video track to split on keyframes
+const AUDIO_FRAGMENT_US: f64 = 1_000_000.0;
+/// Default difference between the video and audio durations (microseconds)
+/// above which finalize reports an A/V sync warning
+const DEFAULT_SYNC_TOLERANCE_US: f64 = 100_000.0;
+
+/// Longest stretch of one track's media (microseconds) stored contiguously
+/// in mdat before the other tracks' samples of the same time follow
+const INTERLEAVE_US: f64 = 500_000.0;
+
+/// Leading chunks of each track checked against the declared codec
+const CODEC_CHECK_CHUNKS: usize = 3;
+
+/// Output container format
+#[derive(Clone, Copy, PartialEq)]
+enum Container {
+    Mp4,
+    Webm,
+}
+
+impl Container {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "mp4" => Some(Self::Mp4),
+            "webm" => Some(Self::Webm),
+            _ => None,
+        }
+    }
+}
+
+/// Media-specific parameters of a track
+pub(crate) enum TrackKind {
+    Video {
+        width: u32,
+        height: u32,
+    },
+    Audio {
+        sample_rate: u32,
+        channels: u32,
+    },
+    /// Subtitles: tx3g in MP4, WebVTT in WebM
+    Text,
+    /// Chapter titles: a disabled text track referenced by the first track
+    /// in MP4 (QuickTime chapters), a Chapters element in WebM
+    Chapters,
+}
+
+/// Muxer for combining encoded video and audio chunks into an MP4 (default)
+/// or WebM container
 #[wasm_bindgen]
 pub struct Muxer {
-    video_chunks: Vec<Vec<u8>>,
-    audio_chunks: Vec<Vec<u8>>,
-    video_config: Option<VideoConfig>,
+    container: Container,
+    /// Video tracks in track id order; the single-track methods use the
+    /// first one
+    video_tracks: Vec<VideoTrack>,
+    audio_chunks: Vec<Chunk>,
     audio_config: Option<AudioConfig>,
+    /// Caller-provided audio description (AudioSpecificConfig or OpusHead)
+    audio_codec_config: Option<Vec<u8>>,
+    /// AudioSpecificConfig derived from the first ADTS header, used when
+    /// the caller provides none
+    stream_audio_config: Option<Vec<u8>>,
+    /// Strip ADTS headers from the chunks of an AAC track
+    strip_adts: bool,
+    /// Subtitle cues sorted by start time
+    subtitle_cues: Vec<Cue>,
+    /// Chapters sorted by time
+    chapters: Vec<Chapter>,
+    /// Sequence number of the next moof (mfhd), starting at 1
+    fragment_sequence: u32,
+    /// Receives the init segment and each fragment in streaming mode
+    output_callback: Option<Function>,
+    init_segment_sent: bool,
+    /// Tags from set_metadata, in the order they were first set
+    tags: Vec<(Tag, String)>,
+    /// Ticks per second of the mvhd/tkhd durations and edit lists
+    movie_timescale: u32,
+    video_timescale: u32,
+    /// Audio track timescale; the sample rate when not set
+    audio_timescale: Option<u32>,
+    /// Write moov before mdat in finalized MP4 files
+    faststart: bool,
+    /// Minimum media per fragment in microseconds; `None` flushes whatever
+    /// is buffered
+    fragment_duration: Option<f64>,
+    /// Video/audio duration difference in microseconds that finalize
+    /// tolerates before warning
+    sync_tolerance: f64,
+    /// Warning from the last finalize when the durations diverged
+    sync_warning: Option<String>,
+    /// Start and end in microseconds of the part of the recording that
+    /// finalize keeps
+    trim_range: Option<(f64, f64)>,
+    /// Largest chunk accepted, in bytes; `None` for no limit
+    max_chunk_bytes: Option<usize>,
+    /// Creation time in seconds since the Unix epoch; the time the file is
+    /// written when unset
+    creation_time: Option<u64>,
+}
+
+/// Encoded chunk with its presentation timestamp in microseconds
+struct Chunk {
+    data: Vec<u8>,
+    timestamp: f64,
+    is_key: bool,
+}
+
+/// A video track with its buffered chunks
+#[derive(Default)]
+struct VideoTrack {
+    config: Option<VideoConfig>,
+    chunks: Vec<Chunk>,
+    /// Caller-provided decoder configuration record (avcC/hvcC/av1C
+    /// payload)
+    codec_config: Option<Vec<u8>>,
+    /// avcC or av1C derived from the parameter sets or sequence header of
+    /// the first H.264/AV1 keyframe, used when the caller provides none
+    stream_codec_config: Option<Vec<u8>>,
 }
 
 struct VideoConfig {
@@ -22,74 +162,2078 @@ struct AudioConfig {
     codec: String,
 }
 
+/// Result of Muxer.parse_init_segment. The video getters are undefined
+/// without a video track, the audio getters without an audio track.
+#[wasm_bindgen]
+pub struct InitSegmentInfo {
+    video: Option<probe::ParsedVideo>,
+    audio: Option<probe::ParsedAudio>,
+}
+
+#[wasm_bindgen]
+impl InitSegmentInfo {
+    /// Codec string of the first video track, e.g. "avc1.64001f"
+    #[wasm_bindgen(getter)]
+    pub fn video_codec(&self) -> Option<String> {
+        self.video.as_ref().map(|video| video.codec.clone())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> Option<u32> {
+        self.video.as_ref().map(|video| video.width)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> Option<u32> {
+        self.video.as_ref().map(|video| video.height)
+    }
+
+    /// avcC/hvcC/vpcC/av1C payload, the VideoDecoderConfig description
+    #[wasm_bindgen(getter)]
+    pub fn video_description(&self) -> Option<Uint8Array> {
+        let description = self.video.as_ref()?.description.as_deref()?;
+        Some(Uint8Array::from(description))
+    }
+
+    /// Codec string of the first audio track, e.g. "mp4a.40.2" or "opus"
+    #[wasm_bindgen(getter)]
+    pub fn audio_codec(&self) -> Option<String> {
+        self.audio.as_ref().map(|audio| audio.codec.clone())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.audio.as_ref().map(|audio| audio.sample_rate)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn channels(&self) -> Option<u32> {
+        self.audio.as_ref().map(|audio| audio.channels)
+    }
+
+    /// AudioSpecificConfig (from esds) for AAC, an OpusHead header (from
+    /// dOps) for Opus: the AudioDecoderConfig description
+    #[wasm_bindgen(getter)]
+    pub fn audio_description(&self) -> Option<Uint8Array> {
+        let description = self.audio.as_ref()?.description.as_deref()?;
+        Some(Uint8Array::from(description))
+    }
+}
+
 #[wasm_bindgen]
 impl Muxer {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Self {
         Self {
-            video_chunks: Vec::new(),
+            container: Container::Mp4,
+            video_tracks: Vec::new(),
             audio_chunks: Vec::new(),
-            video_config: None,
             audio_config: None,
+            audio_codec_config: None,
+            stream_audio_config: None,
+            strip_adts: true,
+            subtitle_cues: Vec::new(),
+            chapters: Vec::new(),
+            fragment_sequence: 1,
+            output_callback: None,
+            init_segment_sent: false,
+            tags: Vec::new(),
+            movie_timescale: DEFAULT_MOVIE_TIMESCALE,
+            video_timescale: DEFAULT_VIDEO_TIMESCALE,
+            audio_timescale: None,
+            faststart: false,
+            fragment_duration: None,
+            sync_tolerance: DEFAULT_SYNC_TOLERANCE_US,
+            sync_warning: None,
+            trim_range: None,
+            max_chunk_bytes: None,
+            creation_time: None,
         }
     }
 
-    /// Configure video track parameters
+    /// Create a muxer for the given container ("mp4" or "webm")
     #[wasm_bindgen]
-    pub fn configure_video(&mut self, width: u32, height: u32, codec: &str) {
-        self.video_config = Some(VideoConfig {
-            width,
-            height,
-            codec: codec.to_string(),
+    pub fn new_with_container(container: &str) -> Result<Muxer, JsValue> {
+        let container = Container::parse(container)
+            .ok_or_else(|| MuxerError::UnsupportedContainer(container.to_string()))?;
+        Ok(Self {
+            container,
+            ..Self::new()
+        })
+    }
+
+    /// Configure video track parameters; throws on unsupported codecs or
+    /// dimensions that do not fit an MP4 sample entry
+    #[wasm_bindgen]
+    pub fn configure_video(&mut self, width: u32, height: u32, codec: &str) -> Result<(), JsValue> {
+        let config = self.video_config(width, height, codec)?;
+        self.first_video_track().config = Some(config);
+        Ok(())
+    }
+
+    /// Add another video track (e.g. a picture-in-picture overlay) and
+    /// return its track id for add_video_track_chunk. Video tracks are
+    /// numbered from 1 in the order they are created, including the one
+    /// set up by configure_video.
+    #[wasm_bindgen]
+    pub fn add_video_track(
+        &mut self,
+        width: u32,
+        height: u32,
+        codec: &str,
+    ) -> Result<u32, JsValue> {
+        let config = self.video_config(width, height, codec)?;
+        self.video_tracks.push(VideoTrack {
+            config: Some(config),
+            ..VideoTrack::default()
         });
+        Ok(self.video_tracks.len() as u32)
     }
 
-    /// Configure audio track parameters
+    /// Configure audio track parameters; throws on unsupported codecs or a
+    /// zero sample rate or channel count
     #[wasm_bindgen]
-    pub fn configure_audio(&mut self, sample_rate: u32, channels: u32, codec: &str) {
+    pub fn configure_audio(
+        &mut self,
+        sample_rate: u32,
+        channels: u32,
+        codec: &str,
+    ) -> Result<(), JsValue> {
+        if sample_rate == 0 || channels == 0 {
+            return Err(MuxerError::InvalidAudioConfig {
+                sample_rate,
+                channels,
+            }
+            .into());
+        }
+        let supported = match self.container {
+            Container::Mp4 => codec::audio_sample_entry(codec).is_some(),
+            Container::Webm => codec::webm_audio_codec_id(codec).is_some(),
+        };
+        if !supported {
+            return Err(unsupported_codec("audio", codec).into());
+        }
         self.audio_config = Some(AudioConfig {
             sample_rate,
             channels,
             codec: codec.to_string(),
         });
+        Ok(())
+    }
+
+    /// Set the timescale (ticks per second) of the movie header, track
+    /// header and edit list durations; defaults to 1000. Throws on 0.
+    #[wasm_bindgen]
+    pub fn set_movie_timescale(&mut self, timescale: u32) -> Result<(), JsValue> {
+        self.movie_timescale = checked_timescale("movie", timescale)?;
+        Ok(())
+    }
+
+    /// Set the timescale of every video track, in which sample durations
+    /// and composition offsets are stored; defaults to 90000. Throws on 0.
+    #[wasm_bindgen]
+    pub fn set_video_timescale(&mut self, timescale: u32) -> Result<(), JsValue> {
+        self.video_timescale = checked_timescale("video", timescale)?;
+        Ok(())
+    }
+
+    /// Set the timescale of the audio track; defaults to its sample rate,
+    /// which makes every AAC/Opus packet a whole number of ticks. Throws
+    /// on 0.
+    #[wasm_bindgen]
+    pub fn set_audio_timescale(&mut self, timescale: u32) -> Result<(), JsValue> {
+        self.audio_timescale = Some(checked_timescale("audio", timescale)?);
+        Ok(())
+    }
+
+    /// Largest difference between the video and audio durations, in
+    /// seconds, that finalize accepts without an A/V sync warning
+    /// (default 0.1). Throws on a negative or non-finite value.
+    #[wasm_bindgen]
+    pub fn set_sync_tolerance(&mut self, seconds: f64) -> Result<(), JsValue> {
+        if !seconds.is_finite() || seconds < 0.0 {
+            return Err(MuxerError::InvalidSyncTolerance(seconds).into());
+        }
+        self.sync_tolerance = seconds * 1_000_000.0;
+        Ok(())
+    }
+
+    /// Reject video and audio chunks larger than `bytes`, before their
+    /// data is copied into WASM memory, so a runaway encoder throws
+    /// instead of exhausting it. 0 removes the limit (the default).
+    #[wasm_bindgen]
+    pub fn set_max_chunk_bytes(&mut self, bytes: usize) {
+        self.max_chunk_bytes = Some(bytes).filter(|&bytes| bytes > 0);
+    }
+
+    /// Place the moov box before mdat in the file from finalize, so
+    /// playback can start before the whole file is downloaded. Chunk
+    /// offsets account for the moov size, using co64 where they do not fit
+    /// 32 bits. Only affects non-streaming MP4 output.
+    #[wasm_bindgen]
+    pub fn set_faststart(&mut self, enabled: bool) {
+        self.faststart = enabled;
     }
 
-    /// Add encoded video chunk
+    /// Turn off (or back on) the stripping of ADTS headers from the chunks
+    /// of an AAC track, for callers that pass raw AAC whose first bytes
+    /// could be mistaken for an ADTS header. On by default.
     #[wasm_bindgen]
-    pub fn add_video_chunk(&mut self, data: &Uint8Array, _timestamp: f64, _is_key: bool) {
-        let chunk = data.to_vec();
-        self.video_chunks.push(chunk);
+    pub fn set_strip_adts(&mut self, enabled: bool) {
+        self.strip_adts = enabled;
     }
 
-    /// Add encoded audio chunk
+    /// Set the video decoder configuration record, i.e. the `description`
+    /// of WebCodecs' VideoDecoderConfig (avcC for H.264, hvcC for HEVC,
+    /// av1C for AV1, or the vpcC fields after version and flags for VP9).
+    /// Without it the record is derived from the codec string (VP9), or the
+    /// codec string and first keyframe (H.264, AV1).
     #[wasm_bindgen]
-    pub fn add_audio_chunk(&mut self, data: &Uint8Array, _timestamp: f64) {
-        let chunk = data.to_vec();
-        self.audio_chunks.push(chunk);
+    pub fn set_video_codec_config(&mut self, extradata: &Uint8Array) {
+        self.first_video_track().codec_config = Some(extradata.to_vec());
     }
 
-    /// Finalize and return the muxed MP4 data
+    /// Set the decoder configuration record of a track from add_video_track
     #[wasm_bindgen]
-    pub fn finalize(&mut self) -> Uint8Array {
-        // TODO: Implement actual MP4 muxing using the mp4 crate
-        // For now, return empty array as placeholder
-        web_sys::console::log_1(&"Muxer finalize called".into());
-        
-        let output: Vec<u8> = Vec::new();
-        Uint8Array::from(&output[..])
+    pub fn set_video_track_codec_config(
+        &mut self,
+        track_id: u32,
+        extradata: &Uint8Array,
+    ) -> Result<(), JsValue> {
+        self.video_track(track_id)?.codec_config = Some(extradata.to_vec());
+        Ok(())
     }
 
-    /// Reset muxer state for reuse
+    /// Set the audio codec private data, i.e. the `description` of
+    /// WebCodecs' AudioDecoderConfig (AudioSpecificConfig for AAC, OpusHead
+    /// for Opus). Without it a default is derived from configure_audio.
+    #[wasm_bindgen]
+    pub fn set_audio_codec_config(&mut self, extradata: &Uint8Array) {
+        self.audio_codec_config = Some(extradata.to_vec());
+    }
+
+    /// Set a tag written into the file: "title", "artist", "comment" or
+    /// "encoder". Setting a tag again replaces its value and an empty value
+    /// removes it. MP4 stores the tags in udta/meta/ilst, WebM in Tags,
+    /// for Opus and every other codec alike: neither container carries an
+    /// OpusTags header. Once any tag is set, an encoder tag naming this
+    /// muxer is added unless "encoder" was set. The creation time is filled
+    /// in automatically when the file is written, unless set with
+    /// set_creation_time.
+    #[wasm_bindgen]
+    pub fn set_metadata(&mut self, key: &str, value: &str) -> Result<(), JsValue> {
+        let tag = Tag::parse(key).ok_or_else(|| MuxerError::UnknownMetadataKey(key.to_string()))?;
+        self.tags.retain(|(t, _)| *t != tag);
+        if !value.is_empty() {
+            self.tags.push((tag, value.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Creation time written into the file, in seconds since 1970 (e.g.
+    /// `Date.now() / 1000` when recording started), instead of the time
+    /// the file is written. Cleared by reset; throws on a negative or
+    /// non-finite value.
+    #[wasm_bindgen]
+    pub fn set_creation_time(&mut self, seconds: f64) -> Result<(), JsValue> {
+        if !seconds.is_finite() || seconds < 0.0 {
+            return Err(MuxerError::InvalidCreationTime(seconds).into());
+        }
+        self.creation_time = Some(seconds as u64);
+        Ok(())
+    }
+
+    /// Switch to streaming output: the callback is invoked with the init
+    /// segment, then with a moof+mdat fragment for every completed GOP (or
+    /// second of audio when there is no video), or the GOPs spanning the
+    /// fragment duration when one is set, so only the current fragment is
+    /// held in memory. MP4 only.
+    #[wasm_bindgen]
+    pub fn set_output_callback(&mut self, cb: Function) -> Result<(), JsValue> {
+        self.require_mp4("streaming output")?;
+        self.output_callback = Some(cb);
+        Ok(())
+    }
+
+    /// Convert a start-code delimited (Annex B) H.264/HEVC chunk to
+    /// length-prefixed NAL units (AVCC). add_video_chunk already does this
+    /// for Annex B input to H.264/HEVC tracks.
+    #[wasm_bindgen]
+    pub fn annexb_to_avcc(data: &Uint8Array) -> Uint8Array {
+        Uint8Array::from(&codec::annex_b_to_avcc(&data.to_vec())[..])
+    }
+
+    /// Whether `data` is a complete ADTS frame (AAC with its transport
+    /// header, as some encoders emit it). MP4 stores raw AAC, so such a
+    /// frame needs its header stripped; add_audio_chunk does that for AAC
+    /// tracks.
+    #[wasm_bindgen]
+    pub fn validate_aac_adts(data: &Uint8Array) -> bool {
+        codec::adts_header_len(&data.to_vec()).is_some()
+    }
+
+    /// The raw AAC frame of an ADTS frame; other data is returned unchanged
+    #[wasm_bindgen]
+    pub fn strip_adts_header(data: &Uint8Array) -> Uint8Array {
+        Uint8Array::from(codec::strip_adts(&data.to_vec()))
+    }
+
+    /// Whether `data` is a well-formed Opus packet: its TOC byte, frame
+    /// count, frame lengths and padding are consistent with its size.
+    /// finalize runs the same check on the first audio chunks.
+    #[wasm_bindgen]
+    pub fn validate_opus_packet(data: &Uint8Array) -> bool {
+        codec::is_opus_packet(&data.to_vec())
+    }
+
+    /// Read the track configurations of an MP4 initialization segment (an
+    /// ftyp + moov prefix, such as init_segment's output or the start of a
+    /// faststart file): codec strings, dimensions, sample rate, channel
+    /// count and the decoder descriptions of its first video and first
+    /// audio track, ready for VideoDecoder/AudioDecoder.configure. Throws
+    /// when the data is not an init segment or holds neither track.
+    #[wasm_bindgen]
+    pub fn parse_init_segment(data: &Uint8Array) -> Result<InitSegmentInfo, JsValue> {
+        let info = probe::parse_init_segment(&data.to_vec())?;
+        Ok(InitSegmentInfo {
+            video: info.video,
+            audio: info.audio,
+        })
+    }
+
+    /// Add encoded video chunk (timestamp in microseconds, chunks in decode
+    /// order). H.264/HEVC chunks in Annex B format are converted to AVCC,
+    /// and the SPS/PPS of the first H.264 keyframe build the avcC box
+    /// unless a codec config was set. Throws before configure_video, on an
+    /// empty chunk or on one over the set_max_chunk_bytes limit.
+    #[wasm_bindgen]
+    pub fn add_video_chunk(
+        &mut self,
+        data: &Uint8Array,
+        timestamp: f64,
+        is_key: bool,
+    ) -> Result<(), JsValue> {
+        let index = self.first_video_chunks()?.len();
+        self.check_chunk_len("video", index, data.length() as usize)?;
+        self.push_video_chunk(0, data.to_vec(), timestamp, is_key)
+    }
+
+    /// Add encoded video chunk to a track from add_video_track (timestamp
+    /// in microseconds, chunks in decode order)
+    #[wasm_bindgen]
+    pub fn add_video_track_chunk(
+        &mut self,
+        track_id: u32,
+        data: &Uint8Array,
+        timestamp: f64,
+        is_key: bool,
+    ) -> Result<(), JsValue> {
+        let index = self.video_track(track_id)?.chunks.len();
+        self.check_chunk_len("video", index, data.length() as usize)?;
+        self.push_video_chunk(track_id as usize - 1, data.to_vec(), timestamp, is_key)
+    }
+
+    /// Add encoded audio chunk (timestamp in microseconds). ADTS headers
+    /// on chunks of an AAC track are stripped unless set_strip_adts turned
+    /// that off, and the first one builds the esds AudioSpecificConfig
+    /// unless a codec config was set. Throws on an empty chunk, including
+    /// one that is only an ADTS header, or one over the
+    /// set_max_chunk_bytes limit.
+    #[wasm_bindgen]
+    pub fn add_audio_chunk(&mut self, data: &Uint8Array, timestamp: f64) -> Result<(), JsValue> {
+        let index = self.audio_chunks.len();
+        self.check_chunk_len("audio", index, data.length() as usize)?;
+        let audio_only = self.video_tracks.is_empty();
+        let buffered_from = self.audio_chunks.first().map(|c| c.timestamp);
+        let target = self.fragment_duration.unwrap_or(AUDIO_FRAGMENT_US);
+        if audio_only && buffered_from.is_some_and(|t| timestamp - t >= target) {
+            self.stream_buffered()?;
+        }
+        let mut data = data.to_vec();
+        let is_aac = self
+            .audio_config
+            .as_ref()
+            .is_some_and(|config| codec::audio_sample_entry(&config.codec) == Some(*b"mp4a"));
+        let header_len = codec::adts_header_len(&data).filter(|_| is_aac && self.strip_adts);
+        if let Some(header_len) = header_len {
+            if self.stream_audio_config.is_none() {
+                self.stream_audio_config = codec::asc_from_adts(&data);
+            }
+            data.drain(..header_len);
+        }
+        if data.is_empty() {
+            return Err(MuxerError::EmptyChunk {
+                track: "audio",
+                index,
+            }
+            .into());
+        }
+        self.audio_chunks.push(Chunk {
+            data,
+            timestamp,
+            is_key: true,
+        });
+        Ok(())
+    }
+
+    /// Add a subtitle cue shown from `start` until `end` (microseconds, on
+    /// the same clock as the chunk timestamps). Cues may be added in any
+    /// order and are written by finalize as their own track: tx3g in MP4,
+    /// with empty samples filling the gaps between cues, or WebVTT in WebM.
+    /// A cue overlapping the next one is cut short by it. Fragmented output
+    /// carries audio and video only. Throws on non-finite times or an end
+    /// before the start.
+    #[wasm_bindgen]
+    pub fn add_subtitle_cue(&mut self, start: f64, end: f64, text: &str) -> Result<(), JsValue> {
+        if !start.is_finite() || !end.is_finite() || end < start {
+            return Err(MuxerError::InvalidCue { start, end }.into());
+        }
+        let index = self.subtitle_cues.partition_point(|cue| cue.start <= start);
+        self.subtitle_cues.insert(
+            index,
+            Cue {
+                start,
+                end,
+                text: text.to_string(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Add a chapter starting at `time` (microseconds, on the same clock as
+    /// the chunk timestamps) that players list in their scrubber. Chapters
+    /// are sorted by time and each lasts until the next one; the first
+    /// always starts at the beginning of the file. finalize writes them as
+    /// a QuickTime chapter track in MP4 or a Chapters element in WebM.
+    /// Throws on a non-finite time.
+    #[wasm_bindgen]
+    pub fn add_chapter(&mut self, time: f64, title: &str) -> Result<(), JsValue> {
+        if !time.is_finite() {
+            return Err(MuxerError::InvalidChapterTime(time).into());
+        }
+        let index = self
+            .chapters
+            .partition_point(|chapter| chapter.time <= time);
+        self.chapters.insert(
+            index,
+            Chapter {
+                time,
+                title: title.to_string(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Keep only the part of the recording from `start` to `end`
+    /// (microseconds, on the same clock as the chunk timestamps) when
+    /// finalizing, without re-encoding. Video starts on the keyframe at or
+    /// before `start`; MP4 hides the frames before `start` with an edit
+    /// list, while WebM, which has no edit lists, shows them. Audio starts
+    /// on the chunk closest to `start`, and every track ends with the last
+    /// chunk presented before `end`. Subtitle cues and chapters are cut to
+    /// the range. Streaming and fragmented output are not trimmed. Throws
+    /// on non-finite times or an end that is not after the start.
+    #[wasm_bindgen]
+    pub fn set_trim_range(&mut self, start: f64, end: f64) -> Result<(), JsValue> {
+        if !start.is_finite() || !end.is_finite() || end <= start {
+            return Err(MuxerError::InvalidTrimRange { start, end }.into());
+        }
+        self.trim_range = Some((start, end));
+        Ok(())
+    }
+
+    /// Finalize the whole recording again after set_trim_range
+    #[wasm_bindgen]
+    pub fn clear_trim_range(&mut self) {
+        self.trim_range = None;
+    }
+
+    /// Finalize and return the muxed file; throws when no track is
+    /// configured or a chunk is malformed. In streaming mode the remaining
+    /// chunks go to the output callback and an empty array is returned.
+    ///
+    /// Chunks that arrived out of order are sorted first. Audio chunks are
+    /// stable-sorted by timestamp. Video chunks are presentation-timestamped
+    /// in decode order, so they are sorted a GOP at a time: each keyframe
+    /// and the chunks added after it up to the next keyframe move together,
+    /// ordered by the keyframe's timestamp, and keep their call order
+    /// inside the GOP so B-frame reordering is preserved. Ties keep call
+    /// order. Chunks already streamed to the output callback stay as sent.
+    ///
+    /// When the video and audio durations differ by more than the sync
+    /// tolerance, a warning is logged to the console and kept for
+    /// av_sync_warning. Streaming output skips the check, as most chunks
+    /// are gone by then.
+    #[wasm_bindgen]
+    pub fn finalize(&mut self) -> Result<Uint8Array, JsValue> {
+        for track in &mut self.video_tracks {
+            sort_gops(&mut track.chunks);
+        }
+        self.audio_chunks
+            .sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
+        if self.output_callback.is_some() {
+            self.stream_buffered()?;
+            return Ok(Uint8Array::new_with_length(0));
+        }
+        self.sync_warning = self.check_av_sync();
+        if let Some(warning) = &self.sync_warning {
+            web_sys::console::warn_1(&JsValue::from_str(warning));
+        }
+        let output = match self.container {
+            Container::Mp4 => self.write_mp4()?,
+            Container::Webm => self.write_webm()?,
+        };
+        Ok(Uint8Array::from(&output[..]))
+    }
+
+    /// The A/V sync warning of the last finalize, or undefined when the
+    /// video and audio durations matched within the tolerance
+    #[wasm_bindgen]
+    pub fn av_sync_warning(&self) -> Option<String> {
+        self.sync_warning.clone()
+    }
+
+    /// First video timestamp minus first audio timestamp in seconds, i.e.
+    /// how much later the video starts; 0.0 unless both have chunks
+    #[wasm_bindgen]
+    pub fn av_sync_offset(&self) -> f64 {
+        let video = self
+            .video_tracks
+            .first()
+            .and_then(|track| start_timestamp(&track.chunks));
+        match (video, start_timestamp(&self.audio_chunks)) {
+            (Some(video), Some(audio)) => (video - audio) / 1_000_000.0,
+            _ => 0.0,
+        }
+    }
+
+    /// Length of the buffered recording in seconds: the longer of the video
+    /// and audio tracks, each counted from the earliest chunk of any track,
+    /// as written to the mvhd/tkhd durations. Returns 0.0 when no chunks
+    /// have been added.
+    #[wasm_bindgen]
+    pub fn duration_seconds(&self) -> f64 {
+        let movie_start = self
+            .video_tracks
+            .iter()
+            .map(|t| &t.chunks[..])
+            .chain([&self.audio_chunks[..]])
+            .filter_map(start_timestamp)
+            .chain(self.subtitle_cues.first().map(|cue| cue.start))
+            .fold(f64::INFINITY, f64::min);
+        let video = self
+            .video_tracks
+            .iter()
+            .map(|track| {
+                track_duration(
+                    &track.chunks,
+                    self.video_timescale,
+                    self.video_timescale / DEFAULT_FRAME_RATE,
+                    movie_start,
+                )
+            })
+            .fold(0.0, f64::max);
+        let audio = match &self.audio_config {
+            Some(config) => {
+                let timescale = self.audio_timescale.unwrap_or(config.sample_rate);
+                let is_opus = config.codec.starts_with("opus");
+                track_duration(
+                    &self.audio_chunks,
+                    timescale,
+                    audio_frame_duration(config.sample_rate, timescale, is_opus),
+                    movie_start,
+                )
+            }
+            None => 0.0,
+        };
+        let subtitles = self
+            .subtitle_cues
+            .iter()
+            .map(|cue| (cue.end - movie_start) / 1_000_000.0)
+            .fold(0.0, f64::max);
+        video.max(audio).max(subtitles)
+    }
+
+    /// Bytes of chunk data the muxer is holding across all video tracks
+    /// and the audio track, for backpressure or deciding when to flush
+    #[wasm_bindgen]
+    pub fn buffered_bytes(&self) -> usize {
+        self.video_tracks
+            .iter()
+            .flat_map(|track| &track.chunks)
+            .chain(&self.audio_chunks)
+            .map(|chunk| chunk.data.len())
+            .sum()
+    }
+
+    /// Number of queued chunks as `[video, audio]`, video summed over all
+    /// video tracks
+    #[wasm_bindgen]
+    pub fn chunk_counts(&self) -> Vec<u32> {
+        let video: usize = self
+            .video_tracks
+            .iter()
+            .map(|track| track.chunks.len())
+            .sum();
+        vec![video as u32, self.audio_chunks.len() as u32]
+    }
+
+    /// Initialization segment for fragmented output, e.g. the first
+    /// SourceBuffer.appendBuffer of Media Source Extensions: exactly ftyp
+    /// and a moov with the track descriptions, empty sample tables and an
+    /// mvex box. Throws when an H.264/HEVC track has no decoder
+    /// configuration yet (set one, or add its first keyframe first).
+    #[wasm_bindgen]
+    pub fn init_segment(&self) -> Result<Uint8Array, JsValue> {
+        let output = self.write_init_segment()?;
+        Ok(Uint8Array::from(&output[..]))
+    }
+
+    /// Target media duration of each fragment in seconds, for
+    /// flush_fragment and streaming output; 0 (the default) disables the
+    /// target. Fragments still always begin on a keyframe of the first
+    /// video track, so they run from one keyframe to the first keyframe at
+    /// least this far after it. Throws on a negative or non-finite value.
+    #[wasm_bindgen]
+    pub fn set_fragment_duration(&mut self, seconds: f64) -> Result<(), JsValue> {
+        if !seconds.is_finite() || seconds < 0.0 {
+            return Err(MuxerError::InvalidFragmentDuration(seconds).into());
+        }
+        self.fragment_duration = (seconds > 0.0).then_some(seconds * 1_000_000.0);
+        Ok(())
+    }
+
+    /// Fragment duration target in seconds; 0 when none is set
+    #[wasm_bindgen]
+    pub fn fragment_duration(&self) -> f64 {
+        self.fragment_duration.map_or(0.0, |us| us / 1_000_000.0)
+    }
+
+    /// Emit a moof+mdat fragment. Without a fragment duration target it
+    /// holds every chunk added since the last flush. With one, chunks
+    /// accumulate until the buffer spans the target: the fragment then
+    /// ends before the first keyframe at least the target after the first
+    /// chunk, and the rest stays buffered. Returns an empty array when
+    /// nothing is buffered or the target is not reached yet; set the
+    /// duration to 0 to flush the remainder at the end of a stream.
+    #[wasm_bindgen]
+    pub fn flush_fragment(&mut self) -> Result<Uint8Array, JsValue> {
+        let output = match self.fragment_duration {
+            Some(target) => match self.fragment_split(target) {
+                Some(split) => self.take_fragment(Some(split))?,
+                None => Vec::new(),
+            },
+            None => self.take_fragment(None)?,
+        };
+        Ok(Uint8Array::from(&output[..]))
+    }
+
+    /// Emit a moof+mdat media segment for appending after init_segment.
+    /// With video it holds every completed GOP, i.e. the chunks before the
+    /// last keyframe of the first video track and the audio before that
+    /// keyframe, so each segment starts on a keyframe; the open GOP stays
+    /// buffered for the next call or flush_fragment. Audio-only output
+    /// takes everything. Returns an empty array when no GOP is complete.
+    #[wasm_bindgen]
+    pub fn next_media_segment(&mut self) -> Result<Uint8Array, JsValue> {
+        let output = self.take_media_segment()?;
+        Ok(Uint8Array::from(&output[..]))
+    }
+
+    /// Reset for the next recording: drops buffered chunks and the trim
+    /// range and restarts fragment numbering, but keeps the track
+    /// configuration, codec
+    /// descriptions passed by the caller, metadata and the output callback
     #[wasm_bindgen]
     pub fn reset(&mut self) {
-        self.video_chunks.clear();
+        for track in &mut self.video_tracks {
+            track.chunks.clear();
+            track.stream_codec_config = None;
+        }
         self.audio_chunks.clear();
+        self.stream_audio_config = None;
+        self.subtitle_cues.clear();
+        self.chapters.clear();
+        self.sync_warning = None;
+        self.trim_range = None;
+        self.creation_time = None;
+        self.fragment_sequence = 1;
+        self.init_segment_sent = false;
+    }
+
+    /// Reset everything except the container, as if newly constructed
+    #[wasm_bindgen]
+    pub fn reset_full(&mut self) {
+        *self = Self {
+            container: self.container,
+            ..Self::new()
+        };
     }
 }
 
+impl Muxer {
+    /// Check video track parameters against the container
+    fn video_config(
+        &self,
+        width: u32,
+        height: u32,
+        codec: &str,
+    ) -> Result<VideoConfig, MuxerError> {
+        if width == 0 || height == 0 || width > 0xFFFF || height > 0xFFFF {
+            return Err(MuxerError::InvalidVideoConfig { width, height });
+        }
+        let supported = match self.container {
+            Container::Mp4 => codec::video_sample_entry(codec).is_some(),
+            Container::Webm => codec::webm_video_codec_id(codec).is_some(),
+        };
+        if !supported {
+            return Err(unsupported_codec("video", codec));
+        }
+        Ok(VideoConfig {
+            width,
+            height,
+            codec: codec.to_string(),
+        })
+    }
+
+    /// Reject a chunk of `len` bytes that is empty or over the limit
+    fn check_chunk_len(
+        &self,
+        track: &'static str,
+        index: usize,
+        len: usize,
+    ) -> Result<(), MuxerError> {
+        if len == 0 {
+            return Err(MuxerError::EmptyChunk { track, index });
+        }
+        match self.max_chunk_bytes {
+            Some(max) if len > max => Err(MuxerError::ChunkTooLarge {
+                track,
+                index,
+                size: len,
+                max,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Chunks of the track add_video_chunk feeds. That track only exists
+    /// once configure_video or set_video_codec_config created it, so a
+    /// stray chunk cannot turn an audio-only file into one with video.
+    fn first_video_chunks(&self) -> Result<&[Chunk], MuxerError> {
+        self.video_tracks
+            .first()
+            .map(|track| &track.chunks[..])
+            .ok_or(MuxerError::MissingConfig { track: "video" })
+    }
+
+    /// Track used by the single-track video configuration methods, created
+    /// by the first of them
+    fn first_video_track(&mut self) -> &mut VideoTrack {
+        if self.video_tracks.is_empty() {
+            self.video_tracks.push(VideoTrack::default());
+        }
+        &mut self.video_tracks[0]
+    }
+
+    fn video_track(&mut self, track_id: u32) -> Result<&mut VideoTrack, MuxerError> {
+        track_id
+            .checked_sub(1)
+            .and_then(|index| self.video_tracks.get_mut(index as usize))
+            .ok_or(MuxerError::UnknownTrack { track_id })
+    }
+
+    fn push_video_chunk(
+        &mut self,
+        index: usize,
+        data: Vec<u8>,
+        timestamp: f64,
+        is_key: bool,
+    ) -> Result<(), JsValue> {
+        // A keyframe on the first track closes the previous GOP, which can
+        // now be streamed once the buffer spans the fragment duration
+        let buffered_from = self.video_tracks[0].chunks.first().map(|c| c.timestamp);
+        let target = self.fragment_duration.unwrap_or(0.0);
+        if index == 0 && is_key && buffered_from.is_some_and(|t| timestamp - t >= target) {
+            self.stream_buffered()?;
+        }
+        let track = &mut self.video_tracks[index];
+        let sample_entry = track
+            .config
+            .as_ref()
+            .and_then(|config| codec::video_sample_entry(&config.codec));
+        let data = if matches!(sample_entry.as_ref(), Some(b"avc1" | b"hvc1"))
+            && codec::is_annex_b(&data)
+        {
+            codec::annex_b_to_avcc(&data)
+        } else {
+            data
+        };
+        if is_key && track.stream_codec_config.is_none() {
+            track.stream_codec_config = match (sample_entry.as_ref(), &track.config) {
+                (Some(b"avc1"), _) => codec::avc_config_from_keyframe(&data),
+                (Some(b"av01"), Some(config)) => codec::av1c_from_keyframe(&config.codec, &data),
+                _ => None,
+            };
+        }
+        track.chunks.push(Chunk {
+            data,
+            timestamp,
+            is_key,
+        });
+        Ok(())
+    }
+
+    /// Samples of the tx3g subtitle track
+    fn subtitle_chunks(cues: &[Cue]) -> Vec<Chunk> {
+        subtitle::tx3g_samples(cues)
+            .into_iter()
+            .map(|(timestamp, data)| Chunk {
+                data,
+                timestamp,
+                is_key: true,
+            })
+            .collect()
+    }
+
+    /// Start and end of the movie in microseconds: the earliest and latest
+    /// presentation time of the tracks and subtitle cues
+    fn movie_span(&self, tracks: &[(TrackInfo, &[Chunk])], cues: &[Cue]) -> (f64, f64) {
+        let media = tracks
+            .iter()
+            .filter(|(info, _)| !matches!(info.kind, TrackKind::Text));
+        let start = media
+            .clone()
+            .filter_map(|(info, chunks)| self.presented_start(&info.kind, chunks))
+            .chain(cues.first().map(|cue| cue.start))
+            .fold(f64::INFINITY, f64::min);
+        let end = media
+            .filter_map(|(_, chunks)| end_timestamp(chunks))
+            .chain(cues.iter().map(|cue| cue.end))
+            .fold(start, f64::max);
+        (start, end)
+    }
+
+    /// Samples of the chapter track, the first moved to `movie_start`
+    fn chapter_chunks(chapters: &[Chapter], movie_start: f64) -> Vec<Chunk> {
+        if !movie_start.is_finite() {
+            return Vec::new();
+        }
+        chapters
+            .iter()
+            .enumerate()
+            .map(|(index, chapter)| Chunk {
+                data: subtitle::tx3g_sample(&chapter.title),
+                timestamp: if index == 0 {
+                    movie_start
+                } else {
+                    chapter.time.max(movie_start)
+                },
+                is_key: true,
+            })
+            .collect()
+    }
+
+    /// The chunks of a track that finalize keeps under the trim range, in
+    /// decode order: up to the last chunk presented before the end, from
+    /// the keyframe at or before the start (video) or the chunk closest to
+    /// it (audio)
+    fn trimmed<'a>(&self, chunks: &'a [Chunk], video: bool) -> &'a [Chunk] {
+        let Some((start, end)) = self.trim_range else {
+            return chunks;
+        };
+        let len = chunks
+            .iter()
+            .rposition(|c| c.timestamp < end)
+            .map_or(0, |last| last + 1);
+        let chunks = &chunks[..len];
+        let first = if video {
+            chunks
+                .iter()
+                .rposition(|c| c.is_key && c.timestamp <= start)
+                .or_else(|| chunks.iter().position(|c| c.is_key))
+        } else {
+            let distance = |c: &Chunk| (c.timestamp - start).abs();
+            (0..chunks.len()).min_by(|&a, &b| distance(&chunks[a]).total_cmp(&distance(&chunks[b])))
+        };
+        first.map_or(&[], |first| &chunks[first..])
+    }
+
+    /// Time in microseconds the first frame of a track is shown: its
+    /// earliest timestamp, or the trim start for video that starts on an
+    /// earlier keyframe
+    fn presented_start(&self, kind: &TrackKind, chunks: &[Chunk]) -> Option<f64> {
+        let first = start_timestamp(chunks)?;
+        Some(match (kind, self.trim_range) {
+            (TrackKind::Video { .. }, Some((start, _))) => first.max(start),
+            _ => first,
+        })
+    }
+
+    /// Subtitle cues overlapping the trim range, cut to it
+    fn trimmed_cues(&self) -> Cow<'_, [Cue]> {
+        let Some((start, end)) = self.trim_range else {
+            return Cow::Borrowed(&self.subtitle_cues);
+        };
+        self.subtitle_cues
+            .iter()
+            .filter(|cue| cue.end > start && cue.start < end)
+            .map(|cue| Cue {
+                start: cue.start.max(start),
+                end: cue.end.min(end),
+                text: cue.text.clone(),
+            })
+            .collect()
+    }
+
+    /// Chapters starting before the end of the trim range; the one running
+    /// at its start moves to the start
+    fn trimmed_chapters(&self) -> Cow<'_, [Chapter]> {
+        let Some((start, end)) = self.trim_range else {
+            return Cow::Borrowed(&self.chapters);
+        };
+        let first = self
+            .chapters
+            .partition_point(|chapter| chapter.time <= start)
+            .saturating_sub(1);
+        self.chapters[first..]
+            .iter()
+            .filter(|chapter| chapter.time < end)
+            .map(|chapter| Chapter {
+                time: chapter.time.max(start),
+                title: chapter.title.clone(),
+            })
+            .collect()
+    }
+
+    /// Tags and the creation time, the current time unless one was set
+    fn metadata(&self) -> Metadata<'_> {
+        Metadata {
+            creation_time: self.creation_time.unwrap_or_else(now),
+            tags: &self.tags,
+        }
+    }
+
+    /// Whether at least one track has been configured
+    fn is_configured(&self) -> bool {
+        self.audio_config.is_some() || self.video_tracks.iter().any(|t| t.config.is_some())
+    }
+
+    /// Check that the buffered chunks can be muxed with the current
+    /// configuration
+    fn validate(&self) -> Result<(), MuxerError> {
+        if !self.is_configured() {
+            return Err(MuxerError::NotConfigured);
+        }
+        let tracks = self
+            .video_tracks
+            .iter()
+            .map(|t| ("video", t.config.is_some(), &t.chunks))
+            .chain([("audio", self.audio_config.is_some(), &self.audio_chunks)]);
+        for (track, configured, chunks) in tracks {
+            if !configured && !chunks.is_empty() {
+                return Err(MuxerError::MissingConfig { track });
+            }
+            for (index, chunk) in chunks.iter().enumerate() {
+                let reason = if chunk.data.is_empty() {
+                    "chunk is empty"
+                } else if !chunk.timestamp.is_finite() {
+                    "timestamp is not a finite number"
+                } else {
+                    continue;
+                };
+                return Err(MuxerError::MalformedChunk {
+                    track,
+                    index,
+                    reason: reason.to_string(),
+                });
+            }
+        }
+        for track in &self.video_tracks {
+            let Some(config) = &track.config else {
+                continue;
+            };
+            let first = track.chunks.iter().take(CODEC_CHECK_CHUNKS).enumerate();
+            for (index, chunk) in first {
+                if let Some(reason) =
+                    codec::video_chunk_mismatch(&config.codec, &chunk.data, chunk.is_key)
+                {
+                    return Err(MuxerError::CodecMismatch {
+                        track: "video",
+                        index,
+                        codec: config.codec.clone(),
+                        reason,
+                    });
+                }
+            }
+        }
+        if let Some(config) = &self.audio_config {
+            let first = self
+                .audio_chunks
+                .iter()
+                .take(CODEC_CHECK_CHUNKS)
+                .enumerate();
+            for (index, chunk) in first {
+                if let Some(reason) = codec::audio_chunk_mismatch(&config.codec, &chunk.data) {
+                    return Err(MuxerError::CodecMismatch {
+                        track: "audio",
+                        index,
+                        codec: config.codec.clone(),
+                        reason,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Describe the configured tracks (video first, then audio) without
+    /// sample tables, paired with the chunks buffered for each
+    fn tracks(&self) -> Result<Vec<(TrackInfo, &[Chunk])>, MuxerError> {
+        let mut tracks = Vec::new();
+        for (index, track) in self.video_tracks.iter().enumerate() {
+            let Some(config) = &track.config else {
+                continue;
+            };
+            let sample_entry = codec::video_sample_entry(&config.codec)
+                .ok_or_else(|| unsupported_codec("video", &config.codec))?;
+            let codec_config = track
+                .codec_config
+                .clone()
+                .or_else(|| track.stream_codec_config.clone())
+                .or_else(|| match &sample_entry {
+                    b"vp09" => codec::vpcc_from_codec(&config.codec),
+                    _ => None,
+                });
+            let first_byte = codec_config.as_ref().and_then(|c| c.first().copied());
+            if sample_entry == *b"avc1" && first_byte.is_some_and(|b| b != 1) {
+                return Err(MuxerError::InvalidCodecConfig {
+                    track: "video",
+                    reason: "avcC record must start with configurationVersion 1",
+                });
+            }
+            if sample_entry == *b"av01" && first_byte.is_some_and(|b| b != 0x81) {
+                return Err(MuxerError::InvalidCodecConfig {
+                    track: "video",
+                    reason: "av1C record must start with marker and version 1",
+                });
+            }
+            let info = TrackInfo::new(
+                index as u32 + 1,
+                TrackKind::Video {
+                    width: config.width,
+                    height: config.height,
+                },
+                sample_entry,
+                self.video_timescale,
+                codec_config,
+            );
+            tracks.push((info, &track.chunks[..]));
+        }
+        if let Some(config) = &self.audio_config {
+            let sample_entry = codec::audio_sample_entry(&config.codec)
+                .ok_or_else(|| unsupported_codec("audio", &config.codec))?;
+            let info = TrackInfo::new(
+                self.video_tracks.len() as u32 + 1,
+                TrackKind::Audio {
+                    sample_rate: config.sample_rate,
+                    channels: config.channels,
+                },
+                sample_entry,
+                self.audio_timescale.unwrap_or(config.sample_rate),
+                Some(self.mp4_audio_codec_config(config, &sample_entry)?),
+            );
+            tracks.push((info, &self.audio_chunks[..]));
+        }
+        Ok(tracks)
+    }
+
+    /// Payload of the audio codec configuration box for the sample entry
+    fn mp4_audio_codec_config(
+        &self,
+        config: &AudioConfig,
+        sample_entry: &[u8; 4],
+    ) -> Result<Vec<u8>, MuxerError> {
+        match sample_entry {
+            b"Opus" => {
+                let head = self.opus_head(config);
+                codec::dops_from_opus_head(&head).ok_or(MuxerError::InvalidCodecConfig {
+                    track: "audio",
+                    reason: "Opus description is not a valid OpusHead header",
+                })
+            }
+            _ => match &self.audio_codec_config {
+                Some(asc) if asc.len() < 2 => Err(MuxerError::InvalidCodecConfig {
+                    track: "audio",
+                    reason: "AudioSpecificConfig must be at least 2 bytes",
+                }),
+                Some(asc) => Ok(asc.clone()),
+                None => Ok(self.stream_audio_config.clone().unwrap_or_else(|| {
+                    codec::aac_audio_specific_config(
+                        &config.codec,
+                        config.sample_rate,
+                        config.channels,
+                    )
+                })),
+            },
+        }
+    }
+
+    /// Caller-provided OpusHead, or a default one for the configuration
+    fn opus_head(&self, config: &AudioConfig) -> Vec<u8> {
+        self.audio_codec_config
+            .clone()
+            .unwrap_or_else(|| codec::opus_head(config.sample_rate, config.channels))
+    }
+
+    /// Assemble ftyp, mdat and moov from the buffered chunks.
+    ///
+    /// The samples are interleaved in mdat: each track is split into chunks
+    /// of less than INTERLEAVE_US of media, stored in decode time order
+    /// across tracks, so a progressive download has every track's data at
+    /// hand at any position. mdat is followed by the moov box, or preceded
+    /// by it with faststart. Tracks whose first timestamp is later than the
+    /// earliest one across all tracks, or that are trimmed to start after a
+    /// keyframe, get an edit list so they stay in sync. B-frames need none:
+    /// their negative composition offsets go in a version 1 ctts.
+    fn write_mp4(&self) -> Result<Vec<u8>, MuxerError> {
+        self.validate()?;
+        let mut tracks = self.tracks()?;
+        let sample_entries: Vec<[u8; 4]> =
+            tracks.iter().map(|(info, _)| info.sample_entry).collect();
+        let mut w = BoxWriter::new();
+        mp4::write_ftyp(&mut w, &sample_entries, false);
+
+        for (info, chunks) in &mut tracks {
+            *chunks = self.trimmed(chunks, matches!(info.kind, TrackKind::Video { .. }));
+        }
+        let cues = self.trimmed_cues();
+        let subtitles = Self::subtitle_chunks(&cues);
+        if !subtitles.is_empty() {
+            let id = tracks.iter().map(|(info, _)| info.id).max().unwrap_or(0) + 1;
+            let info = TrackInfo::new(id, TrackKind::Text, *b"tx3g", SUBTITLE_TIMESCALE, None);
+            tracks.push((info, &subtitles[..]));
+        }
+        let (movie_start, movie_end) = self.movie_span(&tracks, &cues);
+        let chapters = Self::chapter_chunks(&self.trimmed_chapters(), movie_start);
+        if !chapters.is_empty() {
+            let id = tracks.iter().map(|(info, _)| info.id).max().unwrap_or(0) + 1;
+            let info = TrackInfo::new(id, TrackKind::Chapters, *b"tx3g", SUBTITLE_TIMESCALE, None);
+            tracks[0].0.chapter_track = Some(id);
+            tracks.push((info, &chapters[..]));
+        }
+        let payload_len: u64 = tracks
+            .iter()
+            .flat_map(|(_, chunks)| chunks.iter())
+            .map(|c| c.data.len() as u64)
+            .sum();
+        let mut mdat = BoxWriter::new();
+        mp4::write_mdat_header(&mut mdat, payload_len);
+
+        let mut infos = Vec::new();
+        for (mut info, chunks) in tracks {
+            let mut timing =
+                timing::sample_timing(&timestamps(chunks), info.timescale, default_duration(&info));
+            // The last cue lasts until its own end and the last chapter
+            // until the end of the movie, instead of repeating the previous
+            // sample's duration
+            let end = match info.kind {
+                TrackKind::Text => cues.last().map(|cue| cue.end),
+                TrackKind::Chapters => Some(movie_end),
+                _ => None,
+            };
+            if let (Some(end), Some(last)) = (end, chunks.last()) {
+                let duration = timing::to_ticks(end, info.timescale)
+                    - timing::to_ticks(last.timestamp, info.timescale);
+                if let Some(duration_ticks) = timing.durations.last_mut() {
+                    *duration_ticks = duration.max(0) as u32;
+                }
+            }
+            if let (Some(first), Some(start)) = (
+                start_timestamp(chunks),
+                self.presented_start(&info.kind, chunks),
+            ) {
+                let delay = timing::to_ticks(start - movie_start, self.movie_timescale);
+                info.start_delay = delay.max(0) as u64;
+                let preroll = timing::to_ticks(start, info.timescale)
+                    - timing::to_ticks(first, info.timescale);
+                info.preroll = preroll.max(0) as u64;
+            }
+            info.sample_sizes = sample_sizes(chunks);
+            info.sample_durations = timing.durations;
+            info.composition_offsets = timing.composition_offsets;
+            info.sync_samples = sync_samples(chunks);
+            infos.push((info, chunks));
+        }
+        let track_timestamps: Vec<Vec<f64>> =
+            infos.iter().map(|(_, chunks)| timestamps(chunks)).collect();
+        let track_timestamps: Vec<&[f64]> = track_timestamps.iter().map(Vec::as_slice).collect();
+        for (track, samples) in timing::interleave(&track_timestamps, INTERLEAVE_US) {
+            let (info, chunks) = &mut infos[track];
+            // Relative to the start of the mdat box until its position is known
+            info.chunk_offsets.push(mdat.len() as u64);
+            info.samples_per_chunk.push(samples.len() as u32);
+            for chunk in &chunks[samples] {
+                mdat.bytes(&chunk.data);
+            }
+        }
+        let mut infos: Vec<TrackInfo> = infos.into_iter().map(|(info, _)| info).collect();
+
+        let metadata = self.metadata();
+        let write_moov = |infos: &[TrackInfo]| {
+            let mut moov = BoxWriter::new();
+            mp4::write_moov(&mut moov, infos, false, self.movie_timescale, &metadata);
+            moov.into_inner()
+        };
+        let relative: Vec<Vec<u64>> = infos
+            .iter()
+            .map(|info| info.chunk_offsets.clone())
+            .collect();
+        let place_mdat = |infos: &mut [TrackInfo], mdat_start: u64| {
+            for (info, offsets) in infos.iter_mut().zip(&relative) {
+                info.chunk_offsets = offsets.iter().map(|offset| mdat_start + offset).collect();
+            }
+        };
+        if self.faststart {
+            // The moov size depends on the offsets (stco or co64), which
+            // depend on the moov size; repeat until it settles
+            let mut moov_len = 0;
+            let moov = loop {
+                place_mdat(&mut infos, (w.len() + moov_len) as u64);
+                let moov = write_moov(&infos);
+                if moov.len() == moov_len {
+                    break moov;
+                }
+                moov_len = moov.len();
+            };
+            w.bytes(&moov);
+            w.bytes(&mdat.into_inner());
+        } else {
+            place_mdat(&mut infos, w.len() as u64);
+            w.bytes(&mdat.into_inner());
+            w.bytes(&write_moov(&infos));
+        }
+        Ok(w.into_inner())
+    }
+
+    /// Assemble a WebM file with one SimpleBlock per chunk and a BlockGroup
+    /// per subtitle cue, interleaved by timestamp. Timestamps are rebased
+    /// so the earliest chunk or cue starts at 0.
+    fn write_webm(&self) -> Result<Vec<u8>, MuxerError> {
+        self.validate()?;
+        let video_chunks: Vec<&[Chunk]> = self
+            .video_tracks
+            .iter()
+            .map(|t| self.trimmed(&t.chunks, true))
+            .collect();
+        let audio_chunks = self.trimmed(&self.audio_chunks, false);
+        let cues = self.trimmed_cues();
+        let start = video_chunks
+            .iter()
+            .flat_map(|chunks| chunks.iter())
+            .chain(audio_chunks)
+            .map(|c| c.timestamp)
+            .chain(cues.first().map(|cue| cue.start))
+            .fold(f64::INFINITY, f64::min);
+        let to_ms = |timestamp: f64| ((timestamp - start) / 1000.0).round() as i64;
+
+        let mut tracks = Vec::new();
+        let mut blocks = Vec::new();
+        for (track, chunks) in self.video_tracks.iter().zip(&video_chunks) {
+            let Some(config) = &track.config else {
+                continue;
+            };
+            let number = tracks.len() as u64 + 1;
+            tracks.push(WebmTrack {
+                number,
+                kind: TrackKind::Video {
+                    width: config.width,
+                    height: config.height,
+                },
+                codec_id: codec::webm_video_codec_id(&config.codec)
+                    .ok_or_else(|| unsupported_codec("video", &config.codec))?,
+                codec_private: track
+                    .codec_config
+                    .clone()
+                    .or_else(|| track.stream_codec_config.clone()),
+            });
+            blocks.extend(chunks.iter().map(|c| Block {
+                track: number,
+                timestamp_ms: to_ms(c.timestamp),
+                is_key: c.is_key,
+                data: &c.data,
+                duration_ms: None,
+            }));
+        }
+        if let Some(config) = &self.audio_config {
+            let number = tracks.len() as u64 + 1;
+            let codec_id = codec::webm_audio_codec_id(&config.codec)
+                .ok_or_else(|| unsupported_codec("audio", &config.codec))?;
+            tracks.push(WebmTrack {
+                number,
+                kind: TrackKind::Audio {
+                    sample_rate: config.sample_rate,
+                    channels: config.channels,
+                },
+                codec_id,
+                codec_private: match codec_id {
+                    "A_OPUS" => Some(self.opus_head(config)),
+                    _ => self.audio_codec_config.clone(),
+                },
+            });
+            blocks.extend(audio_chunks.iter().map(|c| Block {
+                track: number,
+                timestamp_ms: to_ms(c.timestamp),
+                is_key: true,
+                data: &c.data,
+                duration_ms: None,
+            }));
+        }
+        if !cues.is_empty() {
+            let number = tracks.len() as u64 + 1;
+            tracks.push(WebmTrack {
+                number,
+                kind: TrackKind::Text,
+                codec_id: "D_WEBVTT/SUBTITLES",
+                codec_private: None,
+            });
+            blocks.extend(cues.iter().map(|cue| Block {
+                track: number,
+                timestamp_ms: to_ms(cue.start),
+                is_key: true,
+                data: cue.text.as_bytes(),
+                duration_ms: Some((to_ms(cue.end) - to_ms(cue.start)) as u64),
+            }));
+        }
+        blocks.sort_by_key(|block| block.timestamp_ms);
+
+        let end = video_chunks
+            .iter()
+            .copied()
+            .chain([audio_chunks])
+            .filter_map(end_timestamp)
+            .chain(cues.iter().map(|cue| cue.end))
+            .fold(start, f64::max);
+        let duration_ms = if end.is_finite() {
+            (end - start) / 1000.0
+        } else {
+            0.0
+        };
+        let trimmed_chapters = self.trimmed_chapters();
+        let chapters: Vec<WebmChapter> = trimmed_chapters
+            .iter()
+            .enumerate()
+            .map(|(index, chapter)| WebmChapter {
+                start_ms: if index == 0 {
+                    0.0
+                } else {
+                    ((chapter.time - start) / 1000.0).max(0.0)
+                },
+                end_ms: trimmed_chapters
+                    .get(index + 1)
+                    .map_or(duration_ms, |next| (next.time - start) / 1000.0),
+                title: &chapter.title,
+            })
+            .collect();
+        Ok(webm::write_webm(
+            &tracks,
+            &blocks,
+            duration_ms,
+            &self.metadata(),
+            &chapters,
+        ))
+    }
+
+    /// Describe how far the durations of the first video track and the
+    /// audio track diverge, when it is more than the sync tolerance
+    fn check_av_sync(&self) -> Option<String> {
+        let span = |chunks: &[Chunk]| Some(end_timestamp(chunks)? - start_timestamp(chunks)?);
+        let video = span(&self.video_tracks.first()?.chunks)?;
+        let audio = span(&self.audio_chunks)?;
+        let drift = (video - audio).abs();
+        (drift > self.sync_tolerance).then(|| {
+            format!(
+                "A/V sync: video lasts {:.3} s and audio {:.3} s, {:.3} s apart (tolerance {:.3} s)",
+                video / 1_000_000.0,
+                audio / 1_000_000.0,
+                drift / 1_000_000.0,
+                self.sync_tolerance / 1_000_000.0
+            )
+        })
+    }
+
+    /// Timestamp of the first keyframe of the first video track (or audio
+    /// chunk, without video) at least `target_us` after the first buffered
+    /// chunk, where a fragment of the target duration ends
+    fn fragment_split(&self, target_us: f64) -> Option<f64> {
+        let chunks = match self.video_tracks.first() {
+            Some(track) => &track.chunks,
+            None => &self.audio_chunks,
+        };
+        let start = chunks.first()?.timestamp;
+        chunks
+            .iter()
+            .skip(1)
+            .find(|c| c.is_key && c.timestamp - start >= target_us)
+            .map(|c| c.timestamp)
+    }
+
+    /// The completed GOPs as a media segment, as next_media_segment
+    /// describes; empty when there is none
+    fn take_media_segment(&mut self) -> Result<Vec<u8>, MuxerError> {
+        let split = match self.video_tracks.first() {
+            Some(track) => match track.chunks.iter().rev().find(|c| c.is_key) {
+                Some(keyframe) => Some(keyframe.timestamp),
+                None => return Ok(Vec::new()),
+            },
+            None => None,
+        };
+        self.take_fragment(split)
+    }
+
+    /// Write a fragment from the buffered chunks before `split` (all of
+    /// them without one) and release them
+    fn take_fragment(&mut self, split: Option<f64>) -> Result<Vec<u8>, MuxerError> {
+        let output = self.write_fragment(self.fragment_sequence, split)?;
+        if !output.is_empty() {
+            self.fragment_sequence += 1;
+            for track in &mut self.video_tracks {
+                track.chunks.drain(..fragment_len(&track.chunks, split));
+            }
+            self.audio_chunks
+                .drain(..fragment_len(&self.audio_chunks, split));
+        }
+        Ok(output)
+    }
+
+    /// In streaming mode, hand the init segment (once) and a fragment of
+    /// the buffered chunks to the output callback
+    fn stream_buffered(&mut self) -> Result<(), JsValue> {
+        let Some(callback) = self.output_callback.clone() else {
+            return Ok(());
+        };
+        if !self.init_segment_sent {
+            let init = self.write_init_segment()?;
+            callback.call1(&JsValue::NULL, &Uint8Array::from(&init[..]))?;
+            self.init_segment_sent = true;
+        }
+        let fragment = self.take_fragment(None)?;
+        if !fragment.is_empty() {
+            callback.call1(&JsValue::NULL, &Uint8Array::from(&fragment[..]))?;
+        }
+        Ok(())
+    }
+
+    /// Fail with a descriptive error when an MP4-only feature is used with
+    /// another container
+    fn require_mp4(&self, feature: &'static str) -> Result<(), MuxerError> {
+        match self.container {
+            Container::Mp4 => Ok(()),
+            Container::Webm => Err(MuxerError::UnsupportedOperation {
+                feature,
+                container: "webm",
+            }),
+        }
+    }
+
+    /// Build ftyp+moov with empty sample tables and an mvex box
+    fn write_init_segment(&self) -> Result<Vec<u8>, MuxerError> {
+        self.require_mp4("fragmented output")?;
+        if !self.is_configured() {
+            return Err(MuxerError::NotConfigured);
+        }
+        let infos: Vec<TrackInfo> = self.tracks()?.into_iter().map(|(info, _)| info).collect();
+        let needs_config =
+            |info: &TrackInfo| matches!(&info.sample_entry, b"avc1" | b"hvc1" | b"vp09" | b"av01");
+        if infos
+            .iter()
+            .any(|info| needs_config(info) && info.codec_config.is_none())
+        {
+            return Err(MuxerError::InvalidCodecConfig {
+                track: "video",
+                reason: "no decoder configuration record yet; set one or add a keyframe first",
+            });
+        }
+        let sample_entries: Vec<[u8; 4]> = infos.iter().map(|info| info.sample_entry).collect();
+        let mut w = BoxWriter::new();
+        mp4::write_ftyp(&mut w, &sample_entries, true);
+        mp4::write_moov(&mut w, &infos, true, self.movie_timescale, &self.metadata());
+        Ok(w.into_inner())
+    }
+
+    /// Build a moof+mdat fragment from the buffered chunks before `split`,
+    /// or all of them.
+    ///
+    /// The decode time of each track fragment is its earliest chunk
+    /// timestamp, unshifted by B-frame reordering, so fragments line up
+    /// back to back; the last sample of a fragment repeats the previous
+    /// sample duration since the next chunk is not known yet.
+    fn write_fragment(
+        &self,
+        sequence_number: u32,
+        split: Option<f64>,
+    ) -> Result<Vec<u8>, MuxerError> {
+        self.require_mp4("fragmented output")?;
+        self.validate()?;
+        let tracks: Vec<(TrackInfo, &[Chunk])> = self
+            .tracks()?
+            .into_iter()
+            .map(|(info, chunks)| (info, &chunks[..fragment_len(chunks, split)]))
+            .collect();
+        let tracks: Vec<(TrackInfo, &[Chunk])> = tracks
+            .into_iter()
+            .filter(|(_, chunks)| !chunks.is_empty())
+            .collect();
+        let fragments: Vec<TrackFragment> = tracks
+            .iter()
+            .map(|(info, chunks)| {
+                let timing = timing::sample_timing(
+                    &timestamps(chunks),
+                    info.timescale,
+                    default_duration(info),
+                );
+                TrackFragment {
+                    track_id: info.id,
+                    base_decode_time: timing.base_decode_time.max(0) as u64,
+                    sample_sizes: sample_sizes(chunks),
+                    sample_durations: timing.durations,
+                    composition_offsets: timing.composition_offsets,
+                    sample_flags: chunks.iter().map(|c| mp4::sample_flags(c.is_key)).collect(),
+                }
+            })
+            .collect();
+        if fragments.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let track_timestamps: Vec<Vec<f64>> = tracks
+            .iter()
+            .map(|(_, chunks)| timestamps(chunks))
+            .collect();
+        let track_timestamps: Vec<&[f64]> = track_timestamps.iter().map(Vec::as_slice).collect();
+        let layout = timing::interleave(&track_timestamps, INTERLEAVE_US);
+
+        let mut w = BoxWriter::new();
+        mp4::write_moof(&mut w, sequence_number, &fragments, &layout);
+        let payload_len: u64 = fragments
+            .iter()
+            .flat_map(|f| f.sample_sizes.iter())
+            .map(|&size| size as u64)
+            .sum();
+        mp4::write_mdat_header(&mut w, payload_len);
+        for (track, samples) in layout {
+            for chunk in &tracks[track].1[samples] {
+                w.bytes(&chunk.data);
+            }
+        }
+        Ok(w.into_inner())
+    }
+}
+
+fn checked_timescale(track: &'static str, timescale: u32) -> Result<u32, MuxerError> {
+    if timescale == 0 {
+        Err(MuxerError::InvalidTimescale { track })
+    } else {
+        Ok(timescale)
+    }
+}
+
+/// Error for a codec string that the container cannot carry
+fn unsupported_codec(track: &'static str, codec: &str) -> MuxerError {
+    MuxerError::UnsupportedCodec {
+        track,
+        codec: codec.to_string(),
+    }
+}
+
+/// Earliest presentation timestamp of a track in microseconds
+fn start_timestamp(chunks: &[Chunk]) -> Option<f64> {
+    chunks.iter().map(|c| c.timestamp).min_by(f64::total_cmp)
+}
+
+/// Presentation end of a track in microseconds, extrapolating the last
+/// frame's duration from the previous one
+fn end_timestamp(chunks: &[Chunk]) -> Option<f64> {
+    let mut timestamps = timestamps(chunks);
+    timestamps.sort_by(f64::total_cmp);
+    let last = *timestamps.last()?;
+    let previous = timestamps
+        .len()
+        .checked_sub(2)
+        .map_or(last, |i| timestamps[i]);
+    Some(2.0 * last - previous)
+}
+
+/// Stable-sort video chunks by GOP: the chunks from one keyframe up to the
+/// next stay together in call order, and GOPs are ordered by the timestamp
+/// of their first chunk
+fn sort_gops(chunks: &mut Vec<Chunk>) {
+    let mut gops: Vec<Vec<Chunk>> = Vec::new();
+    for chunk in chunks.drain(..) {
+        match gops.last_mut() {
+            Some(gop) if !chunk.is_key => gop.push(chunk),
+            _ => gops.push(vec![chunk]),
+        }
+    }
+    gops.sort_by(|a, b| a[0].timestamp.total_cmp(&b[0].timestamp));
+    chunks.extend(gops.into_iter().flatten());
+}
+
+/// Duration assumed for a track's last sample when it cannot be derived
+fn default_duration(info: &TrackInfo) -> u32 {
+    match info.kind {
+        TrackKind::Video { .. } => info.timescale / DEFAULT_FRAME_RATE,
+        TrackKind::Audio { sample_rate, .. } => {
+            audio_frame_duration(sample_rate, info.timescale, &info.sample_entry == b"Opus")
+        }
+        // Replaced by the length of the last cue or chapter
+        TrackKind::Text | TrackKind::Chapters => info.timescale,
+    }
+}
+
+/// Length of one audio packet at `sample_rate`, in `timescale` ticks
+fn audio_frame_duration(sample_rate: u32, timescale: u32, is_opus: bool) -> u32 {
+    let samples = if is_opus {
+        sample_rate * OPUS_FRAME_MS / 1000
+    } else {
+        AAC_FRAME_SAMPLES
+    };
+    (samples as u64 * timescale as u64 / sample_rate.max(1) as u64) as u32
+}
+
+/// Total sample duration of a track in seconds, plus the delay of its
+/// first chunk after `movie_start` (microseconds)
+fn track_duration(
+    chunks: &[Chunk],
+    timescale: u32,
+    default_duration: u32,
+    movie_start: f64,
+) -> f64 {
+    let Some(start) = start_timestamp(chunks).filter(|_| timescale != 0) else {
+        return 0.0;
+    };
+    let timing = timing::sample_timing(&timestamps(chunks), timescale, default_duration);
+    let ticks: u64 = timing.durations.iter().map(|&d| d as u64).sum();
+    ticks as f64 / timescale as f64 + (start - movie_start) / 1_000_000.0
+}
+
+/// Seconds since the Unix epoch
+#[cfg(target_arch = "wasm32")]
+fn now() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+/// Seconds since the Unix epoch; the JS clock is only there in the
+/// browser, so native builds such as tests use the system clock
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Number of leading chunks that belong in a fragment cut at `split`:
+/// those before the first keyframe at or after it (every audio chunk is a
+/// keyframe)
+fn fragment_len(chunks: &[Chunk], split: Option<f64>) -> usize {
+    split
+        .and_then(|split| chunks.iter().position(|c| c.is_key && c.timestamp >= split))
+        .unwrap_or(chunks.len())
+}
+
+fn timestamps(chunks: &[Chunk]) -> Vec<f64> {
+    chunks.iter().map(|c| c.timestamp).collect()
+}
+
+fn sample_sizes(chunks: &[Chunk]) -> Vec<u32> {
+    chunks.iter().map(|c| c.data.len() as u32).collect()
+}
+
+/// 1-based sample numbers of the keyframes
+fn sync_samples(chunks: &[Chunk]) -> Vec<u32> {
+    chunks
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.is_key)
+        .map(|(i, _)| i as u32 + 1)
+        .collect()
+}
+
 impl Default for Muxer {
     fn default() -> Self {
         Self::new()
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::boxes;
+
+    fn kinds(data: &[u8]) -> Vec<[u8; 4]> {
+        boxes(data).map(|(kind, _)| kind).collect()
+    }
+
+    fn children<'a>(data: &'a [u8], kind: &[u8; 4]) -> Vec<&'a [u8]> {
+        boxes(data)
+            .filter(|(k, _)| k == kind)
+            .map(|(_, body)| body)
+            .collect()
+    }
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    fn push_frames(muxer: &mut Muxer, timestamps: std::ops::Range<u32>) {
+        for i in timestamps {
+            let is_key = i % 2 == 0;
+            let nal = if is_key { 0x65 } else { 0x41 };
+            let data = vec![0, 0, 0, 6, nal, 1, 2, 3, 4, 5];
+            let timestamp = i as f64 * 500_000.0;
+            muxer.push_video_chunk(0, data, timestamp, is_key).unwrap();
+            muxer.audio_chunks.push(Chunk {
+                data: vec![0xF8, 0xFF, 0xFE],
+                timestamp,
+                is_key: true,
+            });
+        }
+    }
+
+    /// (sequence number, video tfdt, video duration) of a moof
+    fn fragment_timing(moof: &[u8]) -> (u32, u64, u64) {
+        let mfhd = children(moof, b"mfhd")[0];
+        let traf = children(moof, b"traf")[0];
+        let tfdt = children(traf, b"tfdt")[0];
+        let base = (u32_at(tfdt, 4) as u64) << 32 | u32_at(tfdt, 8) as u64;
+        let duration = children(traf, b"trun")
+            .into_iter()
+            .map(|trun| {
+                let stride = if u32_at(trun, 0) & 0x0800 != 0 {
+                    16
+                } else {
+                    12
+                };
+                (0..u32_at(trun, 4) as usize)
+                    .map(|i| u32_at(trun, 12 + i * stride) as u64)
+                    .sum::<u64>()
+            })
+            .sum();
+        (u32_at(mfhd, 4), base, duration)
+    }
+
+    fn child<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> &'a [u8] {
+        path.iter().fold(data, |data, kind| children(data, kind)[0])
+    }
+
+    /// Sample bytes of a trak, read back from the file through its
+    /// stco or co64, stsc and stsz
+    fn trak_samples<'a>(file: &'a [u8], trak: &[u8]) -> Vec<&'a [u8]> {
+        let stbl = child(trak, &[b"mdia", b"minf", b"stbl"]);
+        let stsz = children(stbl, b"stsz")[0];
+        let sizes: Vec<usize> = (0..u32_at(stsz, 8) as usize)
+            .map(|i| u32_at(stsz, 12 + i * 4) as usize)
+            .collect();
+        let stsc = children(stbl, b"stsc")[0];
+        let runs: Vec<(u32, u32)> = (0..u32_at(stsc, 4) as usize)
+            .map(|i| (u32_at(stsc, 8 + i * 12), u32_at(stsc, 12 + i * 12)))
+            .collect();
+        let offsets: Vec<usize> = match children(stbl, b"stco").first() {
+            Some(stco) => (0..u32_at(stco, 4) as usize)
+                .map(|i| u32_at(stco, 8 + i * 4) as usize)
+                .collect(),
+            None => {
+                let co64 = children(stbl, b"co64")[0];
+                (0..u32_at(co64, 4) as usize)
+                    .map(|i| {
+                        (u32_at(co64, 8 + i * 8) as usize) << 32 | u32_at(co64, 12 + i * 8) as usize
+                    })
+                    .collect()
+            }
+        };
+        let mut sizes = sizes.into_iter();
+        let mut samples = Vec::new();
+        for (index, &offset) in offsets.iter().enumerate() {
+            let chunk = index as u32 + 1;
+            let (_, per_chunk) = runs
+                .iter()
+                .rev()
+                .find(|(first, _)| *first <= chunk)
+                .unwrap();
+            let mut pos = offset;
+            for size in sizes.by_ref().take(*per_chunk as usize) {
+                samples.push(&file[pos..pos + size]);
+                pos += size;
+            }
+        }
+        assert_eq!(sizes.next(), None);
+        samples
+    }
+
+    /// Expand the (count, value) entries of an stts or ctts box
+    fn run_lengths(table: &[u8]) -> Vec<u32> {
+        (0..u32_at(table, 4) as usize)
+            .flat_map(|i| {
+                let count = u32_at(table, 8 + i * 8) as usize;
+                std::iter::repeat_n(u32_at(table, 12 + i * 8), count)
+            })
+            .collect()
+    }
+
+    /// Read an EBML vint, keeping the length marker for IDs and dropping
+    /// it for sizes; returns the value and its length
+    fn vint(data: &[u8], keep_marker: bool) -> (u64, usize) {
+        let len = data[0].leading_zeros() as usize + 1;
+        let value = data[..len].iter().fold(0, |v, &b| v << 8 | b as u64);
+        if keep_marker {
+            (value, len)
+        } else {
+            (value & !(1 << (7 * len)), len)
+        }
+    }
+
+    /// (id, body) of each EBML element in data
+    fn elements(mut data: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut elements = Vec::new();
+        while !data.is_empty() {
+            let (id, id_len) = vint(data, true);
+            let (size, size_len) = vint(&data[id_len..], false);
+            let start = id_len + size_len;
+            elements.push((id as u32, &data[start..start + size as usize]));
+            data = &data[start + size as usize..];
+        }
+        elements
+    }
+
+    fn element(data: &[u8], id: u32) -> &[u8] {
+        elements(data)
+            .into_iter()
+            .find(|(i, _)| *i == id)
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn segments_follow_the_init_segment_in_sequence() {
+        let mut muxer = Muxer::new();
+        muxer.configure_video(64, 64, "avc1.42001f").unwrap();
+        muxer.configure_audio(48000, 2, "opus").unwrap();
+        muxer.first_video_track().codec_config = Some(vec![1, 0x42, 0, 0x1f, 0xff, 0xe0, 0]);
+
+        let init = muxer.write_init_segment().unwrap();
+        assert_eq!(kinds(&init), [*b"ftyp", *b"moov"]);
+        let moov = children(&init, b"moov")[0];
+        assert_eq!(children(moov, b"trak").len(), 2);
+        let mvex = children(moov, b"mvex")[0];
+        let trex_ids: Vec<u32> = children(mvex, b"trex")
+            .into_iter()
+            .map(|trex| u32_at(trex, 4))
+            .collect();
+        assert_eq!(trex_ids, [1, 2]);
+
+        let mut previous_end = None;
+        for (segment, frames) in [0..3, 3..5, 5..7].into_iter().enumerate() {
+            push_frames(&mut muxer, frames);
+            let output = muxer.take_media_segment().unwrap();
+            assert_eq!(kinds(&output), [*b"moof", *b"mdat"]);
+            let moof = children(&output, b"moof")[0];
+            let (sequence, base, duration) = fragment_timing(moof);
+            assert_eq!(sequence, segment as u32 + 1);
+            if let Some(end) = previous_end {
+                assert_eq!(base, end);
+            }
+            previous_end = Some(base + duration);
+        }
+    }
+
+    #[test]
+    fn video_chunks_without_a_video_track_are_rejected() {
+        let mut muxer = Muxer::new();
+        muxer.configure_audio(48000, 2, "opus").unwrap();
+        assert_eq!(
+            muxer.first_video_chunks().err(),
+            Some(MuxerError::MissingConfig { track: "video" })
+        );
+        assert!(muxer.video_tracks.is_empty());
+        for i in 0..3 {
+            muxer.audio_chunks.push(Chunk {
+                data: vec![0xF8, 0xFF, 0xFE],
+                timestamp: i as f64 * 20_000.0,
+                is_key: true,
+            });
+        }
+
+        let output = muxer.write_mp4().unwrap();
+        let moov = children(&output, b"moov")[0];
+        let handlers: Vec<&[u8]> = children(moov, b"trak")
+            .into_iter()
+            .map(|trak| {
+                let mdia = children(trak, b"mdia")[0];
+                &children(mdia, b"hdlr")[0][8..12]
+            })
+            .collect();
+        assert_eq!(handlers, [b"soun"]);
+    }
+
+    #[test]
+    fn finalized_mp4_reads_back_every_sample() {
+        for faststart in [false, true] {
+            let mut muxer = Muxer::new();
+            muxer.configure_video(64, 64, "avc1.42001f").unwrap();
+            muxer.configure_audio(48000, 2, "opus").unwrap();
+            muxer.first_video_track().codec_config = Some(vec![1, 0x42, 0, 0x1f, 0xff, 0xe0, 0]);
+            muxer.set_faststart(faststart);
+            let mut video = Vec::new();
+            let mut audio = Vec::new();
+            for i in 0..12u8 {
+                let is_key = i % 4 == 0;
+                let nal = if is_key { 0x65 } else { 0x41 };
+                let data = vec![0, 0, 0, 3, nal, i, i];
+                let timestamp = i as f64 * 40_000.0;
+                muxer
+                    .push_video_chunk(0, data.clone(), timestamp, is_key)
+                    .unwrap();
+                video.push(data);
+                let data = vec![0xF8, i, i, i];
+                muxer.audio_chunks.push(Chunk {
+                    data: data.clone(),
+                    timestamp,
+                    is_key: true,
+                });
+                audio.push(data);
+            }
+
+            let output = muxer.write_mp4().unwrap();
+            let layout = if faststart {
+                [*b"ftyp", *b"moov", *b"mdat"]
+            } else {
+                [*b"ftyp", *b"mdat", *b"moov"]
+            };
+            assert_eq!(kinds(&output), layout);
+            let moov = children(&output, b"moov")[0];
+            let traks = children(moov, b"trak");
+            assert_eq!(trak_samples(&output, traks[0]), video);
+            assert_eq!(trak_samples(&output, traks[1]), audio);
+
+            // The chunk offsets point inside the mdat payload
+            let mdat = children(&output, b"mdat")[0];
+            let mdat_start = mdat.as_ptr() as usize - output.as_ptr() as usize;
+            let stco = child(traks[0], &[b"mdia", b"minf", b"stbl", b"stco"]);
+            assert_eq!(u32_at(stco, 8) as usize, mdat_start);
+
+            let stbl = child(traks[0], &[b"mdia", b"minf", b"stbl"]);
+            let stss = children(stbl, b"stss")[0];
+            let sync: Vec<u32> = (0..u32_at(stss, 4) as usize)
+                .map(|i| u32_at(stss, 8 + i * 4))
+                .collect();
+            assert_eq!(sync, [1, 5, 9]);
+            assert_eq!(run_lengths(children(stbl, b"stts")[0]), [3600; 12]);
+            assert!(children(stbl, b"ctts").is_empty());
+            let stbl = child(traks[1], &[b"mdia", b"minf", b"stbl"]);
+            assert_eq!(run_lengths(children(stbl, b"stts")[0]), [1920; 12]);
+        }
+    }
+
+    #[test]
+    fn finalized_mp4_offsets_reordered_frames() {
+        let mut muxer = Muxer::new();
+        muxer.configure_video(64, 64, "avc1.42001f").unwrap();
+        muxer.first_video_track().codec_config = Some(vec![1, 0x42, 0, 0x1f, 0xff, 0xe0, 0]);
+        // I P B B in decode order, presented as I B B P
+        for (i, pts) in [0, 3, 1, 2].into_iter().enumerate() {
+            let nal = if i == 0 { 0x65 } else { 0x41 };
+            let data = vec![0, 0, 0, 2, nal, i as u8];
+            muxer
+                .push_video_chunk(0, data, pts as f64 * 40_000.0, i == 0)
+                .unwrap();
+        }
+
+        let output = muxer.write_mp4().unwrap();
+        let stbl = child(&output, &[b"moov", b"trak", b"mdia", b"minf", b"stbl"]);
+        assert_eq!(run_lengths(children(stbl, b"stts")[0]), [3600; 4]);
+        let ctts = children(stbl, b"ctts")[0];
+        assert_eq!(ctts[0], 1);
+        let offsets: Vec<i32> = run_lengths(ctts).into_iter().map(|o| o as i32).collect();
+        assert_eq!(offsets, [0, 7200, -3600, -3600]);
+    }
+
+    #[test]
+    fn finalized_webm_starts_a_cluster_at_each_keyframe() {
+        let mut muxer = Muxer::new_with_container("webm").unwrap();
+        muxer.configure_video(64, 64, "vp8").unwrap();
+        muxer.configure_audio(48000, 2, "opus").unwrap();
+        for i in 0..4u8 {
+            let is_key = i % 2 == 0;
+            let data = if is_key {
+                vec![0x10, 0x02, 0x00, 0x9D, 0x01, 0x2A, 64, 0, 64, 0, i]
+            } else {
+                vec![0x01, i]
+            };
+            muxer
+                .push_video_chunk(0, data, i as f64 * 40_000.0, is_key)
+                .unwrap();
+        }
+        for i in 0..3u8 {
+            muxer.audio_chunks.push(Chunk {
+                data: vec![0xF8, i],
+                timestamp: i as f64 * 50_000.0,
+                is_key: true,
+            });
+        }
+
+        let output = muxer.write_webm().unwrap();
+        let top: Vec<u32> = elements(&output).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(top, [0x1A45_DFA3, 0x1853_8067]);
+        let segment = element(&output, 0x1853_8067);
+        let ids: Vec<u32> = elements(segment).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, [0x1549_A966, 0x1654_AE6B, 0x1F43_B675, 0x1F43_B675]);
+        let tracks = element(segment, 0x1654_AE6B);
+        let codecs: Vec<&[u8]> = elements(tracks)
+            .into_iter()
+            .map(|(_, entry)| element(entry, 0x86))
+            .collect();
+        assert_eq!(codecs, [&b"V_VP8"[..], b"A_OPUS"]);
+
+        // (cluster timestamp, [(track, relative timestamp, key flag, last byte)])
+        let clusters: Vec<(u64, Vec<_>)> = elements(segment)
+            .into_iter()
+            .filter(|(id, _)| *id == 0x1F43_B675)
+            .map(|(_, cluster)| {
+                let timestamp = element(cluster, 0xE7);
+                let timestamp = timestamp.iter().fold(0, |v, &b| v << 8 | b as u64);
+                let blocks = elements(cluster)
+                    .into_iter()
+                    .filter(|(id, _)| *id == 0xA3)
+                    .map(|(_, block)| {
+                        let relative = i16::from_be_bytes([block[1], block[2]]);
+                        (
+                            block[0] & 0x7F,
+                            relative,
+                            block[3] == 0x80,
+                            *block.last().unwrap(),
+                        )
+                    })
+                    .collect();
+                (timestamp, blocks)
+            })
+            .collect();
+        assert_eq!(
+            clusters,
+            [
+                (
+                    0,
+                    vec![
+                        (1, 0, true, 0),
+                        (2, 0, true, 0),
+                        (1, 40, false, 1),
+                        (2, 50, true, 1)
+                    ]
+                ),
+                (
+                    80,
+                    vec![(1, 0, true, 2), (2, 20, true, 2), (1, 40, false, 3)]
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Descriptive tags and timestamps written into the container
+
+/// Seconds from the MP4 epoch (1904-01-01) to the Unix epoch
+const MP4_EPOCH_OFFSET: u64 = 2_082_844_800;
+/// Seconds from the Unix epoch to the Matroska epoch (2001-01-01)
+const MATROSKA_EPOCH_OFFSET: i64 = 978_307_200;
+
+/// Name of this muxer as written into files, e.g. the WebM MuxingApp and
+/// the default encoder tag
+pub(crate) const APP_NAME: &str = concat!("fuse-muxer ", env!("CARGO_PKG_VERSION"));
+
+/// Tag that can be set with Muxer.set_metadata
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Tag {
+    Title,
+    Artist,
+    Comment,
+    /// Application that produced the file
+    Encoder,
+}
+
+impl Tag {
+    pub(crate) fn parse(key: &str) -> Option<Self> {
+        match key.to_ascii_lowercase().as_str() {
+            "title" => Some(Self::Title),
+            "artist" => Some(Self::Artist),
+            "comment" => Some(Self::Comment),
+            "encoder" => Some(Self::Encoder),
+            _ => None,
+        }
+    }
+
+    /// iTunes-style item type in the MP4 ilst box
+    pub(crate) fn ilst_item(self) -> [u8; 4] {
+        let name = match self {
+            Self::Title => b"nam",
+            Self::Artist => b"ART",
+            Self::Comment => b"cmt",
+            Self::Encoder => b"too",
+        };
+        [0xA9, name[0], name[1], name[2]]
+    }
+
+    /// TagName of the Matroska SimpleTag
+    pub(crate) fn webm_name(self) -> &'static str {
+        match self {
+            Self::Title => "TITLE",
+            Self::Artist => "ARTIST",
+            Self::Comment => "COMMENT",
+            Self::Encoder => "ENCODER",
+        }
+    }
+}
+
+/// Movie-level information shared by the MP4 and WebM writers
+pub(crate) struct Metadata<'a> {
+    /// When the file was written, in seconds since the Unix epoch
+    pub creation_time: u64,
+    pub tags: &'a [(Tag, String)],
+}
+
+impl Metadata<'_> {
+    /// Creation time for the mvhd/tkhd/mdhd boxes, in seconds since 1904
+    pub(crate) fn mp4_time(&self) -> u64 {
+        self.creation_time.saturating_add(MP4_EPOCH_OFFSET)
+    }
+
+    /// Creation time for the WebM DateUTC element, in nanoseconds since
+    /// 2001
+    pub(crate) fn webm_date(&self) -> i64 {
+        (self.creation_time as i64 - MATROSKA_EPOCH_OFFSET) * 1_000_000_000
+    }
+
+    /// The tags to write: those that were set, plus the encoder naming
+    /// this muxer unless one was set, like the vendor string of Opus and
+    /// Vorbis comments
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (Tag, &str)> {
+        let encoder = match self.get(Tag::Encoder) {
+            Some(_) => None,
+            None => Some((Tag::Encoder, APP_NAME)),
+        };
+        self.tags
+            .iter()
+            .map(|(tag, value)| (*tag, value.as_str()))
+            .chain(encoder)
+    }
+
+    pub(crate) fn get(&self, tag: Tag) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, value)| value.as_str())
+    }
+}
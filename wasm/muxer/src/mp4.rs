@@ -0,0 +1,910 @@
+//! ISO BMFF (MP4) box writing
+
+use std::ops::Range;
+
+use crate::metadata::Metadata;
+use crate::TrackKind;
+
+/// Unity transformation matrix shared by mvhd and tkhd
+const UNITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// Growable byte buffer with helpers for nested, size-prefixed boxes
+pub(crate) struct BoxWriter {
+    buf: Vec<u8>,
+}
+
+impl BoxWriter {
+    pub fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u24(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_be_bytes()[1..]);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    pub fn zeros(&mut self, count: usize) {
+        self.buf.resize(self.buf.len() + count, 0);
+    }
+
+    /// Write a box, patching its 32-bit size once the body has been written
+    pub fn write_box(&mut self, fourcc: &[u8; 4], body: impl FnOnce(&mut Self)) {
+        let start = self.buf.len();
+        self.u32(0);
+        self.bytes(fourcc);
+        body(self);
+        let size = (self.buf.len() - start) as u32;
+        self.buf[start..start + 4].copy_from_slice(&size.to_be_bytes());
+    }
+
+    /// Write a full box (box header followed by version and flags)
+    pub fn write_full_box(
+        &mut self,
+        fourcc: &[u8; 4],
+        version: u8,
+        flags: u32,
+        body: impl FnOnce(&mut Self),
+    ) {
+        self.write_box(fourcc, |w| {
+            w.u8(version);
+            w.u24(flags);
+            body(w);
+        });
+    }
+}
+
+/// Everything needed to describe one track in the moov box
+pub(crate) struct TrackInfo {
+    pub id: u32,
+    pub kind: TrackKind,
+    /// Sample entry type (avc1, mp4a, ...)
+    pub sample_entry: [u8; 4],
+    pub timescale: u32,
+    /// Size in bytes of each sample, in decode order
+    pub sample_sizes: Vec<u32>,
+    /// Duration of each sample in `timescale` units
+    pub sample_durations: Vec<u32>,
+    /// Composition time offset of each sample in `timescale` units,
+    /// negative for samples shown before their decode time (B-frames)
+    pub composition_offsets: Vec<i32>,
+    /// 1-based numbers of sync samples; every sample is a sync sample when
+    /// this lists all of them
+    pub sync_samples: Vec<u32>,
+    /// Absolute file offset of each chunk of the track in mdat
+    pub chunk_offsets: Vec<u64>,
+    /// Number of samples in each chunk
+    pub samples_per_chunk: Vec<u32>,
+    /// Time in movie timescale units from the start of the movie to the
+    /// track's first sample, written as an empty edit
+    pub start_delay: u64,
+    /// Media time in `timescale` units at the start that is decoded but
+    /// not shown: the frames between the keyframe a trimmed track starts
+    /// on and the trim start
+    pub preroll: u64,
+    /// Id of the chapter track this track refers to (tref/chap)
+    pub chapter_track: Option<u32>,
+    /// Codec configuration record: avcC/hvcC/dOps payload or the
+    /// AudioSpecificConfig wrapped in esds
+    pub codec_config: Option<Vec<u8>>,
+}
+
+impl TrackInfo {
+    /// Track description with empty sample tables
+    pub fn new(
+        id: u32,
+        kind: TrackKind,
+        sample_entry: [u8; 4],
+        timescale: u32,
+        codec_config: Option<Vec<u8>>,
+    ) -> Self {
+        Self {
+            id,
+            kind,
+            sample_entry,
+            timescale,
+            sample_sizes: Vec::new(),
+            sample_durations: Vec::new(),
+            composition_offsets: Vec::new(),
+            sync_samples: Vec::new(),
+            chunk_offsets: Vec::new(),
+            samples_per_chunk: Vec::new(),
+            start_delay: 0,
+            preroll: 0,
+            chapter_track: None,
+            codec_config,
+        }
+    }
+
+    fn duration(&self) -> u64 {
+        self.sample_durations.iter().map(|&d| d as u64).sum()
+    }
+
+    /// Duration of the presented media (pre-roll excluded) in
+    /// `movie_timescale` units
+    fn media_movie_duration(&self, movie_timescale: u32) -> u64 {
+        if self.timescale == 0 {
+            return 0;
+        }
+        let presented = self.duration().saturating_sub(self.preroll);
+        presented * movie_timescale as u64 / self.timescale as u64
+    }
+
+    /// Duration of the track on the movie timeline, start delay included
+    fn movie_duration(&self, movie_timescale: u32) -> u64 {
+        self.start_delay + self.media_movie_duration(movie_timescale)
+    }
+}
+
+/// Write the ftyp box; iso4 covers the signed offsets of a version 1 ctts,
+/// `sample_entries` adds the avc1 or av01 brand of an H.264 or AV1 track,
+/// and `fragmented` adds the brands that cover tfdt and
+/// default-base-is-moof track fragments
+pub(crate) fn write_ftyp(w: &mut BoxWriter, sample_entries: &[[u8; 4]], fragmented: bool) {
+    w.write_box(b"ftyp", |w| {
+        w.bytes(b"isom");
+        w.u32(0x200);
+        w.bytes(b"isom");
+        w.bytes(b"iso2");
+        w.bytes(b"iso4");
+        for brand in [b"avc1", b"av01"] {
+            if sample_entries.contains(brand) {
+                w.bytes(brand);
+            }
+        }
+        w.bytes(b"mp41");
+        if fragmented {
+            w.bytes(b"iso5");
+            w.bytes(b"iso6");
+        }
+    });
+}
+
+/// Write the mdat box header, switching to a 64-bit largesize when the
+/// box does not fit a 32-bit size
+pub(crate) fn write_mdat_header(w: &mut BoxWriter, payload_len: u64) {
+    if payload_len + 8 > u32::MAX as u64 {
+        w.u32(1);
+        w.bytes(b"mdat");
+        w.u64(payload_len + 16);
+    } else {
+        w.u32((payload_len + 8) as u32);
+        w.bytes(b"mdat");
+    }
+}
+
+/// Write the moov box with mvhd/tkhd durations in `movie_timescale`
+/// units; `fragmented` adds an mvex box announcing movie fragments, and
+/// tags go into a udta box
+pub(crate) fn write_moov(
+    w: &mut BoxWriter,
+    tracks: &[TrackInfo],
+    fragmented: bool,
+    movie_timescale: u32,
+    metadata: &Metadata,
+) {
+    w.write_box(b"moov", |w| {
+        let duration = tracks
+            .iter()
+            .map(|t| t.movie_duration(movie_timescale))
+            .max()
+            .unwrap_or(0);
+        let next_track_id = tracks.iter().map(|t| t.id).max().unwrap_or(0) + 1;
+        let time = metadata.mp4_time();
+        write_mvhd(w, movie_timescale, duration, next_track_id, time);
+        for track in tracks {
+            write_trak(w, track, movie_timescale, time);
+        }
+        if fragmented {
+            write_mvex(w, tracks);
+        }
+        if !metadata.tags.is_empty() {
+            write_udta(w, metadata);
+        }
+    });
+}
+
+/// Write the tags, default encoder included, as iTunes-style metadata
+/// (udta/meta/ilst), which most players and file managers read
+fn write_udta(w: &mut BoxWriter, metadata: &Metadata) {
+    w.write_box(b"udta", |w| {
+        w.write_full_box(b"meta", 0, 0, |w| {
+            w.write_full_box(b"hdlr", 0, 0, |w| {
+                w.u32(0); // pre_defined
+                w.bytes(b"mdir");
+                w.bytes(b"appl");
+                w.zeros(8);
+                w.u8(0); // empty name
+            });
+            w.write_box(b"ilst", |w| {
+                for (tag, value) in metadata.entries() {
+                    w.write_box(&tag.ilst_item(), |w| {
+                        // type 1: UTF-8 text
+                        w.write_full_box(b"data", 0, 1, |w| {
+                            w.u32(0); // locale
+                            w.bytes(value.as_bytes());
+                        });
+                    });
+                }
+            });
+        });
+    });
+}
+
+fn write_mvex(w: &mut BoxWriter, tracks: &[TrackInfo]) {
+    w.write_box(b"mvex", |w| {
+        for track in tracks {
+            w.write_full_box(b"trex", 0, 0, |w| {
+                w.u32(track.id);
+                w.u32(1); // default_sample_description_index
+                w.u32(0); // default_sample_duration
+                w.u32(0); // default_sample_size
+                w.u32(0); // default_sample_flags
+            });
+        }
+    });
+}
+
+/// Samples of one track within a movie fragment
+pub(crate) struct TrackFragment {
+    pub track_id: u32,
+    /// Decode time of the first sample in track timescale units (tfdt)
+    pub base_decode_time: u64,
+    pub sample_sizes: Vec<u32>,
+    pub sample_durations: Vec<u32>,
+    /// Presentation minus decode time of each sample, negative for samples
+    /// shown before their decode time (B-frames)
+    pub composition_offsets: Vec<i32>,
+    pub sample_flags: Vec<u32>,
+}
+
+/// trun sample flags for a sync or non-sync sample
+pub(crate) fn sample_flags(is_sync: bool) -> u32 {
+    if is_sync {
+        0x0200_0000 // sample_depends_on = 2 (independent)
+    } else {
+        0x0101_0000 // sample_depends_on = 1, sample_is_non_sync_sample
+    }
+}
+
+/// Write a moof box whose trafs reference sample data in the mdat that
+/// immediately follows it, laid out as the runs of `layout`: (fragment
+/// index, sample range) pairs in mdat order. Each run gets its own trun.
+pub(crate) fn write_moof(
+    w: &mut BoxWriter,
+    sequence_number: u32,
+    fragments: &[TrackFragment],
+    layout: &[(usize, Range<usize>)],
+) {
+    // The moof size does not depend on the data offsets, so measure it first
+    let mut probe = BoxWriter::new();
+    write_moof_with_offset(&mut probe, sequence_number, fragments, layout, 0);
+    let data_start = probe.len() as u32 + 8;
+    write_moof_with_offset(w, sequence_number, fragments, layout, data_start);
+}
+
+fn write_moof_with_offset(
+    w: &mut BoxWriter,
+    sequence_number: u32,
+    fragments: &[TrackFragment],
+    layout: &[(usize, Range<usize>)],
+    data_start: u32,
+) {
+    let mut runs: Vec<Vec<(u32, Range<usize>)>> = vec![Vec::new(); fragments.len()];
+    let mut data_offset = data_start;
+    for (index, samples) in layout {
+        runs[*index].push((data_offset, samples.clone()));
+        data_offset += fragments[*index].sample_sizes[samples.clone()]
+            .iter()
+            .sum::<u32>();
+    }
+    w.write_box(b"moof", |w| {
+        w.write_full_box(b"mfhd", 0, 0, |w| w.u32(sequence_number));
+        for (fragment, runs) in fragments.iter().zip(&runs) {
+            write_traf(w, fragment, runs);
+        }
+    });
+}
+
+/// Write a traf with one trun per (data offset, sample range) run
+fn write_traf(w: &mut BoxWriter, fragment: &TrackFragment, runs: &[(u32, Range<usize>)]) {
+    w.write_box(b"traf", |w| {
+        // flags: default-base-is-moof
+        w.write_full_box(b"tfhd", 0, 0x02_0000, |w| w.u32(fragment.track_id));
+        w.write_full_box(b"tfdt", 1, 0, |w| {
+            w.u32((fragment.base_decode_time >> 32) as u32);
+            w.u32(fragment.base_decode_time as u32);
+        });
+
+        let has_offsets = fragment.composition_offsets.iter().any(|&o| o != 0);
+        // data-offset, sample-duration, sample-size, sample-flags
+        let mut flags = 0x0001 | 0x0100 | 0x0200 | 0x0400;
+        if has_offsets {
+            flags |= 0x0800;
+        }
+        // Version 1 makes the composition offsets signed, so decode times
+        // need no shift and stay continuous from one fragment to the next
+        for (data_offset, samples) in runs {
+            w.write_full_box(b"trun", 1, flags, |w| {
+                w.u32(samples.len() as u32);
+                w.u32(*data_offset);
+                for i in samples.clone() {
+                    w.u32(fragment.sample_durations[i]);
+                    w.u32(fragment.sample_sizes[i]);
+                    w.u32(fragment.sample_flags[i]);
+                    if has_offsets {
+                        w.u32(fragment.composition_offsets[i] as u32);
+                    }
+                }
+            });
+        }
+    });
+}
+
+/// Version of an mvhd, tkhd or mdhd box: 1 when the times or the
+/// duration do not fit the 32-bit fields of version 0
+fn header_version(time: u64, duration: u64) -> u8 {
+    (time > u32::MAX as u64 || duration > u32::MAX as u64) as u8
+}
+
+/// Write a time or duration field of an mvhd, tkhd or mdhd box
+fn write_header_field(w: &mut BoxWriter, version: u8, value: u64) {
+    if version == 1 {
+        w.u64(value);
+    } else {
+        w.u32(value as u32);
+    }
+}
+
+fn write_mvhd(w: &mut BoxWriter, timescale: u32, duration: u64, next_track_id: u32, time: u64) {
+    let version = header_version(time, duration);
+    w.write_full_box(b"mvhd", version, 0, |w| {
+        write_header_field(w, version, time); // creation_time
+        write_header_field(w, version, time); // modification_time
+        w.u32(timescale);
+        write_header_field(w, version, duration);
+        w.u32(0x0001_0000); // rate 1.0
+        w.u16(0x0100); // volume 1.0
+        w.zeros(2 + 8);
+        for value in UNITY_MATRIX {
+            w.u32(value);
+        }
+        w.zeros(24); // pre_defined
+        w.u32(next_track_id);
+    });
+}
+
+/// Write a trak box; `time` is the creation time in seconds since 1904
+fn write_trak(w: &mut BoxWriter, track: &TrackInfo, movie_timescale: u32, time: u64) {
+    w.write_box(b"trak", |w| {
+        write_tkhd(w, track, movie_timescale, time);
+        if let Some(chapter_track) = track.chapter_track {
+            w.write_box(b"tref", |w| w.write_box(b"chap", |w| w.u32(chapter_track)));
+        }
+        if track.start_delay > 0 || track.preroll > 0 {
+            write_edts(w, track, movie_timescale);
+        }
+        w.write_box(b"mdia", |w| {
+            write_mdhd(w, track, time);
+            write_hdlr(w, track);
+            write_minf(w, track);
+        });
+    });
+}
+
+fn write_tkhd(w: &mut BoxWriter, track: &TrackInfo, movie_timescale: u32, time: u64) {
+    // flags: track_enabled | track_in_movie; the chapter track is only
+    // read through the chap reference and must not play as subtitles
+    let flags = match track.kind {
+        TrackKind::Chapters => 0,
+        _ => 0x3,
+    };
+    let duration = track.movie_duration(movie_timescale);
+    let version = header_version(time, duration);
+    w.write_full_box(b"tkhd", version, flags, |w| {
+        write_header_field(w, version, time); // creation_time
+        write_header_field(w, version, time); // modification_time
+        w.u32(track.id);
+        w.u32(0);
+        write_header_field(w, version, duration);
+        w.zeros(8);
+        w.u16(0); // layer
+        match track.kind {
+            TrackKind::Video { .. } | TrackKind::Text | TrackKind::Chapters => {
+                w.u16(0); // alternate_group
+                w.u16(0); // volume
+            }
+            TrackKind::Audio { .. } => {
+                w.u16(1);
+                w.u16(0x0100);
+            }
+        }
+        w.u16(0);
+        for value in UNITY_MATRIX {
+            w.u32(value);
+        }
+        match track.kind {
+            TrackKind::Video { width, height } => {
+                w.u32(width << 16);
+                w.u32(height << 16);
+            }
+            TrackKind::Audio { .. } | TrackKind::Text | TrackKind::Chapters => {
+                w.u32(0);
+                w.u32(0);
+            }
+        }
+    });
+}
+
+/// Write an edit list that places the track on the movie timeline: an
+/// empty edit for the start delay, then the media past any pre-roll
+fn write_edts(w: &mut BoxWriter, track: &TrackInfo, movie_timescale: u32) {
+    w.write_box(b"edts", |w| {
+        w.write_full_box(b"elst", 0, 0, |w| {
+            let empty = track.start_delay > 0;
+            w.u32(1 + empty as u32);
+            if empty {
+                w.u32(track.start_delay as u32);
+                w.u32(-1i32 as u32); // media_time: empty edit
+                w.u32(0x0001_0000); // media_rate 1.0
+            }
+            w.u32(track.media_movie_duration(movie_timescale) as u32);
+            w.u32(track.preroll as u32);
+            w.u32(0x0001_0000);
+        });
+    });
+}
+
+fn write_mdhd(w: &mut BoxWriter, track: &TrackInfo, time: u64) {
+    let duration = track.duration();
+    let version = header_version(time, duration);
+    w.write_full_box(b"mdhd", version, 0, |w| {
+        write_header_field(w, version, time); // creation_time
+        write_header_field(w, version, time); // modification_time
+        w.u32(track.timescale);
+        write_header_field(w, version, duration);
+        w.u16(0x55C4); // language "und"
+        w.u16(0);
+    });
+}
+
+fn write_hdlr(w: &mut BoxWriter, track: &TrackInfo) {
+    let (handler, name): (&[u8; 4], &[u8]) = match track.kind {
+        TrackKind::Video { .. } => (b"vide", b"VideoHandler\0"),
+        TrackKind::Audio { .. } => (b"soun", b"SoundHandler\0"),
+        TrackKind::Text => (b"sbtl", b"SubtitleHandler\0"),
+        TrackKind::Chapters => (b"text", b"ChapterHandler\0"),
+    };
+    w.write_full_box(b"hdlr", 0, 0, |w| {
+        w.u32(0); // pre_defined
+        w.bytes(handler);
+        w.zeros(12);
+        w.bytes(name);
+    });
+}
+
+fn write_minf(w: &mut BoxWriter, track: &TrackInfo) {
+    w.write_box(b"minf", |w| {
+        match track.kind {
+            TrackKind::Video { .. } => w.write_full_box(b"vmhd", 0, 1, |w| w.zeros(8)),
+            TrackKind::Audio { .. } => w.write_full_box(b"smhd", 0, 0, |w| w.zeros(4)),
+            TrackKind::Text | TrackKind::Chapters => w.write_full_box(b"nmhd", 0, 0, |_| {}),
+        }
+        w.write_box(b"dinf", |w| {
+            w.write_full_box(b"dref", 0, 0, |w| {
+                w.u32(1);
+                // flags: media data is in the same file
+                w.write_full_box(b"url ", 0, 1, |_| {});
+            });
+        });
+        write_stbl(w, track);
+    });
+}
+
+fn write_stbl(w: &mut BoxWriter, track: &TrackInfo) {
+    w.write_box(b"stbl", |w| {
+        w.write_full_box(b"stsd", 0, 0, |w| {
+            w.u32(1);
+            write_sample_entry(w, track);
+        });
+        write_stts(w, &track.sample_durations);
+        if track.composition_offsets.iter().any(|&offset| offset != 0) {
+            write_ctts(w, &track.composition_offsets);
+        }
+        if track.composition_offsets.iter().any(|&offset| offset < 0) {
+            write_cslg(w, &track.sample_durations, &track.composition_offsets);
+        }
+        // stss is omitted when every sample is a sync sample
+        if track.sync_samples.len() != track.sample_sizes.len() {
+            write_stss(w, &track.sync_samples);
+        }
+        write_stsc(w, &track.samples_per_chunk);
+        write_stsz(w, &track.sample_sizes);
+        write_stco(w, track);
+    });
+}
+
+fn write_sample_entry(w: &mut BoxWriter, track: &TrackInfo) {
+    let fourcc = track.sample_entry;
+    w.write_box(&fourcc, |w| {
+        w.zeros(6);
+        w.u16(1); // data_reference_index
+        match track.kind {
+            TrackKind::Video { width, height } => {
+                w.zeros(16); // pre_defined + reserved
+                w.u16(width as u16);
+                w.u16(height as u16);
+                w.u32(0x0048_0000); // 72 dpi horizontal
+                w.u32(0x0048_0000); // 72 dpi vertical
+                w.u32(0);
+                w.u16(1); // frame_count
+                w.zeros(32); // compressorname
+                w.u16(0x0018); // depth
+                w.u16(0xFFFF); // pre_defined
+            }
+            TrackKind::Audio {
+                sample_rate,
+                channels,
+            } => {
+                w.zeros(8);
+                w.u16(channels as u16);
+                w.u16(16); // samplesize
+                w.zeros(4);
+                // 16.16 fixed point; rates above 65535 Hz do not fit
+                w.u32(sample_rate.min(0xFFFF) << 16);
+            }
+            TrackKind::Text | TrackKind::Chapters => write_tx3g_entry(w),
+        }
+        if let Some(config) = &track.codec_config {
+            write_codec_config(w, track, config);
+        }
+    });
+}
+
+/// Body of a tx3g sample entry: white text centered at the bottom of the
+/// picture, in the player's default sans-serif font
+fn write_tx3g_entry(w: &mut BoxWriter) {
+    w.u32(0); // displayFlags
+    w.u8(1); // horizontal-justification: center
+    w.u8(0xFF); // vertical-justification: bottom
+    w.zeros(4); // background-color-rgba: transparent
+    w.zeros(8); // default-text-box: the whole track
+                // StyleRecord
+    w.u16(0); // startChar
+    w.u16(0); // endChar
+    w.u16(1); // font-ID
+    w.u8(0); // face-style-flags
+    w.u8(18); // font-size
+    w.u32(0xFFFF_FFFF); // text-color-rgba: opaque white
+    w.write_box(b"ftab", |w| {
+        w.u16(1);
+        w.u16(1); // font-ID
+        let name = b"Sans-Serif";
+        w.u8(name.len() as u8);
+        w.bytes(name);
+    });
+}
+
+/// Write the codec configuration box that belongs to the sample entry
+fn write_codec_config(w: &mut BoxWriter, track: &TrackInfo, config: &[u8]) {
+    match &track.sample_entry {
+        b"avc1" => w.write_box(b"avcC", |w| w.bytes(config)),
+        b"hvc1" => w.write_box(b"hvcC", |w| w.bytes(config)),
+        b"vp09" => w.write_full_box(b"vpcC", 1, 0, |w| w.bytes(config)),
+        b"av01" => w.write_box(b"av1C", |w| w.bytes(config)),
+        b"mp4a" => write_esds(w, track.id, config),
+        b"Opus" => w.write_box(b"dOps", |w| w.bytes(config)),
+        _ => {}
+    }
+}
+
+/// Write an MPEG-4 descriptor header using the 4-byte size encoding
+fn write_descriptor(w: &mut BoxWriter, tag: u8, body: impl FnOnce(&mut BoxWriter)) {
+    let mut inner = BoxWriter::new();
+    body(&mut inner);
+    let inner = inner.into_inner();
+    let len = inner.len() as u32;
+    w.u8(tag);
+    w.u8(0x80 | ((len >> 21) & 0x7F) as u8);
+    w.u8(0x80 | ((len >> 14) & 0x7F) as u8);
+    w.u8(0x80 | ((len >> 7) & 0x7F) as u8);
+    w.u8((len & 0x7F) as u8);
+    w.bytes(&inner);
+}
+
+fn write_esds(w: &mut BoxWriter, track_id: u32, audio_specific_config: &[u8]) {
+    w.write_full_box(b"esds", 0, 0, |w| {
+        write_descriptor(w, 0x03, |w| {
+            w.u16(track_id as u16); // ES_ID
+            w.u8(0);
+            write_descriptor(w, 0x04, |w| {
+                w.u8(0x40); // objectTypeIndication: MPEG-4 Audio
+                w.u8(0x15); // streamType: audio, upStream 0, reserved 1
+                w.u24(0); // bufferSizeDB
+                w.u32(0); // maxBitrate
+                w.u32(0); // avgBitrate
+                write_descriptor(w, 0x05, |w| w.bytes(audio_specific_config));
+            });
+            write_descriptor(w, 0x06, |w| w.u8(0x02));
+        });
+    });
+}
+
+/// Collapse consecutive equal values into (count, value) runs
+fn run_lengths<T: Copy + PartialEq>(values: &[T]) -> Vec<(u32, T)> {
+    let mut runs: Vec<(u32, T)> = Vec::new();
+    for &value in values {
+        match runs.last_mut() {
+            Some((count, last)) if *last == value => *count += 1,
+            _ => runs.push((1, value)),
+        }
+    }
+    runs
+}
+
+fn write_stts(w: &mut BoxWriter, durations: &[u32]) {
+    let runs = run_lengths(durations);
+    w.write_full_box(b"stts", 0, 0, |w| {
+        w.u32(runs.len() as u32);
+        for (count, delta) in runs {
+            w.u32(count);
+            w.u32(delta);
+        }
+    });
+}
+
+/// Write the composition offsets, as signed values in a version 1 box
+/// when any is negative; version 0 offsets are unsigned
+fn write_ctts(w: &mut BoxWriter, offsets: &[i32]) {
+    let runs = run_lengths(offsets);
+    let version = offsets.iter().any(|&offset| offset < 0) as u8;
+    w.write_full_box(b"ctts", version, 0, |w| {
+        w.u32(runs.len() as u32);
+        for (count, offset) in runs {
+            w.u32(count);
+            w.u32(offset as u32);
+        }
+    });
+}
+
+/// Write the composition to decode relation that goes with negative
+/// composition offsets: the shift that would make them non-negative, the
+/// offset range and the span of composition times. Version 1 widens the
+/// fields when a value does not fit 32 bits.
+fn write_cslg(w: &mut BoxWriter, durations: &[u32], offsets: &[i32]) {
+    let least = offsets.iter().copied().min().unwrap_or(0) as i64;
+    let greatest = offsets.iter().copied().max().unwrap_or(0) as i64;
+    let mut decode_time = 0i64;
+    let mut start = i64::MAX;
+    let mut end = i64::MIN;
+    for (&duration, &offset) in durations.iter().zip(offsets) {
+        let composition_time = decode_time + offset as i64;
+        start = start.min(composition_time);
+        end = end.max(composition_time + duration as i64);
+        decode_time += duration as i64;
+    }
+    let fields = [(-least).max(0), least, greatest, start, end];
+    let wide = fields.iter().any(|&v| i32::try_from(v).is_err());
+    w.write_full_box(b"cslg", wide as u8, 0, |w| {
+        for value in fields {
+            if wide {
+                w.u64(value as u64);
+            } else {
+                w.u32(value as i32 as u32);
+            }
+        }
+    });
+}
+
+fn write_stss(w: &mut BoxWriter, sync_samples: &[u32]) {
+    w.write_full_box(b"stss", 0, 0, |w| {
+        w.u32(sync_samples.len() as u32);
+        for &sample in sync_samples {
+            w.u32(sample);
+        }
+    });
+}
+
+fn write_stsc(w: &mut BoxWriter, samples_per_chunk: &[u32]) {
+    let runs = run_lengths(samples_per_chunk);
+    w.write_full_box(b"stsc", 0, 0, |w| {
+        w.u32(runs.len() as u32);
+        let mut first_chunk = 1;
+        for (count, samples) in runs {
+            w.u32(first_chunk);
+            w.u32(samples);
+            w.u32(1); // sample_description_index
+            first_chunk += count;
+        }
+    });
+}
+
+fn write_stsz(w: &mut BoxWriter, sizes: &[u32]) {
+    w.write_full_box(b"stsz", 0, 0, |w| {
+        w.u32(0); // sample_size: sizes follow
+        w.u32(sizes.len() as u32);
+        for &size in sizes {
+            w.u32(size);
+        }
+    });
+}
+
+/// Chunk offset box: stco, or co64 when an offset does not fit 32 bits,
+/// which only happens in files past 4 GiB
+fn write_stco(w: &mut BoxWriter, track: &TrackInfo) {
+    let large = track
+        .chunk_offsets
+        .iter()
+        .any(|&offset| offset > u32::MAX as u64);
+    let fourcc = if large { b"co64" } else { b"stco" };
+    w.write_full_box(fourcc, 0, 0, |w| {
+        w.u32(track.chunk_offsets.len() as u32);
+        for &offset in &track.chunk_offsets {
+            if large {
+                w.u64(offset);
+            } else {
+                w.u32(offset as u32);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_composition_offsets_use_ctts_version_1() {
+        let mut w = BoxWriter::new();
+        write_ctts(&mut w, &[0, 2, -1, -1]);
+        let ctts = w.into_inner();
+        assert_eq!(&ctts[4..8], b"ctts");
+        assert_eq!(ctts[8], 1);
+        // Runs of (count, offset): (1, 0), (1, 2), (2, -1)
+        assert_eq!(&ctts[12..16], &3u32.to_be_bytes());
+        assert_eq!(&ctts[32..40], &[0, 0, 0, 2, 0xFF, 0xFF, 0xFF, 0xFF]);
+
+        let mut w = BoxWriter::new();
+        write_ctts(&mut w, &[0, 1]);
+        assert_eq!(w.into_inner()[8], 0);
+
+        let mut w = BoxWriter::new();
+        write_cslg(&mut w, &[1, 1, 1, 1], &[0, 2, -1, -1]);
+        let cslg = w.into_inner();
+        assert_eq!(&cslg[4..8], b"cslg");
+        let fields: Vec<i32> = cslg[12..]
+            .chunks_exact(4)
+            .map(|b| i32::from_be_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(fields, [1, -1, 2, 0, 4]);
+    }
+
+    #[test]
+    fn ftyp_brands_follow_the_video_codec() {
+        let brands = |sample_entries: &[[u8; 4]], fragmented| {
+            let mut w = BoxWriter::new();
+            write_ftyp(&mut w, sample_entries, fragmented);
+            let ftyp = w.into_inner();
+            ftyp[16..]
+                .chunks_exact(4)
+                .map(|brand| String::from_utf8(brand.to_vec()).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            brands(&[*b"avc1", *b"mp4a"], false),
+            ["isom", "iso2", "iso4", "avc1", "mp41"]
+        );
+        assert_eq!(
+            brands(&[*b"av01", *b"Opus"], true),
+            ["isom", "iso2", "iso4", "av01", "mp41", "iso5", "iso6"]
+        );
+        assert_eq!(brands(&[*b"Opus"], false), ["isom", "iso2", "iso4", "mp41"]);
+    }
+
+    #[test]
+    fn headers_past_32_bits_switch_to_version_1() {
+        let kind = TrackKind::Video {
+            width: 64,
+            height: 64,
+        };
+        let mut track = TrackInfo::new(1, kind, *b"avc1", 90000, None);
+        track.sample_durations = vec![u32::MAX, 1];
+        let mut w = BoxWriter::new();
+        write_mdhd(&mut w, &track, 5);
+        let mdhd = w.into_inner();
+        assert_eq!(mdhd[8], 1);
+        assert_eq!(
+            &mdhd[12..28],
+            &[0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 5]
+        );
+        assert_eq!(&mdhd[28..32], &90000u32.to_be_bytes());
+        assert_eq!(&mdhd[32..40], &(u32::MAX as u64 + 1).to_be_bytes());
+
+        let mut w = BoxWriter::new();
+        write_tkhd(&mut w, &track, 90000, 5);
+        let tkhd = w.into_inner();
+        assert_eq!(tkhd[8], 1);
+        assert_eq!(&tkhd[36..44], &(u32::MAX as u64 + 1).to_be_bytes());
+
+        let time = u32::MAX as u64 + 1;
+        let mut w = BoxWriter::new();
+        write_mvhd(&mut w, 1000, 10, 2, time);
+        let mvhd = w.into_inner();
+        assert_eq!(mvhd[8], 1);
+        assert_eq!(&mvhd[12..20], &time.to_be_bytes());
+        assert_eq!(&mvhd[32..40], &10u64.to_be_bytes());
+
+        track.sample_durations = vec![3600];
+        let mut w = BoxWriter::new();
+        write_mdhd(&mut w, &track, 5);
+        let mdhd = w.into_inner();
+        assert_eq!(mdhd[8], 0);
+        assert_eq!(&mdhd[24..28], &3600u32.to_be_bytes());
+    }
+
+    #[test]
+    fn chunk_offsets_past_32_bits_switch_to_co64() {
+        let kind = TrackKind::Audio {
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let mut track = TrackInfo::new(1, kind, *b"mp4a", 48000, None);
+        track.chunk_offsets = vec![8, u32::MAX as u64];
+        let mut w = BoxWriter::new();
+        write_stco(&mut w, &track);
+        let stco = w.into_inner();
+        assert_eq!(&stco[4..8], b"stco");
+        assert_eq!(stco.len(), 16 + 2 * 4);
+        assert_eq!(&stco[20..24], &u32::MAX.to_be_bytes());
+
+        track.chunk_offsets = vec![8, u32::MAX as u64 + 1];
+        let mut w = BoxWriter::new();
+        write_stco(&mut w, &track);
+        let co64 = w.into_inner();
+        assert_eq!(&co64[4..8], b"co64");
+        assert_eq!(co64.len(), 16 + 2 * 8);
+        assert_eq!(&co64[24..32], &(u32::MAX as u64 + 1).to_be_bytes());
+    }
+
+    #[test]
+    fn mdat_past_32_bits_uses_a_largesize() {
+        let mut w = BoxWriter::new();
+        write_mdat_header(&mut w, u32::MAX as u64 - 8);
+        assert_eq!(
+            w.into_inner(),
+            [&u32::MAX.to_be_bytes()[..], b"mdat"].concat()
+        );
+
+        let mut w = BoxWriter::new();
+        write_mdat_header(&mut w, u32::MAX as u64 - 7);
+        let header = w.into_inner();
+        assert_eq!(&header[..8], [0, 0, 0, 1, b'm', b'd', b'a', b't']);
+        assert_eq!(&header[8..], &(u32::MAX as u64 + 9).to_be_bytes());
+    }
+}
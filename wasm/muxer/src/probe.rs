@@ -0,0 +1,398 @@
+//! Reading track configurations back out of an MP4 initialization segment
+
+use crate::error::MuxerError;
+
+/// First video and first audio track found in a moov box
+#[derive(Debug, Default)]
+pub(crate) struct InitInfo {
+    pub video: Option<ParsedVideo>,
+    pub audio: Option<ParsedAudio>,
+}
+
+#[derive(Debug)]
+pub(crate) struct ParsedVideo {
+    /// Codec string in WebCodecs form, e.g. "avc1.64001f"
+    pub codec: String,
+    pub width: u32,
+    pub height: u32,
+    /// avcC/hvcC/vpcC/av1C payload
+    pub description: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct ParsedAudio {
+    pub codec: String,
+    pub sample_rate: u32,
+    pub channels: u32,
+    /// AudioSpecificConfig for AAC, OpusHead for Opus
+    pub description: Option<Vec<u8>>,
+}
+
+fn invalid(reason: &'static str) -> MuxerError {
+    MuxerError::InvalidInitSegment(reason)
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+/// Iterate over the (type, payload) of the boxes laid end to end in `data`,
+/// stopping at the first truncated or malformed box
+pub(crate) fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let size = u32_at(data, pos)? as u64;
+        let kind: [u8; 4] = data.get(pos + 4..pos + 8)?.try_into().ok()?;
+        let (header, size) = match size {
+            0 => (8, (data.len() - pos) as u64),
+            1 => (
+                16,
+                (u32_at(data, pos + 8)? as u64) << 32 | u32_at(data, pos + 12)? as u64,
+            ),
+            size => (8, size),
+        };
+        if size < header as u64 {
+            return None;
+        }
+        // A crafted size must neither wrap nor truncate on 32-bit targets
+        let end = usize::try_from((pos as u64).checked_add(size)?).ok()?;
+        let body = data.get(pos + header..end)?;
+        pos = end;
+        Some((kind, body))
+    })
+}
+
+/// Payload of the first child box of type `kind`
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data).find(|(k, _)| k == kind).map(|(_, body)| body)
+}
+
+/// Follow a path of nested box types from `data`
+fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    path.iter().try_fold(data, |data, kind| child(data, kind))
+}
+
+/// Parse an ftyp + moov prefix (as written by init_segment or at the start
+/// of a faststart file) into the configuration of its first video and
+/// first audio track
+pub(crate) fn parse_init_segment(data: &[u8]) -> Result<InitInfo, MuxerError> {
+    let mut top = boxes(data);
+    match top.next() {
+        Some((kind, _)) if &kind == b"ftyp" => {}
+        _ => return Err(invalid("does not start with an ftyp box")),
+    }
+    let moov = top
+        .find(|(kind, _)| kind == b"moov")
+        .map(|(_, body)| body)
+        .ok_or_else(|| invalid("missing moov box"))?;
+    let mut info = InitInfo::default();
+    for (_, trak) in boxes(moov).filter(|(kind, _)| kind == b"trak") {
+        let handler = find(trak, &[b"mdia", b"hdlr"])
+            .and_then(|hdlr| hdlr.get(8..12))
+            .ok_or_else(|| invalid("track without a handler"))?;
+        let entry = find(trak, &[b"mdia", b"minf", b"stbl", b"stsd"])
+            .and_then(|stsd| boxes(stsd.get(8..)?).next())
+            .ok_or_else(|| invalid("track without a sample description"))?;
+        match handler {
+            b"vide" if info.video.is_none() => {
+                info.video = Some(video_config(trak, entry)?);
+            }
+            b"soun" if info.audio.is_none() => {
+                let timescale = find(trak, &[b"mdia", b"mdhd"]).and_then(mdhd_timescale);
+                info.audio = Some(audio_config(entry, timescale)?);
+            }
+            _ => {}
+        }
+    }
+    if info.video.is_none() && info.audio.is_none() {
+        return Err(invalid("no video or audio track"));
+    }
+    Ok(info)
+}
+
+fn mdhd_timescale(mdhd: &[u8]) -> Option<u32> {
+    match mdhd.first()? {
+        1 => u32_at(mdhd, 20),
+        _ => u32_at(mdhd, 12),
+    }
+}
+
+fn video_config(trak: &[u8], (fourcc, entry): ([u8; 4], &[u8])) -> Result<ParsedVideo, MuxerError> {
+    // SampleEntry (8 bytes) then VisualSampleEntry fields up to depth and
+    // pre_defined, 78 bytes in all before the child boxes
+    let children = entry
+        .get(78..)
+        .ok_or_else(|| invalid("video sample entry is too short"))?;
+    let (mut width, mut height) = (
+        u16_at(entry, 24).unwrap_or(0) as u32,
+        u16_at(entry, 26).unwrap_or(0) as u32,
+    );
+    // tkhd carries the display size in 16.16 fixed point as its last fields
+    if let Some(tkhd) = child(trak, b"tkhd") {
+        let len = tkhd.len();
+        if let (Some(w), Some(h)) = (
+            u32_at(tkhd, len.wrapping_sub(8)),
+            u32_at(tkhd, len.wrapping_sub(4)),
+        ) {
+            if w >> 16 != 0 && h >> 16 != 0 {
+                width = w >> 16;
+                height = h >> 16;
+            }
+        }
+    }
+    let (codec, description) = match &fourcc {
+        b"avc1" | b"avc3" => {
+            let avcc = child(children, b"avcC");
+            let codec = match avcc.and_then(|avcc| avcc.get(1..4)) {
+                Some(ptl) => format!("avc1.{:02x}{:02x}{:02x}", ptl[0], ptl[1], ptl[2]),
+                None => "avc1".to_string(),
+            };
+            (codec, avcc)
+        }
+        b"hvc1" | b"hev1" => {
+            let hvcc = child(children, b"hvcC");
+            let codec = hvcc
+                .and_then(hevc_codec_string)
+                .unwrap_or_else(|| "hvc1".to_string());
+            (codec, hvcc)
+        }
+        b"vp09" => {
+            // vpcC is a full box: skip version and flags
+            let vpcc = child(children, b"vpcC").and_then(|vpcc| vpcc.get(4..));
+            let codec = match vpcc.filter(|vpcc| vpcc.len() >= 3) {
+                Some(vpcc) => format!("vp09.{:02}.{:02}.{:02}", vpcc[0], vpcc[1], vpcc[2] >> 4),
+                None => "vp09".to_string(),
+            };
+            (codec, vpcc)
+        }
+        b"av01" => {
+            let av1c = child(children, b"av1C");
+            let codec = av1c
+                .and_then(av1_codec_string)
+                .unwrap_or_else(|| "av01".to_string());
+            (codec, av1c)
+        }
+        _ => return Err(invalid("unsupported video sample entry")),
+    };
+    Ok(ParsedVideo {
+        codec,
+        width,
+        height,
+        description: description.map(<[u8]>::to_vec),
+    })
+}
+
+/// RFC 6381 codec string of an HEVCDecoderConfigurationRecord, e.g.
+/// "hvc1.1.6.L93.B0"
+fn hevc_codec_string(hvcc: &[u8]) -> Option<String> {
+    let header = hvcc.get(..13)?;
+    let space = ["", "A", "B", "C"][(header[1] >> 6) as usize];
+    let tier = if header[1] & 0x20 != 0 { 'H' } else { 'L' };
+    let profile = header[1] & 0x1F;
+    // The compatibility flags are written in reverse bit order
+    let compatibility = u32_at(header, 2)?.reverse_bits();
+    let mut codec = format!(
+        "hvc1.{space}{profile}.{compatibility:X}.{tier}{}",
+        header[12]
+    );
+    // Constraint bytes, with trailing zero bytes omitted
+    let constraints = &header[6..12];
+    let used = constraints
+        .iter()
+        .rposition(|&byte| byte != 0)
+        .map_or(0, |last| last + 1);
+    for byte in &constraints[..used] {
+        codec.push_str(&format!(".{byte:X}"));
+    }
+    Some(codec)
+}
+
+/// Codec string of an AV1CodecConfigurationRecord, e.g. "av01.0.08M.08"
+fn av1_codec_string(av1c: &[u8]) -> Option<String> {
+    let header = av1c.get(..3)?;
+    let profile = header[1] >> 5;
+    let level = header[1] & 0x1F;
+    let tier = if header[2] & 0x80 != 0 { 'H' } else { 'M' };
+    let bit_depth = match (header[2] & 0x40 != 0, header[2] & 0x20 != 0) {
+        (true, true) => 12,
+        (true, false) => 10,
+        _ => 8,
+    };
+    Some(format!("av01.{profile}.{level:02}{tier}.{bit_depth:02}"))
+}
+
+fn audio_config(
+    (fourcc, entry): ([u8; 4], &[u8]),
+    timescale: Option<u32>,
+) -> Result<ParsedAudio, MuxerError> {
+    // SampleEntry (8 bytes) then AudioSampleEntry fields, 28 bytes in all
+    let children = entry
+        .get(28..)
+        .ok_or_else(|| invalid("audio sample entry is too short"))?;
+    let channels = u16_at(entry, 16).unwrap_or(0) as u32;
+    // 16.16 fixed point, so rates above 65535 Hz are only in mdhd
+    let sample_rate = match u32_at(entry, 24).unwrap_or(0) >> 16 {
+        0 => timescale.unwrap_or(0),
+        rate => rate,
+    };
+    match &fourcc {
+        b"mp4a" => {
+            let config = child(children, b"esds")
+                .and_then(|esds| audio_specific_config(esds.get(4..)?))
+                .ok_or_else(|| invalid("mp4a sample entry without an AudioSpecificConfig"))?;
+            let object_type = config.first().map_or(2, |byte| byte >> 3);
+            Ok(ParsedAudio {
+                codec: format!("mp4a.40.{object_type}"),
+                sample_rate,
+                channels,
+                description: Some(config.to_vec()),
+            })
+        }
+        b"Opus" => {
+            let description = child(children, b"dOps").and_then(opus_head_from_dops);
+            Ok(ParsedAudio {
+                codec: "opus".to_string(),
+                sample_rate,
+                channels,
+                description,
+            })
+        }
+        _ => Err(invalid("unsupported audio sample entry")),
+    }
+}
+
+/// Read an MPEG-4 descriptor header, returning its tag and body
+fn descriptor(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *data.first()?;
+    let mut len = 0usize;
+    let mut pos = 1;
+    loop {
+        let byte = *data.get(pos)?;
+        pos += 1;
+        len = (len << 7) | (byte & 0x7F) as usize;
+        if byte & 0x80 == 0 || pos == 5 {
+            break;
+        }
+    }
+    let body = data.get(pos..pos + len)?;
+    Some((tag, body, &data[pos + len..]))
+}
+
+/// The DecoderSpecificInfo of an esds payload (after version and flags)
+fn audio_specific_config(esds: &[u8]) -> Option<&[u8]> {
+    let (tag, es, _) = descriptor(esds)?;
+    if tag != 0x03 {
+        return None;
+    }
+    // ES_ID, then flags selecting the optional fields that follow
+    let flags = *es.get(2)?;
+    let mut pos = 3;
+    if flags & 0x80 != 0 {
+        pos += 2; // dependsOn_ES_ID
+    }
+    if flags & 0x40 != 0 {
+        pos += 1 + *es.get(pos)? as usize; // URL
+    }
+    if flags & 0x20 != 0 {
+        pos += 2; // OCR_ES_Id
+    }
+    let (tag, decoder_config, _) = descriptor(es.get(pos..)?)?;
+    if tag != 0x04 {
+        return None;
+    }
+    // objectTypeIndication, streamType, bufferSizeDB and bitrates
+    let (tag, info, _) = descriptor(decoder_config.get(13..)?)?;
+    (tag == 0x05).then_some(info)
+}
+
+/// Convert a dOps box payload (big-endian) back into the OpusHead header
+/// WebCodecs expects as the description
+fn opus_head_from_dops(dops: &[u8]) -> Option<Vec<u8>> {
+    let header = dops.get(..11)?;
+    let channels = header[1];
+    let family = header[10];
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(channels);
+    head.extend_from_slice(&u16::from_be_bytes([header[2], header[3]]).to_le_bytes());
+    head.extend_from_slice(
+        &u32::from_be_bytes([header[4], header[5], header[6], header[7]]).to_le_bytes(),
+    );
+    head.extend_from_slice(&i16::from_be_bytes([header[8], header[9]]).to_le_bytes());
+    head.push(family);
+    if family != 0 {
+        head.extend_from_slice(dops.get(11..13 + channels as usize)?);
+    }
+    Some(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Muxer;
+
+    fn kinds(data: &[u8]) -> Vec<[u8; 4]> {
+        boxes(data).map(|(kind, _)| kind).collect()
+    }
+
+    #[test]
+    fn malformed_boxes_end_the_walk() {
+        let free = [0, 0, 0, 10, b'f', b'r', b'e', b'e', 1, 2];
+        assert_eq!(kinds(&free), [*b"free"]);
+        assert_eq!(boxes(&free).next().unwrap().1, [1, 2]);
+
+        // Truncated: the header claims more than is left
+        let mut truncated = free.to_vec();
+        truncated.extend_from_slice(&[0, 0, 0, 16, b'm', b'o', b'o', b'v', 0]);
+        assert_eq!(kinds(&truncated), [*b"free"]);
+        assert!(kinds(&truncated[..6]).is_empty());
+
+        // A size smaller than the header
+        assert!(kinds(&[0, 0, 0, 7, b'f', b'r', b'e', b'e']).is_empty());
+        let mut short = vec![0, 0, 0, 1, b'f', b'r', b'e', b'e'];
+        short.extend_from_slice(&15u64.to_be_bytes());
+        assert!(kinds(&short).is_empty());
+
+        // A largesize that overflows the position
+        let mut huge = free.to_vec();
+        huge.extend_from_slice(&[0, 0, 0, 1, b'm', b'd', b'a', b't']);
+        huge.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert_eq!(kinds(&huge), [*b"free"]);
+        let mut past_end = vec![0, 0, 0, 1, b'm', b'd', b'a', b't'];
+        past_end.extend_from_slice(&(u64::MAX - 8).to_be_bytes());
+        assert!(kinds(&past_end).is_empty());
+
+        // Size 0 runs to the end of the data
+        let mut to_end = free.to_vec();
+        to_end.extend_from_slice(&[0, 0, 0, 0, b'm', b'd', b'a', b't', 9]);
+        assert_eq!(kinds(&to_end), [*b"free", *b"mdat"]);
+        assert!(matches!(
+            parse_init_segment(&huge),
+            Err(MuxerError::InvalidInitSegment(_))
+        ));
+    }
+
+    #[test]
+    fn init_segment_round_trips() {
+        let avcc = vec![1, 0x42, 0, 0x1f, 0xff, 0xe0, 0];
+        let mut muxer = Muxer::new();
+        muxer.configure_video(640, 360, "avc1.42001f").unwrap();
+        muxer.configure_audio(48000, 2, "opus").unwrap();
+        muxer.first_video_track().codec_config = Some(avcc.clone());
+
+        let info = parse_init_segment(&muxer.write_init_segment().unwrap()).unwrap();
+        let video = info.video.unwrap();
+        assert_eq!(video.codec, "avc1.42001f");
+        assert_eq!((video.width, video.height), (640, 360));
+        assert_eq!(video.description, Some(avcc));
+        let audio = info.audio.unwrap();
+        assert_eq!(audio.codec, "opus");
+        assert_eq!((audio.sample_rate, audio.channels), (48000, 2));
+        let head = audio.description.unwrap();
+        assert_eq!(&head[..10], b"OpusHead\x01\x02");
+    }
+}
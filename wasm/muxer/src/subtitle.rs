@@ -0,0 +1,46 @@
+//! Subtitle cues, chapters and their encoding as timed text samples
+
+/// A caption shown from `start` until `end`, in microseconds
+#[derive(Clone)]
+pub(crate) struct Cue {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// A chapter starting at `time`, in microseconds
+#[derive(Clone)]
+pub(crate) struct Chapter {
+    pub time: f64,
+    pub title: String,
+}
+
+/// A tx3g sample: the UTF-8 text behind a 16-bit length, cut at a
+/// character boundary when it does not fit
+pub(crate) fn tx3g_sample(text: &str) -> Vec<u8> {
+    let mut len = text.len().min(u16::MAX as usize);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    let mut sample = Vec::with_capacity(2 + len);
+    sample.extend_from_slice(&(len as u16).to_be_bytes());
+    sample.extend_from_slice(&text.as_bytes()[..len]);
+    sample
+}
+
+/// Samples of a tx3g track as (timestamp, payload) pairs for `cues` sorted
+/// by start. A timed text track has no gaps, so an empty sample clears the
+/// screen wherever one cue ends before the next starts; a cue that
+/// overlaps the next one is cut short by it.
+pub(crate) fn tx3g_samples(cues: &[Cue]) -> Vec<(f64, Vec<u8>)> {
+    let mut samples = Vec::with_capacity(cues.len() * 2);
+    let mut previous_end: Option<f64> = None;
+    for cue in cues {
+        if let Some(end) = previous_end.filter(|&end| end < cue.start) {
+            samples.push((end, tx3g_sample("")));
+        }
+        samples.push((cue.start, tx3g_sample(&cue.text)));
+        previous_end = Some(cue.end);
+    }
+    samples
+}
@@ -0,0 +1,106 @@
+//! Conversion of chunk timestamps into sample table timing
+
+use std::ops::Range;
+
+/// Per-sample timing in track timescale units
+pub(crate) struct SampleTiming {
+    /// Decode time of the first sample, which is the earliest
+    /// presentation time
+    pub base_decode_time: i64,
+    /// Decode duration of each sample (stts)
+    pub durations: Vec<u32>,
+    /// Presentation minus decode time of each sample (ctts), negative for
+    /// samples decoded after ones they are shown before (B-frames)
+    pub composition_offsets: Vec<i32>,
+}
+
+/// Convert a timestamp in microseconds to timescale ticks.
+///
+/// Every timestamp is rounded independently from its absolute value, so
+/// durations computed as differences never accumulate rounding drift.
+pub(crate) fn to_ticks(timestamp_us: f64, timescale: u32) -> i64 {
+    (timestamp_us * timescale as f64 / 1_000_000.0).round() as i64
+}
+
+/// Derive sample durations and composition offsets from presentation
+/// timestamps given in decode order.
+///
+/// Decode timestamps are the presentation timestamps sorted ascending, which
+/// handles variable frame rates and B-frame reordering alike. Reordering
+/// leaves the frames that are shown early with a negative offset instead of
+/// shifting every decode time back, so the first sample is presented at its
+/// decode time and no edit is needed to hide a reorder delay. The last
+/// sample repeats the previous duration, or `default_duration` if it is the
+/// only sample.
+pub(crate) fn sample_timing(
+    timestamps_us: &[f64],
+    timescale: u32,
+    default_duration: u32,
+) -> SampleTiming {
+    let pts: Vec<i64> = timestamps_us
+        .iter()
+        .map(|&t| to_ticks(t, timescale))
+        .collect();
+    let mut dts = pts.clone();
+    dts.sort_unstable();
+
+    let mut durations: Vec<u32> = dts
+        .windows(2)
+        .map(|pair| (pair[1] - pair[0]).clamp(0, u32::MAX as i64) as u32)
+        .collect();
+    if !dts.is_empty() {
+        durations.push(durations.last().copied().unwrap_or(default_duration));
+    }
+
+    let composition_offsets = pts
+        .iter()
+        .zip(&dts)
+        .map(|(p, d)| (p - d).clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+        .collect();
+
+    SampleTiming {
+        base_decode_time: dts.first().copied().unwrap_or(0),
+        durations,
+        composition_offsets,
+    }
+}
+
+/// Lay out the samples of several tracks for interleaved storage.
+///
+/// Each track's samples (timestamps in microseconds, in decode order) are
+/// split into runs spanning less than `max_span_us` of decode time, and the
+/// runs of all tracks are ordered by the decode time of their first
+/// sample, earlier tracks first on ties. Returns (track index, sample
+/// range) pairs in storage order.
+pub(crate) fn interleave(tracks: &[&[f64]], max_span_us: f64) -> Vec<(usize, Range<usize>)> {
+    let mut runs: Vec<(f64, usize, Range<usize>)> = Vec::new();
+    for (track, timestamps) in tracks.iter().enumerate() {
+        let mut dts = timestamps.to_vec();
+        dts.sort_by(f64::total_cmp);
+        let mut start = 0;
+        for i in 1..=dts.len() {
+            if i == dts.len() || dts[i] - dts[start] >= max_span_us {
+                runs.push((dts[start], track, start..i));
+                start = i;
+            }
+        }
+    }
+    runs.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    runs.into_iter()
+        .map(|(_, track, samples)| (track, samples))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn b_frames_get_negative_composition_offsets() {
+        // I0 P3 B1 B2 in decode order, one tick per microsecond
+        let timing = sample_timing(&[0.0, 3.0, 1.0, 2.0], 1_000_000, 1);
+        assert_eq!(timing.base_decode_time, 0);
+        assert_eq!(timing.durations, [1, 1, 1, 1]);
+        assert_eq!(timing.composition_offsets, [0, 2, -1, -1]);
+    }
+}
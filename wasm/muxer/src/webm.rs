@@ -0,0 +1,320 @@
+//! WebM (Matroska) element writing
+
+use crate::metadata::{Metadata, Tag, APP_NAME};
+use crate::TrackKind;
+
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const DURATION: u32 = 0x4489;
+const DATE_UTC: u32 = 0x4461;
+const TITLE: u32 = 0x7BA9;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const BLOCK_DURATION: u32 = 0x9B;
+const TAGS: u32 = 0x1254_C367;
+const TAG: u32 = 0x7373;
+const TARGETS: u32 = 0x63C0;
+const SIMPLE_TAG: u32 = 0x67C8;
+const TAG_NAME: u32 = 0x45A3;
+const TAG_STRING: u32 = 0x4487;
+const CHAPTERS: u32 = 0x1043_A770;
+const EDITION_ENTRY: u32 = 0x45B9;
+const CHAPTER_ATOM: u32 = 0xB6;
+const CHAPTER_UID: u32 = 0x73C4;
+const CHAPTER_TIME_START: u32 = 0x91;
+const CHAPTER_TIME_END: u32 = 0x92;
+const CHAPTER_DISPLAY: u32 = 0x80;
+const CHAP_STRING: u32 = 0x85;
+const CHAP_LANGUAGE: u32 = 0x437C;
+
+/// Block timestamps are in milliseconds (TimestampScale of 1ms)
+const TIMESTAMP_SCALE_NS: u64 = 1_000_000;
+/// Cluster length for audio-only files, which have no keyframes to split on
+const AUDIO_CLUSTER_MS: i64 = 5_000;
+
+/// Byte buffer with helpers for EBML elements
+struct EbmlWriter {
+    buf: Vec<u8>,
+}
+
+impl EbmlWriter {
+    fn id(&mut self, id: u32) {
+        let bytes = id.to_be_bytes();
+        let skip = bytes.iter().take_while(|&&b| b == 0).count();
+        self.buf.extend_from_slice(&bytes[skip..]);
+    }
+
+    /// Write an element size as a variable-length integer of minimal width
+    fn size(&mut self, size: u64) {
+        let mut width = 1;
+        // All-ones values are reserved for "unknown size"
+        while width < 8 && size >= (1u64 << (7 * width)) - 1 {
+            width += 1;
+        }
+        let marked = size | (1u64 << (7 * width));
+        self.buf
+            .extend_from_slice(&marked.to_be_bytes()[8 - width as usize..]);
+    }
+
+    fn uint(&mut self, id: u32, value: u64) {
+        let bytes = value.to_be_bytes();
+        let skip = bytes.iter().take_while(|&&b| b == 0).count().min(7);
+        self.id(id);
+        self.size((8 - skip) as u64);
+        self.buf.extend_from_slice(&bytes[skip..]);
+    }
+
+    fn float(&mut self, id: u32, value: f64) {
+        self.id(id);
+        self.size(8);
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn bytes(&mut self, id: u32, data: &[u8]) {
+        self.id(id);
+        self.size(data.len() as u64);
+        self.buf.extend_from_slice(data);
+    }
+
+    fn string(&mut self, id: u32, value: &str) {
+        self.bytes(id, value.as_bytes());
+    }
+
+    /// Write a master element, patching an 8-byte size once the body is
+    /// written
+    fn master(&mut self, id: u32, body: impl FnOnce(&mut Self)) {
+        self.id(id);
+        let start = self.buf.len();
+        self.buf.extend_from_slice(&[0; 8]);
+        body(self);
+        let size = (self.buf.len() - start - 8) as u64 | (1u64 << 56);
+        self.buf[start..start + 8].copy_from_slice(&size.to_be_bytes());
+    }
+}
+
+/// One track of a WebM file
+pub(crate) struct WebmTrack {
+    pub number: u64,
+    pub kind: TrackKind,
+    pub codec_id: &'static str,
+    pub codec_private: Option<Vec<u8>>,
+}
+
+/// A chapter of a WebM file, in milliseconds from the segment start
+pub(crate) struct WebmChapter<'a> {
+    pub start_ms: f64,
+    pub end_ms: f64,
+    pub title: &'a str,
+}
+
+/// One encoded frame, stored as a SimpleBlock, or a subtitle cue, stored
+/// as a BlockGroup with its duration
+pub(crate) struct Block<'a> {
+    pub track: u64,
+    pub timestamp_ms: i64,
+    pub is_key: bool,
+    pub data: &'a [u8],
+    pub duration_ms: Option<u64>,
+}
+
+/// Write a complete WebM file.
+///
+/// The creation time goes into DateUTC, the title also into the segment
+/// Title, and every tag into a Tags element before the clusters, followed
+/// by the chapters. `blocks` must be sorted by timestamp. A new cluster starts at every video
+/// keyframe (every few seconds for audio-only files) and whenever a block
+/// timestamp would overflow the 16-bit cluster-relative offset.
+pub(crate) fn write_webm(
+    tracks: &[WebmTrack],
+    blocks: &[Block],
+    duration_ms: f64,
+    metadata: &Metadata,
+    chapters: &[WebmChapter],
+) -> Vec<u8> {
+    let mut w = EbmlWriter { buf: Vec::new() };
+    w.master(EBML, |w| {
+        w.uint(EBML_VERSION, 1);
+        w.uint(EBML_READ_VERSION, 1);
+        w.uint(EBML_MAX_ID_LENGTH, 4);
+        w.uint(EBML_MAX_SIZE_LENGTH, 8);
+        w.string(DOC_TYPE, "webm");
+        w.uint(DOC_TYPE_VERSION, 4);
+        w.uint(DOC_TYPE_READ_VERSION, 2);
+    });
+
+    w.master(SEGMENT, |w| {
+        w.master(INFO, |w| {
+            w.uint(TIMESTAMP_SCALE, TIMESTAMP_SCALE_NS);
+            w.string(MUXING_APP, APP_NAME);
+            w.string(WRITING_APP, metadata.get(Tag::Encoder).unwrap_or(APP_NAME));
+            w.float(DURATION, duration_ms);
+            w.bytes(DATE_UTC, &metadata.webm_date().to_be_bytes());
+            if let Some(title) = metadata.get(Tag::Title) {
+                w.string(TITLE, title);
+            }
+        });
+        w.master(TRACKS, |w| {
+            for track in tracks {
+                write_track_entry(w, track);
+            }
+        });
+        if !metadata.tags.is_empty() {
+            write_tags(w, metadata);
+        }
+        if !chapters.is_empty() {
+            write_chapters(w, chapters);
+        }
+        write_clusters(w, tracks, blocks);
+    });
+    w.buf
+}
+
+/// Write the tags, default encoder included, as SimpleTags of one Tag
+/// targeting the whole segment
+fn write_tags(w: &mut EbmlWriter, metadata: &Metadata) {
+    w.master(TAGS, |w| {
+        w.master(TAG, |w| {
+            w.master(TARGETS, |_| {});
+            for (tag, value) in metadata.entries() {
+                w.master(SIMPLE_TAG, |w| {
+                    w.string(TAG_NAME, tag.webm_name());
+                    w.string(TAG_STRING, value);
+                });
+            }
+        });
+    });
+}
+
+/// Write one edition holding a ChapterAtom per chapter; times are in
+/// nanoseconds regardless of TimestampScale
+fn write_chapters(w: &mut EbmlWriter, chapters: &[WebmChapter]) {
+    let to_ns = |ms: f64| (ms * 1_000_000.0).round().max(0.0) as u64;
+    w.master(CHAPTERS, |w| {
+        w.master(EDITION_ENTRY, |w| {
+            for (index, chapter) in chapters.iter().enumerate() {
+                w.master(CHAPTER_ATOM, |w| {
+                    w.uint(CHAPTER_UID, index as u64 + 1);
+                    w.uint(CHAPTER_TIME_START, to_ns(chapter.start_ms));
+                    w.uint(CHAPTER_TIME_END, to_ns(chapter.end_ms));
+                    w.master(CHAPTER_DISPLAY, |w| {
+                        w.string(CHAP_STRING, chapter.title);
+                        w.string(CHAP_LANGUAGE, "und");
+                    });
+                });
+            }
+        });
+    });
+}
+
+fn write_track_entry(w: &mut EbmlWriter, track: &WebmTrack) {
+    w.master(TRACK_ENTRY, |w| {
+        w.uint(TRACK_NUMBER, track.number);
+        w.uint(TRACK_UID, track.number);
+        match track.kind {
+            TrackKind::Video { .. } => w.uint(TRACK_TYPE, 1),
+            TrackKind::Audio { .. } => w.uint(TRACK_TYPE, 2),
+            TrackKind::Text | TrackKind::Chapters => w.uint(TRACK_TYPE, 0x11),
+        }
+        w.string(CODEC_ID, track.codec_id);
+        if let Some(private) = &track.codec_private {
+            w.bytes(CODEC_PRIVATE, private);
+        }
+        match track.kind {
+            TrackKind::Video { width, height } => w.master(VIDEO, |w| {
+                w.uint(PIXEL_WIDTH, width as u64);
+                w.uint(PIXEL_HEIGHT, height as u64);
+            }),
+            TrackKind::Audio {
+                sample_rate,
+                channels,
+            } => w.master(AUDIO, |w| {
+                w.float(SAMPLING_FREQUENCY, sample_rate as f64);
+                w.uint(CHANNELS, channels as u64);
+            }),
+            TrackKind::Text | TrackKind::Chapters => {}
+        }
+    });
+}
+
+fn write_clusters(w: &mut EbmlWriter, tracks: &[WebmTrack], blocks: &[Block]) {
+    let has_video = tracks
+        .iter()
+        .any(|t| matches!(t.kind, TrackKind::Video { .. }));
+    let is_video = |block: &Block| {
+        tracks
+            .iter()
+            .any(|t| t.number == block.track && matches!(t.kind, TrackKind::Video { .. }))
+    };
+
+    let mut start = 0;
+    while start < blocks.len() {
+        let cluster_time = blocks[start].timestamp_ms;
+        let mut end = start + 1;
+        while end < blocks.len() {
+            let block = &blocks[end];
+            let relative = block.timestamp_ms - cluster_time;
+            let split = if has_video {
+                block.is_key && is_video(block)
+            } else {
+                relative >= AUDIO_CLUSTER_MS
+            };
+            if split || relative > i16::MAX as i64 {
+                break;
+            }
+            end += 1;
+        }
+
+        w.master(CLUSTER, |w| {
+            w.uint(TIMESTAMP, cluster_time as u64);
+            for block in &blocks[start..end] {
+                match block.duration_ms {
+                    Some(duration) => w.master(BLOCK_GROUP, |w| {
+                        write_block(w, BLOCK, block, cluster_time);
+                        w.uint(BLOCK_DURATION, duration);
+                    }),
+                    None => write_block(w, SIMPLE_BLOCK, block, cluster_time),
+                }
+            }
+        });
+        start = end;
+    }
+}
+
+/// Write a SimpleBlock or the Block of a BlockGroup, which share a layout
+fn write_block(w: &mut EbmlWriter, id: u32, block: &Block, cluster_time: i64) {
+    w.id(id);
+    // Track numbers are small, so they fit a 1-byte vint
+    w.size(block.data.len() as u64 + 4);
+    w.buf.push(0x80 | block.track as u8);
+    let relative = (block.timestamp_ms - cluster_time) as i16;
+    w.buf.extend_from_slice(&relative.to_be_bytes());
+    w.buf.push(if block.is_key { 0x80 } else { 0 });
+    w.buf.extend_from_slice(block.data);
+}